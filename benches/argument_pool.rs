@@ -0,0 +1,83 @@
+// Distributed under the OSI-approved BSD 3-Clause License.
+// See accompanying LICENSE file for details.
+
+//! Compare the allocations made when unpacking the arguments of a method call with and without a
+//! value pool.
+
+extern crate rust_bus;
+
+use rust_bus::{Arguments, Array, BasicValue, Message, Signature, Struct, Value, ValuePool};
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+
+/// The number of calls to unpack for each measurement.
+const ITERATIONS: usize = 100_000;
+
+/// An allocator which counts the allocations made through it.
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+fn method_call() -> Message {
+    let string = |s: &str| Value::BasicValue(BasicValue::String(s.to_string()));
+    let names = (0..8).map(|i| string(&format!("name{}", i))).collect();
+
+    Message::new_method_call("net.benboeckel.test.rustbus",
+                             "/net/benboeckel",
+                             "net.benboeckel.test.Interface",
+                             "Method")
+        .add_argument(&"argument")
+        .add_argument(&Value::Array(Array::new_with_sig(names, "as".to_string())))
+        .add_argument(&Value::Struct(Struct {
+            objects: vec![string("field"), Value::BasicValue(BasicValue::Uint32(42))],
+            signature: Signature("(su)".to_string()),
+        }))
+}
+
+fn measure(desc: &str, msg: &Message) {
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed);
+    let start = Instant::now();
+
+    for _ in 0..ITERATIONS {
+        let args = Arguments::new(msg).ok().unwrap();
+        assert!(args.extract(2).is_ok());
+    }
+
+    let elapsed = start.elapsed();
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed) - allocations;
+    let nanos = elapsed.as_secs() * 1_000_000_000 + elapsed.subsec_nanos() as u64;
+    println!("{}: {:.1} allocations/call, {} ns/call",
+             desc,
+             allocations as f64 / ITERATIONS as f64,
+             nanos / ITERATIONS as u64);
+}
+
+fn main() {
+    let msg = method_call();
+    measure("without pool", &msg);
+
+    let msg = msg.with_value_pool(Rc::new(ValuePool::new()));
+    measure("with pool", &msg);
+}
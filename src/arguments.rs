@@ -1,41 +1,534 @@
 // Distributed under the OSI-approved BSD 3-Clause License.
 // See accompanying LICENSE file for details.
 
+use error::{Error, ErrorKind};
 use interface::ErrorMessage;
 use message::Message;
-use value::{BasicValue, Value};
+use pool::ValuePool;
+use value::{BasicValue, FromValue, HasSignature, Path, Value, ValueExt};
 
+use std::collections::HashMap;
+use std::mem;
+use std::rc::Rc;
+use std::slice;
+
+fn _extract_string(value: &Value) -> Option<&String> {
+    if let Value::BasicValue(BasicValue::String(ref s)) = *value {
+        Some(s)
+    } else {
+        None
+    }
+}
+
+fn _extract_u32(value: &Value) -> Option<u32> {
+    if let Value::BasicValue(BasicValue::Uint32(n)) = *value {
+        Some(n)
+    } else {
+        None
+    }
+}
+
+fn _extract_dict(value: &Value) -> Option<HashMap<String, Value>> {
+    if let Value::Dictionary(ref dict) = *value {
+        dict.map
+            .iter()
+            .map(|(key, value)| {
+                let value = match *value {
+                    Value::Variant(ref v) => (*v.object).clone(),
+                    ref value => value.clone(),
+                };
+
+                match *key {
+                    BasicValue::String(ref key) => Some((key.clone(), value)),
+                    _ => None,
+                }
+            })
+            .collect()
+    } else {
+        None
+    }
+}
+
+/// The arguments of a message.
+///
+/// Extraction methods return `InvalidArgs` errors which may be returned directly from method
+/// handlers.
+///
+/// If the message has a value pool, the arguments are returned to it when dropped.
 pub struct Arguments {
     values: Vec<Value>,
+    pool: Option<Rc<ValuePool>>,
+}
+
+/// A trait for types which may be decoded from the body of a signal.
+pub trait FromSignalBody: Sized {
+    /// Decode the arguments of a signal.
+    fn from_signal_body(args: &Arguments) -> Result<Self, ErrorMessage>;
 }
 
 impl Arguments {
+    /// Unpack the arguments of a message.
     pub fn new(msg: &Message) -> Result<Arguments, ErrorMessage> {
+        let pool = msg.value_pool();
+        let values = match pool {
+            Some(ref pool) => msg.values_with_pool(pool),
+            None => msg.values(),
+        };
+
         Ok(Arguments {
-            values: msg.values().ok().and_then(|x| x).ok_or(Self::invalid_arguments())?,
+            values: values.ok().and_then(|x| x).ok_or(Self::invalid_arguments())?,
+            pool: pool,
         })
     }
 
+    /// Unpack the arguments of a message, checking that they have the expected signature.
+    ///
+    /// This allows a single error describing the mismatch to be returned rather than an error
+    /// for the first argument which fails to extract.
+    pub fn new_checked(msg: &Message, signature: &str) -> Result<Arguments, ErrorMessage> {
+        let args = Self::new(msg)?;
+        let actual = args.values
+            .iter()
+            .map(|v| v.get_signature())
+            .collect::<Vec<_>>()
+            .join("");
+
+        if actual != signature {
+            return Err(ErrorMessage::new("org.freedesktop.DBus.Error.InvalidArgs",
+                                         &format!("invalid arguments: expected signature '{}', \
+                                                   got '{}'",
+                                                  signature,
+                                                  actual)));
+        }
+
+        Ok(args)
+    }
+
+    /// The number of arguments in the message.
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    /// Whether the message has no arguments.
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    /// Iterate over the arguments in order.
+    pub fn iter(&self) -> slice::Iter<Value> {
+        self.values.iter()
+    }
+
+    /// Get the argument at the given index.
     pub fn extract(&self, index: usize) -> Result<&Value, ErrorMessage> {
         self.values.get(index).ok_or_else(|| Self::invalid_argument(index))
     }
 
+    /// Get the argument at the given index converted to a Rust type.
+    ///
+    /// The error describes the kind of value which was found instead.
+    pub fn get<T>(&self, index: usize) -> Result<T, ErrorMessage>
+        where T: FromValue + HasSignature,
+    {
+        self.extract(index)?
+            .extract()
+            .map_err(|err| Self::_invalid_argument_reason(index, &err.to_string()))
+    }
+
+    /// Get the string argument at the given index.
     pub fn extract_string(&self, index: usize) -> Result<&String, ErrorMessage> {
-        let value = self.extract(index)?;
-        if let Value::BasicValue(BasicValue::String(ref s)) = *value {
-            Ok(s)
-        } else {
-            Err(Self::invalid_argument(index))
+        _extract_string(self.extract(index)?).ok_or_else(|| Self::invalid_argument(index))
+    }
+
+    /// Get the unsigned 32-bit integer argument at the given index.
+    pub fn extract_u32(&self, index: usize) -> Result<u32, ErrorMessage> {
+        self.get(index)
+    }
+
+    /// Get the signed 32-bit integer argument at the given index.
+    pub fn extract_i32(&self, index: usize) -> Result<i32, ErrorMessage> {
+        self.get(index)
+    }
+
+    /// Get the unsigned 64-bit integer argument at the given index.
+    pub fn extract_u64(&self, index: usize) -> Result<u64, ErrorMessage> {
+        self.get(index)
+    }
+
+    /// Get the signed 64-bit integer argument at the given index.
+    pub fn extract_i64(&self, index: usize) -> Result<i64, ErrorMessage> {
+        self.get(index)
+    }
+
+    /// Get the boolean argument at the given index.
+    pub fn extract_bool(&self, index: usize) -> Result<bool, ErrorMessage> {
+        self.get(index)
+    }
+
+    /// Get the byte argument at the given index.
+    pub fn extract_byte(&self, index: usize) -> Result<u8, ErrorMessage> {
+        self.get(index)
+    }
+
+    /// Get the double argument at the given index.
+    pub fn extract_f64(&self, index: usize) -> Result<f64, ErrorMessage> {
+        self.get(index)
+    }
+
+    /// Get the object path argument at the given index.
+    pub fn extract_object_path(&self, index: usize) -> Result<&Path, ErrorMessage> {
+        match *self.extract(index)? {
+            Value::BasicValue(BasicValue::ObjectPath(ref path)) => Ok(path),
+            _ => Err(Self::invalid_argument(index)),
+        }
+    }
+
+    /// Get the fields of the struct argument at the given index.
+    pub fn extract_struct(&self, index: usize) -> Result<&Vec<Value>, ErrorMessage> {
+        match *self.extract(index)? {
+            Value::Struct(ref st) => Ok(&st.objects),
+            _ => Err(Self::invalid_argument(index)),
+        }
+    }
+
+    /// Get the elements of the array argument at the given index.
+    pub fn extract_array(&self, index: usize) -> Result<&Vec<Value>, ErrorMessage> {
+        match *self.extract(index)? {
+            Value::Array(ref array) => Ok(&array.objects),
+            _ => Err(Self::invalid_argument(index)),
         }
     }
 
+    /// Get the dictionary argument with string keys at the given index.
+    ///
+    /// Values which are variants (e.g., for `a{sv}`) are unwrapped.
+    pub fn extract_dict(&self, index: usize) -> Result<HashMap<String, Value>, ErrorMessage> {
+        _extract_dict(self.extract(index)?).ok_or_else(|| Self::invalid_argument(index))
+    }
+
+    /// An error indicating that the arguments are invalid.
     pub fn invalid_arguments() -> ErrorMessage {
         ErrorMessage::new("org.freedesktop.DBus.Error.InvalidArgs",
                           "invalid arguments")
     }
 
+    /// An error indicating that the argument at the given index is invalid.
+    ///
+    /// The index is included as a second argument of the error for programmatic clients.
     fn invalid_argument(index: usize) -> ErrorMessage {
-        ErrorMessage::new("org.freedesktop.DBus.Error.InvalidArgs",
-                          &format!("invalid argument at {}", index))
+        Self::_invalid_argument_message(index, format!("invalid argument at {}", index))
+    }
+
+    /// An error indicating why the argument at the given index is invalid.
+    fn _invalid_argument_reason(index: usize, reason: &str) -> ErrorMessage {
+        Self::_invalid_argument_message(index,
+                                        format!("invalid argument at {}: {}", index, reason))
+    }
+
+    fn _invalid_argument_message(index: usize, message: String) -> ErrorMessage {
+        ErrorMessage::new("org.freedesktop.DBus.Error.InvalidArgs", message)
+            .add_argument(Value::BasicValue(BasicValue::Uint32(index as u32)))
+    }
+}
+
+impl Drop for Arguments {
+    fn drop(&mut self) {
+        if let Some(ref pool) = self.pool {
+            pool.recycle(mem::replace(&mut self.values, vec![]));
+        }
     }
 }
+
+/// The values returned from a method call.
+///
+/// This mirrors `Arguments` for use by clients. Extraction methods return `InvalidReply` errors.
+pub struct Reply {
+    values: Vec<Value>,
+}
+
+impl Reply {
+    /// Wrap the values returned from a method call.
+    pub fn new(values: Vec<Value>) -> Self {
+        Reply {
+            values: values,
+        }
+    }
+
+    /// Get the value at the given index.
+    pub fn extract(&self, index: usize) -> Result<&Value, Error> {
+        match self.values.get(index) {
+            Some(value) => Ok(value),
+            None => Err(Self::invalid_value(index)),
+        }
+    }
+
+    /// Get the string value at the given index.
+    pub fn extract_string(&self, index: usize) -> Result<&String, Error> {
+        _extract_string(self.extract(index)?).ok_or_else(|| Self::invalid_value(index))
+    }
+
+    /// Get the unsigned 32-bit integer value at the given index.
+    pub fn extract_u32(&self, index: usize) -> Result<u32, Error> {
+        _extract_u32(self.extract(index)?).ok_or_else(|| Self::invalid_value(index))
+    }
+
+    /// Get the dictionary value with string keys at the given index.
+    ///
+    /// Values which are variants (e.g., for `a{sv}`) are unwrapped.
+    pub fn extract_dict(&self, index: usize) -> Result<HashMap<String, Value>, Error> {
+        _extract_dict(self.extract(index)?).ok_or_else(|| Self::invalid_value(index))
+    }
+
+    /// Unwrap the values.
+    pub fn into_values(self) -> Vec<Value> {
+        self.values
+    }
+
+    fn invalid_value(index: usize) -> Error {
+        ErrorKind::InvalidReply(format!("invalid value at {}", index)).into()
+    }
+}
+
+#[test]
+fn decode_signal_body() {
+    struct Renamed {
+        old: String,
+        new: String,
+    }
+
+    impl FromSignalBody for Renamed {
+        fn from_signal_body(args: &Arguments) -> Result<Self, ErrorMessage> {
+            Ok(Renamed {
+                old: args.extract_string(0)?.clone(),
+                new: args.extract_string(1)?.clone(),
+            })
+        }
+    }
+
+    let msg = Message::new_signal("/", "net.benboeckel.test.rustbus", "Renamed")
+        .add_argument(&"before")
+        .add_argument(&"after");
+    let renamed = Renamed::from_signal_body(&Arguments::new(&msg).ok().unwrap()).ok().unwrap();
+
+    assert_eq!(renamed.old, "before");
+    assert_eq!(renamed.new, "after");
+
+    let msg = Message::new_signal("/", "net.benboeckel.test.rustbus", "Renamed")
+        .add_argument(&"before");
+    assert!(Renamed::from_signal_body(&Arguments::new(&msg).ok().unwrap()).is_err());
+}
+
+#[test]
+fn checked_signature_mismatch() {
+    let msg = Message::new_method_call("net.benboeckel.test.rustbus",
+                                       "/",
+                                       "net.benboeckel.test.Interface",
+                                       "Method")
+        .add_argument(&"string")
+        .add_argument(&1i32);
+
+    assert!(Arguments::new_checked(&msg, "si").is_ok());
+
+    let err = Arguments::new_checked(&msg, "su").err().unwrap();
+    assert_eq!(err.name(), "org.freedesktop.DBus.Error.InvalidArgs");
+    assert_eq!(err.message(),
+               "invalid arguments: expected signature 'su', got 'si'");
+}
+
+#[test]
+fn invalid_argument_index() {
+    let msg = Message::new_method_call("net.benboeckel.test.rustbus",
+                                       "/",
+                                       "net.benboeckel.test.Interface",
+                                       "Method")
+        .add_argument(&"string")
+        .add_argument(&1i32);
+    let args = Arguments::new(&msg).ok().unwrap();
+
+    let err = args.extract_string(1).err().unwrap();
+    assert_eq!(err.name(), "org.freedesktop.DBus.Error.InvalidArgs");
+    assert_eq!(err.message(), "invalid argument at 1");
+    assert_eq!(err.args(), &[Value::BasicValue(BasicValue::Uint32(1))]);
+}
+
+#[test]
+fn reply_extraction() {
+    let reply = Reply::new(vec![Value::BasicValue(BasicValue::String("name".to_string())),
+                                Value::BasicValue(BasicValue::Uint32(5))]);
+
+    assert_eq!(reply.extract_string(0).unwrap(), "name");
+    assert_eq!(reply.extract_u32(1).unwrap(), 5);
+
+    let err = reply.extract_u32(0).unwrap_err();
+    assert_eq!(format!("{}", err), "invalid reply: invalid value at 0");
+    assert!(reply.extract(2).is_err());
+}
+
+#[test]
+fn pooled_arguments() {
+    use value::{Array, Signature, Struct};
+
+    let msg = Message::new_method_call("net.benboeckel.test.rustbus",
+                                       "/",
+                                       "net.benboeckel.test.Interface",
+                                       "Method")
+        .add_argument(&"name")
+        .add_argument(&Value::Struct(Struct {
+            objects: vec![Value::BasicValue(BasicValue::Int32(42))],
+            signature: Signature("(i)".to_string()),
+        }))
+        .add_argument(&Value::Array(Array::new_with_sig(vec![], "as".to_string())));
+    let expected = msg.values().unwrap().unwrap();
+
+    let pool = Rc::new(ValuePool::new());
+    let msg = msg.with_value_pool(pool.clone());
+    for _ in 0..2 {
+        let args = Arguments::new(&msg).ok().unwrap();
+        for (index, value) in expected.iter().enumerate() {
+            assert_eq!(args.extract(index).ok(), Some(value));
+        }
+        assert_eq!(args.extract_string(0).unwrap(), "name");
+    }
+
+    // The buffers of the last arguments were returned to the pool.
+    assert!(pool.take_string().capacity() > 0);
+    assert!(pool.take_values().capacity() > 0);
+}
+
+#[test]
+fn reply_dict_extraction() {
+    use value::{Dictionary, Variant};
+
+    let mut map = HashMap::new();
+    map.insert(BasicValue::String("Count".to_string()),
+               Value::Variant(Variant::new(Value::BasicValue(BasicValue::Uint32(5)), "u")));
+    map.insert(BasicValue::String("Name".to_string()),
+               Value::Variant(Variant::new(Value::BasicValue(BasicValue::String("name"
+                                               .to_string())),
+                                           "s")));
+    let reply = Reply::new(vec![Value::Dictionary(Dictionary::new_with_sig(map,
+                                                                         "a{sv}".to_string())),
+                                Value::BasicValue(BasicValue::Uint32(5))]);
+
+    let dict = reply.extract_dict(0).unwrap();
+    assert_eq!(dict.len(), 2);
+    assert_eq!(dict["Count"], Value::BasicValue(BasicValue::Uint32(5)));
+    assert_eq!(dict["Name"], Value::BasicValue(BasicValue::String("name".to_string())));
+
+    assert!(reply.extract_dict(1).is_err());
+}
+
+#[test]
+fn typed_argument_extraction() {
+    let msg = Message::new_method_call("net.benboeckel.test.rustbus",
+                                       "/",
+                                       "net.benboeckel.test.Interface",
+                                       "Method")
+        .add_argument(&1u32)
+        .add_argument(&-2i32)
+        .add_argument(&3u64)
+        .add_argument(&-4i64)
+        .add_argument(&true)
+        .add_argument(&0.5f64)
+        .add_argument(&6u8)
+        .add_argument(&Value::BasicValue(BasicValue::ObjectPath(Path("/net/benboeckel"
+            .to_string()))));
+    let args = Arguments::new(&msg).ok().unwrap();
+
+    assert_eq!(args.extract_u32(0).ok(), Some(1));
+    assert_eq!(args.extract_i32(1).ok(), Some(-2));
+    assert_eq!(args.extract_u64(2).ok(), Some(3));
+    assert_eq!(args.extract_i64(3).ok(), Some(-4));
+    assert_eq!(args.extract_bool(4).ok(), Some(true));
+    assert_eq!(args.extract_f64(5).ok(), Some(0.5));
+    assert_eq!(args.extract_byte(6).ok(), Some(6));
+    assert_eq!(args.extract_object_path(7).ok().map(|path| path.0.as_str()),
+               Some("/net/benboeckel"));
+
+    let err = args.extract_i32(0).err().unwrap();
+    assert_eq!(err.message(),
+               "invalid argument at 0: unexpected value: expected type 'i', found \
+                BasicValue::Uint32");
+    let err = args.extract_bool(8).err().unwrap();
+    assert_eq!(err.message(), "invalid argument at 8");
+}
+
+#[test]
+fn generic_argument_extraction() {
+    let msg = Message::new_method_call("net.benboeckel.test.rustbus",
+                                       "/",
+                                       "net.benboeckel.test.Interface",
+                                       "Method")
+        .add_argument(&"name")
+        .add_argument(&5u32);
+    let args = Arguments::new(&msg).ok().unwrap();
+
+    let name: String = args.get(0).ok().unwrap();
+    let count: u32 = args.get(1).ok().unwrap();
+    assert_eq!(name, "name");
+    assert_eq!(count, 5);
+
+    let err = args.get::<i32>(1).err().unwrap();
+    assert_eq!(err.name(), "org.freedesktop.DBus.Error.InvalidArgs");
+    assert_eq!(err.message(),
+               "invalid argument at 1: unexpected value: expected type 'i', found \
+                BasicValue::Uint32");
+}
+
+#[test]
+fn argument_count_and_iteration() {
+    let msg = Message::new_method_call("net.benboeckel.test.rustbus",
+                                       "/",
+                                       "net.benboeckel.test.Interface",
+                                       "Method")
+        .add_argument(&"first")
+        .add_argument(&"second")
+        .add_argument(&"third");
+    let args = Arguments::new(&msg).ok().unwrap();
+
+    assert_eq!(args.len(), 3);
+    assert!(!args.is_empty());
+    assert_eq!(args.iter().map(|v| _extract_string(v).unwrap().as_str()).collect::<Vec<_>>(),
+               vec!["first", "second", "third"]);
+
+    let msg = Message::new_method_call("net.benboeckel.test.rustbus",
+                                       "/",
+                                       "net.benboeckel.test.Interface",
+                                       "Method");
+    let args = Arguments::new(&msg).ok().unwrap();
+    assert!(args.is_empty());
+}
+
+#[test]
+fn container_argument_extraction() {
+    use value::{Array, Dictionary, IntoValue, Signature, Struct, Variant};
+
+    let mut map = HashMap::new();
+    map.insert(BasicValue::String("Count".to_string()),
+               Value::Variant(Variant::new(Value::BasicValue(BasicValue::Uint32(5)), "u")));
+    let msg = Message::new_method_call("net.benboeckel.test.rustbus",
+                                       "/",
+                                       "net.benboeckel.test.Interface",
+                                       "Method")
+        .add_argument(&Value::Struct(Struct {
+            objects: vec![Value::BasicValue(BasicValue::String("answer".to_string())),
+                          Value::BasicValue(BasicValue::Int32(42))],
+            signature: Signature("(si)".to_string()),
+        }))
+        .add_argument(&Value::Dictionary(Dictionary::new_with_sig(map, "a{sv}".to_string())))
+        .add_argument(&Value::Array(Array::new_with_sig(vec![1u8.into_value()],
+                                                        "ay".to_string())));
+    let args = Arguments::new(&msg).ok().unwrap();
+
+    assert_eq!(*args.extract_struct(0).unwrap(),
+               vec![Value::BasicValue(BasicValue::String("answer".to_string())),
+                    Value::BasicValue(BasicValue::Int32(42))]);
+    let dict = args.extract_dict(1).unwrap();
+    assert_eq!(dict.len(), 1);
+    assert_eq!(dict["Count"], Value::BasicValue(BasicValue::Uint32(5)));
+    assert_eq!(*args.extract_array(2).unwrap(), vec![Value::BasicValue(BasicValue::Byte(1))]);
+
+    assert!(args.extract_struct(1).is_err());
+    assert!(args.extract_dict(0).is_err());
+    assert!(args.extract_array(0).is_err());
+}
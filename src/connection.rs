@@ -1,12 +1,24 @@
 // Distributed under the OSI-approved BSD 3-Clause License.
 // See accompanying LICENSE file for details.
 
-use crates::dbus_bytestream::connection;
-
 use error::*;
+use interface::ErrorMessage;
 use message::{Message, MessageType};
+use transport::Transport;
 use value::{BasicValue, Value};
 
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::env;
+use std::path::Path;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+/// The number of seconds the `call` function waits for a reply.
+///
+/// This matches the default used by the reference D-Bus implementation.
+pub const DEFAULT_CALL_TIMEOUT_SECS: u64 = 25;
+
 bitflags! {
     /// Flags for use when requesting a name on the bus from the bus.
     pub flags RequestNameFlags: u32 {
@@ -35,6 +47,19 @@ pub enum RequestNameReply {
     AlreadyOwner,
 }
 
+impl RequestNameReply {
+    /// Decode the reply code of a `RequestName` call.
+    pub fn from_code(code: u32) -> Option<Self> {
+        match code {
+            1 => Some(RequestNameReply::PrimaryOwner),
+            2 => Some(RequestNameReply::InQueue),
+            3 => Some(RequestNameReply::Exists),
+            4 => Some(RequestNameReply::AlreadyOwner),
+            _ => None,
+        }
+    }
+}
+
 #[derive(Debug, PartialEq, Eq)]
 /// Replies from the server when releasing a name.
 pub enum ReleaseNameReply {
@@ -46,9 +71,58 @@ pub enum ReleaseNameReply {
     NotOwner,
 }
 
+impl ReleaseNameReply {
+    /// Decode the reply code of a `ReleaseName` call.
+    pub fn from_code(code: u32) -> Option<Self> {
+        match code {
+            1 => Some(ReleaseNameReply::Released),
+            2 => Some(ReleaseNameReply::NonExistent),
+            3 => Some(ReleaseNameReply::NotOwner),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// A well-known message bus.
+pub enum BusType {
+    /// The per-user session bus.
+    Session,
+    /// The system-wide bus.
+    System,
+}
+
+impl BusType {
+    /// Determine the bus to use from the environment.
+    ///
+    /// Services activated by a bus daemon are told which bus started them using the
+    /// `DBUS_STARTER_BUS_TYPE` variable, so it takes precedence if it is set to either `session`
+    /// or `system`. Otherwise, the session bus is used if `DBUS_SESSION_BUS_ADDRESS` is set and
+    /// the system bus is used if it is not.
+    pub fn from_env() -> Self {
+        let starter = env::var("DBUS_STARTER_BUS_TYPE").ok();
+        let has_session = env::var_os("DBUS_SESSION_BUS_ADDRESS").is_some();
+
+        Self::_from_vars(starter.as_ref().map(String::as_str), has_session)
+    }
+
+    /// Determine the bus to use from the values of the environment variables.
+    fn _from_vars(starter: Option<&str>, has_session: bool) -> Self {
+        match starter {
+            Some("session") => BusType::Session,
+            Some("system") => BusType::System,
+            _ if has_session => BusType::Session,
+            _ => BusType::System,
+        }
+    }
+}
+
+/// A function which receives errors from method handlers before they are sanitized.
+pub type ErrorLogger = Rc<Fn(&ErrorMessage)>;
+
 /// An iterator over messages received from the message bus.
 pub struct Messages<'a> {
-    conn: &'a connection::Connection,
+    conn: &'a Connection,
 }
 
 /// A connection to a bus.
@@ -56,25 +130,147 @@ pub struct Messages<'a> {
 /// A connection is usually to either the system bus or a session bus. User services (e.g.,
 /// `SecretService`, notification daemons, etc.) live on the session bus while system services
 /// (e.g., `Udisks2`, `NetworkManager`, etc.) live on the system bus.
+///
+/// Connections may also be made directly to a peer without a bus daemon in between.
 pub struct Connection {
-    conn: connection::Connection,
+    conn: Transport,
+    bus: Option<BusType>,
+
+    queue: RefCell<VecDeque<Message>>,
+    sanitize_errors: Cell<bool>,
+    error_logger: RefCell<Option<ErrorLogger>>,
 }
 
 impl Connection {
     // TODO: Expose other connection methods?
 
+    fn _new(conn: Transport, bus: Option<BusType>) -> Self {
+        Connection {
+            conn: conn,
+            bus: bus,
+
+            queue: RefCell::new(VecDeque::new()),
+            sanitize_errors: Cell::new(false),
+            error_logger: RefCell::new(None),
+        }
+    }
+
+    /// Connect to the given bus.
+    pub fn new(bus: BusType) -> Result<Self> {
+        match bus {
+            BusType::Session => Self::session_new(),
+            BusType::System => Self::system_new(),
+        }
+    }
+
     /// Connect to the session bus.
     pub fn session_new() -> Result<Self> {
-        Ok(Connection {
-            conn: connection::Connection::connect_session()?,
-        })
+        Ok(Self::_new(Transport::connect_session()?, Some(BusType::Session)))
     }
 
     /// Connect to the system bus.
     pub fn system_new() -> Result<Self> {
-        Ok(Connection {
-            conn: connection::Connection::connect_system()?,
-        })
+        Ok(Self::_new(Transport::connect_system()?, Some(BusType::System)))
+    }
+
+    /// Connect directly to a peer listening on the given Unix socket.
+    ///
+    /// Since there is no bus daemon to route signals on a peer-to-peer connection, signals sent
+    /// over the connection are also delivered to handlers registered on this side of the
+    /// connection.
+    pub fn peer_new<P>(path: P) -> Result<Self>
+        where P: AsRef<Path>,
+    {
+        Ok(Self::_new(Transport::connect_uds(path)?, None))
+    }
+
+    /// The bus the connection is to.
+    ///
+    /// Returns `None` for peer-to-peer connections.
+    pub fn bus_type(&self) -> Option<BusType> {
+        self.bus
+    }
+
+    /// Replace the descriptions of errors returned from method handlers with a generic one.
+    ///
+    /// This avoids leaking internal details (e.g., file paths) to untrusted callers. The names of
+    /// errors are preserved and the original errors are given to the error logger (see
+    /// `set_error_logger`).
+    pub fn set_sanitize_errors(&self, sanitize: bool) {
+        self.sanitize_errors.set(sanitize);
+    }
+
+    /// Whether error descriptions from method handlers are sanitized.
+    pub fn sanitizes_errors(&self) -> bool {
+        self.sanitize_errors.get()
+    }
+
+    /// Set a function to receive errors from method handlers before they are sanitized.
+    ///
+    /// Without a logger, the original descriptions of sanitized errors are discarded.
+    pub fn set_error_logger<F>(&self, logger: F)
+        where F: Fn(&ErrorMessage) + 'static,
+    {
+        *self.error_logger.borrow_mut() = Some(Rc::new(logger));
+    }
+
+    /// The function receiving errors before they are sanitized.
+    pub fn error_logger(&self) -> Option<ErrorLogger> {
+        self.error_logger.borrow().clone()
+    }
+
+    /// Whether the connection is directly to a peer rather than a bus.
+    pub fn is_peer(&self) -> bool {
+        self.bus.is_none()
+    }
+
+    /// The unique name assigned to the connection by the bus.
+    ///
+    /// Unique names start with a `:` (e.g., `:1.23`). The bus assigns the name in its reply to
+    /// the `Hello` call made while connecting. Peer-to-peer connections have no bus to assign a
+    /// name, so `None` is returned for them.
+    pub fn unique_name(&self) -> Option<String> {
+        self.conn.unique_name().map(ToString::to_string)
+    }
+
+    /// Send a method call and wait for its reply.
+    ///
+    /// Other messages received while waiting are queued for the message iterator.
+    fn _call_until(&self, msg: Message, timeout: Duration) -> Result<Message> {
+        let method = msg.member().unwrap_or_else(String::new);
+        let start = Instant::now();
+        let serial = self.send(msg)?;
+
+        loop {
+            let remaining = match timeout.checked_sub(start.elapsed()) {
+                Some(remaining) => remaining,
+                None => bail!(ErrorKind::Timeout(method, timeout)),
+            };
+
+            let reply = match self.conn.read_msg_timeout(Some(remaining))? {
+                Some(reply) => reply,
+                None => continue,
+            };
+            if reply.reply_serial() == Some(serial) {
+                return Ok(reply);
+            }
+
+            if _should_handle(&reply) {
+                self.queue.borrow_mut().push_back(reply);
+            }
+        }
+    }
+
+    /// Call a method and wait for its reply.
+    ///
+    /// The values of the reply are returned. Error replies are returned as errors. Other messages
+    /// received while waiting are queued for the message iterator. If no reply is received within
+    /// the timeout, a `Timeout` error is returned.
+    pub fn call(&self, msg: Message, timeout: Duration) -> Result<Vec<Value>> {
+        let method = msg.member().unwrap_or_else(String::new);
+        let reply = self._call_until(msg, timeout)?;
+
+        _reply_values(&method, reply)
     }
 
     /// Request a name on the bus.
@@ -94,12 +290,9 @@ impl Connection {
             .add_argument(&flags.bits);
         if let Some(mut results) = self.conn.call_sync(msg.message)? {
             if let Some(Value::BasicValue(BasicValue::Uint32(r))) = results.pop() {
-                match r {
-                    1 => Ok(RequestNameReply::PrimaryOwner),
-                    2 => Ok(RequestNameReply::InQueue),
-                    3 => Ok(RequestNameReply::Exists),
-                    4 => Ok(RequestNameReply::AlreadyOwner),
-                    _ => bail!(ErrorKind::InvalidReply(format!("RequestName: invalid response {}", r))),
+                match RequestNameReply::from_code(r) {
+                    Some(reply) => Ok(reply),
+                    None => bail!(ErrorKind::InvalidReply(format!("RequestName: invalid response {}", r))),
                 }
             } else {
                 bail!(ErrorKind::InvalidReply("RequestName: invalid response".to_string()));
@@ -119,11 +312,9 @@ impl Connection {
             .add_argument(&name);
         if let Some(mut results) = self.conn.call_sync(msg.message)? {
             if let Some(Value::BasicValue(BasicValue::Uint32(r))) = results.pop() {
-                match r {
-                    1 => Ok(ReleaseNameReply::Released),
-                    2 => Ok(ReleaseNameReply::NonExistent),
-                    3 => Ok(ReleaseNameReply::NotOwner),
-                    _ => bail!(ErrorKind::InvalidReply(format!("ReleaseName: invalid response {}", r))),
+                match ReleaseNameReply::from_code(r) {
+                    Some(reply) => Ok(reply),
+                    None => bail!(ErrorKind::InvalidReply(format!("ReleaseName: invalid response {}", r))),
                 }
             } else {
                 bail!(ErrorKind::InvalidReply("ReleaseName: invalid response".to_string()));
@@ -140,35 +331,192 @@ impl Connection {
     ///
     /// The match syntax is documented in the [D-Bus
     /// specification](https://dbus.freedesktop.org/doc/dbus-specification.html#message-bus-routing).
-    pub fn add_match(&self, match_rule: &str) -> Result<()> {
+    ///
+    /// Rules may be given as strings or built using `MatchRule`.
+    ///
+    /// Peer-to-peer connections have no bus to route messages, so this does nothing for them.
+    pub fn add_match<R>(&self, match_rule: R) -> Result<()>
+        where R: ToString,
+    {
+        if self.is_peer() {
+            return Ok(());
+        }
+
         let msg = Message::new_method_call("org.freedesktop.DBus",
                                            "/org/freedesktop/DBus",
                                            "org.freedesktop.DBus",
                                            "AddMatch")
-            .add_argument(&match_rule);
+            .add_argument(&match_rule.to_string());
         self.conn.call_sync(msg.message)?;
         Ok(())
     }
 
+    /// Remove a match rule previously added with `add_match`.
+    pub fn remove_match<R>(&self, match_rule: R) -> Result<()>
+        where R: ToString,
+    {
+        if self.is_peer() {
+            return Ok(());
+        }
+
+        let msg = Message::new_method_call("org.freedesktop.DBus",
+                                           "/org/freedesktop/DBus",
+                                           "org.freedesktop.DBus",
+                                           "RemoveMatch")
+            .add_argument(&match_rule.to_string());
+        self.conn.call_sync(msg.message)?;
+        Ok(())
+    }
+
+    /// Whether file descriptors may be passed over the connection.
+    ///
+    /// This is negotiated with the bus or peer while connecting. Handlers should check this
+    /// before attempting to pass file descriptors and return a
+    /// `org.freedesktop.DBus.Error.NotSupported` error instead.
+    pub fn supports_fd_passing(&self) -> bool {
+        self.conn.supports_fd_passing()
+    }
+
+    /// The maximum number of file descriptors which may be attached to a message.
+    ///
+    /// This is `0` unless passing file descriptors was negotiated (see `supports_fd_passing`).
+    pub fn max_fds(&self) -> u32 {
+        self.conn.max_fds()
+    }
+
     /// Send a `Message` on the bus.
     ///
-    /// On success, returns the serial number of the message.
+    /// On success, returns the serial number of the message. Messages with more file descriptors
+    /// attached than allowed by `max_fds` are rejected.
     pub fn send(&self, msg: Message) -> Result<u32> {
-        Ok(self.conn.send(msg.message)?)
+        _check_fds(&msg, self.max_fds())?;
+
+        let local = match msg.message_type() {
+            MessageType::Signal if self.is_peer() => Some(msg.try_clone()?),
+            _ => None,
+        };
+
+        let serial = self.conn.send(&msg.message, msg.fds())?;
+
+        if let Some(mut local) = local {
+            // Local handlers see the same serial as the peer.
+            local.message.serial = serial;
+            self.queue.borrow_mut().push_back(local);
+        }
+
+        Ok(serial)
+    }
+
+    /// Take a message which has already been received without waiting for one.
+    ///
+    /// Messages received while waiting for the reply to a method call are queued. Since reading
+    /// from the underlying transport blocks, messages which have not been read yet are not
+    /// returned.
+    pub fn pending_message(&self) -> Option<Message> {
+        self.queue.borrow_mut().pop_front()
+    }
+
+    /// Wait for the next message received over the connection.
+    ///
+    /// Unlike the message iterator, errors reading from the connection (e.g., because it has been
+    /// closed) are returned.
+    pub fn read_message(&self) -> Result<Message> {
+        if let Some(message) = self.pending_message() {
+            return Ok(message);
+        }
+
+        loop {
+            let message = self.conn.read_msg()?;
+            if _should_handle(&message) {
+                return Ok(message);
+            }
+        }
+    }
+
+    /// Wait for the next message for at most the given amount of time.
+    ///
+    /// Returns `None` if no message was received in time. A timeout of zero only returns messages
+    /// which are ready to be read.
+    pub fn read_message_timeout(&self, timeout: Option<Duration>) -> Result<Option<Message>> {
+        if let Some(message) = self.pending_message() {
+            return Ok(Some(message));
+        }
+
+        let start = Instant::now();
+
+        loop {
+            let remaining = timeout.map(|timeout| {
+                timeout.checked_sub(start.elapsed()).unwrap_or_else(|| Duration::from_secs(0))
+            });
+
+            match self.conn.read_msg_timeout(remaining)? {
+                Some(message) => {
+                    if _should_handle(&message) {
+                        return Ok(Some(message));
+                    }
+                },
+                None => return Ok(None),
+            }
+        }
     }
 
     /// An iterator over messages received over the bus.
     pub fn iter(&self) -> Messages {
         Messages {
-            conn: &self.conn,
+            conn: self,
         }
     }
 }
 
+/// Call a method using a private connection to the given bus.
+///
+/// This is intended for scripts and tests which only need to make a single call without setting
+/// up a `Runner`. The connection is closed once the reply has been received. If no reply is
+/// received within `DEFAULT_CALL_TIMEOUT_SECS` seconds, a `Timeout` error is returned.
+pub fn call(bus: BusType, dest: &str, path: &str, iface: &str, method: &str, args: &[Value])
+            -> Result<Vec<Value>> {
+    let conn = Connection::new(bus)?;
+    let msg = args.iter()
+        .fold(Message::new_method_call(dest, path, iface, method),
+              |msg, arg| msg.add_argument(arg));
+
+    conn.call(msg, Duration::from_secs(DEFAULT_CALL_TIMEOUT_SECS))
+}
+
+/// Extract the values of a method reply.
+///
+/// Error replies are returned as errors.
+pub fn _reply_values(method: &str, reply: Message) -> Result<Vec<Value>> {
+    let values = reply.values()?.unwrap_or_else(Vec::new);
+
+    match reply.message_type() {
+        MessageType::MethodReturn => Ok(values),
+        MessageType::Error => {
+            let name = reply.error_name().unwrap_or_else(|| "unknown error".to_string());
+            let desc = match values.first() {
+                Some(&Value::BasicValue(BasicValue::String(ref s))) => s.clone(),
+                _ => "no description".to_string(),
+            };
+
+            bail!(ErrorKind::RemoteError(name, desc))
+        },
+        _ => bail!(ErrorKind::InvalidReply(format!("{}: unexpected reply", method))),
+    }
+}
+
+fn _check_fds(message: &Message, max: u32) -> Result<()> {
+    let count = message.fds().len();
+    if count > max as usize {
+        bail!(ErrorKind::TooManyFds(count, max));
+    }
+
+    Ok(())
+}
+
 fn _should_handle(message: &Message) -> bool {
     match message.message_type() {
-        MessageType::MethodCall | MessageType::Signal => true,
-        _ => false,
+        MessageType::Invalid => false,
+        _ => true,
     }
 }
 
@@ -181,17 +529,248 @@ impl<'a> Iterator for Messages<'a> {
     /// issue](https://github.com/srwalter/dbus-bytestream/issues/10) for progress on supporting an
     /// event loop.
     fn next(&mut self) -> Option<Self::Item> {
-        let res = self.conn.read_msg();
-        match res {
-            Ok(message) => {
-                let dbus_message = Message::new(message);
-                if _should_handle(&dbus_message) {
-                    Some(dbus_message)
+        self.conn.read_message().ok()
+    }
+}
+
+#[cfg(test)]
+/// Connect to a peer which accepts the authentication of the client.
+///
+/// The peer agrees to pass file descriptors if requested. The peer's end of the connection is
+/// returned once the client has authenticated.
+pub fn _peer_connection(name: &str, unix_fds: bool)
+                        -> (Connection, ::std::os::unix::net::UnixStream) {
+    use std::env;
+    use std::fs;
+    use std::io::{Read, Write};
+    use std::os::unix::net::UnixListener;
+    use std::process;
+    use std::thread;
+
+    let path = env::temp_dir().join(format!("rust-bus-{}-{}", name, process::id()));
+    let _ = fs::remove_file(&path);
+    let listener = UnixListener::bind(&path).unwrap();
+
+    let peer = thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut received = String::new();
+        let mut buf = [0; 256];
+
+        while !received.contains("BEGIN\r\n") {
+            let len = stream.read(&mut buf).unwrap();
+            if len == 0 {
+                break;
+            }
+
+            let was_authed = received.contains("AUTH");
+            let was_negotiated = received.contains("NEGOTIATE_UNIX_FD");
+            received.push_str(&String::from_utf8_lossy(&buf[..len]));
+            if !was_authed && received.contains("AUTH") {
+                stream.write_all(b"OK 0123456789abcdef0123456789abcdef\r\n").unwrap();
+            }
+            if !was_negotiated && received.contains("NEGOTIATE_UNIX_FD") {
+                let reply: &[u8] = if unix_fds {
+                    b"AGREE_UNIX_FD\r\n"
                 } else {
-                    None
-                }
-            },
-            Err(_) => None,
+                    b"ERROR\r\n"
+                };
+                stream.write_all(reply).unwrap();
+            }
         }
+
+        stream
+    });
+
+    let conn = Connection::peer_new(&path).unwrap();
+    let stream = peer.join().unwrap();
+    fs::remove_file(&path).unwrap();
+
+    (conn, stream)
+}
+
+#[cfg(test)]
+/// Send a message to the client from the peer's end of a connection.
+pub fn _peer_send(mut stream: &::std::os::unix::net::UnixStream, msg: &Message, serial: u32) {
+    use std::io::Write;
+
+    stream.write_all(&::transport::_encode(&msg.message, serial, 0).unwrap()).unwrap();
+}
+
+#[test]
+fn too_many_fds() {
+    let msg = Message::new_signal("/", "net.benboeckel.test.rustbus", "Signal").add_fd(0);
+
+    assert!(_check_fds(&msg, 1).is_ok());
+
+    let err = _check_fds(&msg, 0).unwrap_err();
+    assert_eq!(format!("{}", err), "too many file descriptors: 1 (limit 0)");
+}
+
+#[test]
+fn fd_limits() {
+    let signal = |count| {
+        (0..count).fold(Message::new_signal("/", "net.benboeckel.test.rustbus", "Signal"),
+                        |msg, _| msg.add_fd(0))
+    };
+
+    let (conn, _stream) = _peer_connection("fd-limits", true);
+    assert!(conn.supports_fd_passing());
+    assert_eq!(conn.max_fds(), 16);
+
+    conn.send(signal(16)).unwrap();
+    let err = conn.send(signal(17)).unwrap_err();
+    assert_eq!(format!("{}", err), "too many file descriptors: 17 (limit 16)");
+
+    // Without negotiating, no file descriptors may be sent.
+    let (conn, _stream) = _peer_connection("no-fds", false);
+    assert!(!conn.supports_fd_passing());
+    assert_eq!(conn.max_fds(), 0);
+
+    conn.send(signal(0)).unwrap();
+    let err = conn.send(signal(1)).unwrap_err();
+    assert_eq!(format!("{}", err), "too many file descriptors: 1 (limit 0)");
+}
+
+#[test]
+fn session_fd_passing() {
+    let conn = Connection::session_new().unwrap();
+
+    assert!(conn.supports_fd_passing());
+}
+
+#[test]
+fn bus_unique_name() {
+    let conn = Connection::session_new().unwrap();
+    let name = conn.unique_name().unwrap();
+
+    assert!(name.starts_with(':'));
+    assert_eq!(conn.unique_name(), Some(name));
+}
+
+#[test]
+fn one_shot_call() {
+    let names = call(BusType::Session,
+                     "org.freedesktop.DBus",
+                     "/org/freedesktop/DBus",
+                     "org.freedesktop.DBus",
+                     "ListNames",
+                     &[])
+        .unwrap();
+
+    assert_eq!(names.len(), 1);
+    if let Value::Array(ref names) = names[0] {
+        assert!(names.objects
+            .contains(&Value::BasicValue(BasicValue::String("org.freedesktop.DBus".to_string()))));
+    } else {
+        panic!("unexpected ListNames reply");
     }
 }
+
+#[test]
+fn method_call_reply() {
+    let conn = Connection::session_new().unwrap();
+    let timeout = Duration::from_secs(5);
+
+    let msg = Message::new_method_call("org.freedesktop.DBus",
+                                       "/org/freedesktop/DBus",
+                                       "org.freedesktop.DBus",
+                                       "GetNameOwner")
+        .add_argument(&"org.freedesktop.DBus");
+    let owner = conn.call(msg, timeout).unwrap();
+    assert_eq!(owner,
+               vec![Value::BasicValue(BasicValue::String("org.freedesktop.DBus".to_string()))]);
+
+    let msg = Message::new_method_call("org.freedesktop.DBus",
+                                       "/org/freedesktop/DBus",
+                                       "org.freedesktop.DBus",
+                                       "GetNameOwner")
+        .add_argument(&"net.benboeckel.test.rustbus.missing");
+    match *conn.call(msg, timeout).unwrap_err().kind() {
+        ErrorKind::RemoteError(ref name, _) => {
+            assert_eq!(name, "org.freedesktop.DBus.Error.NameHasNoOwner")
+        },
+        ref kind => panic!("unexpected error: {}", kind),
+    }
+
+    let err = Error::from_kind(ErrorKind::Timeout("Method".to_string(),
+                                                  Duration::from_millis(1500)));
+    assert_eq!(format!("{}", err), "no reply to Method within 1500ms");
+}
+
+#[test]
+fn call_timeout() {
+    let (conn, _stream) = _peer_connection("call-timeout", true);
+    let timeout = Duration::from_millis(50);
+
+    // The peer never replies.
+    let msg = Message::new_method_call("net.benboeckel.test.rustbus",
+                                       "/net/benboeckel",
+                                       "net.benboeckel.test.Interface",
+                                       "Method");
+    let start = Instant::now();
+    match *conn.call(msg, timeout).unwrap_err().kind() {
+        ErrorKind::Timeout(ref method, t) => {
+            assert_eq!(method, "Method");
+            assert_eq!(t, timeout);
+        },
+        ref kind => panic!("unexpected error: {}", kind),
+    }
+    assert!(start.elapsed() >= timeout);
+    assert!(start.elapsed() < Duration::from_secs(5));
+
+    assert!(conn.read_message_timeout(Some(Duration::from_secs(0))).unwrap().is_none());
+}
+
+#[test]
+fn error_reply() {
+    let call = Message::new_method_call("net.benboeckel.test.rustbus",
+                                        "/",
+                                        "net.benboeckel.test.Interface",
+                                        "Method");
+    let reply = call.error_message("org.freedesktop.DBus.Error.UnknownMethod")
+        .add_argument(&"unknown method: Method");
+
+    let err = _reply_values("Method", reply).unwrap_err();
+    match *err.kind() {
+        ErrorKind::RemoteError(ref name, ref message) => {
+            assert_eq!(name, "org.freedesktop.DBus.Error.UnknownMethod");
+            assert_eq!(message, "unknown method: Method");
+        },
+        ref kind => panic!("unexpected error: {}", kind),
+    }
+    assert_eq!(format!("{}", err),
+               "org.freedesktop.DBus.Error.UnknownMethod: unknown method: Method");
+
+    let reply = call.return_message().add_argument(&5u32);
+    assert_eq!(_reply_values("Method", reply).unwrap(),
+               vec![Value::BasicValue(BasicValue::Uint32(5))]);
+}
+
+#[test]
+fn name_request_codes() {
+    assert_eq!(ALLOW_REPLACEMENT.bits, 0x1);
+    assert_eq!(REPLACE_EXISTING.bits, 0x2);
+    assert_eq!(DO_NOT_QUEUE.bits, 0x4);
+    assert_eq!((ALLOW_REPLACEMENT | DO_NOT_QUEUE).bits, 0x5);
+
+    assert_eq!(RequestNameReply::from_code(1), Some(RequestNameReply::PrimaryOwner));
+    assert_eq!(RequestNameReply::from_code(2), Some(RequestNameReply::InQueue));
+    assert_eq!(RequestNameReply::from_code(3), Some(RequestNameReply::Exists));
+    assert_eq!(RequestNameReply::from_code(4), Some(RequestNameReply::AlreadyOwner));
+    assert_eq!(RequestNameReply::from_code(5), None);
+
+    assert_eq!(ReleaseNameReply::from_code(1), Some(ReleaseNameReply::Released));
+    assert_eq!(ReleaseNameReply::from_code(2), Some(ReleaseNameReply::NonExistent));
+    assert_eq!(ReleaseNameReply::from_code(3), Some(ReleaseNameReply::NotOwner));
+    assert_eq!(ReleaseNameReply::from_code(0), None);
+}
+
+#[test]
+fn bus_type_precedence() {
+    assert_eq!(BusType::_from_vars(Some("session"), false), BusType::Session);
+    assert_eq!(BusType::_from_vars(Some("system"), true), BusType::System);
+    assert_eq!(BusType::_from_vars(Some("starter"), true), BusType::Session);
+    assert_eq!(BusType::_from_vars(Some("starter"), false), BusType::System);
+    assert_eq!(BusType::_from_vars(None, true), BusType::Session);
+    assert_eq!(BusType::_from_vars(None, false), BusType::System);
+}
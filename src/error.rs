@@ -4,19 +4,50 @@
 use crates::dbus_bytestream::connection;
 use crates::dbus_bytestream::demarshal;
 
+use std::io;
+use std::time::Duration;
+
 error_chain! {
     foreign_links {
-        DBusMessage(connection::Error)
-            #[doc = "An error message from the underlying D-Bus communication."];
+        Io(io::Error)
+            #[doc = "An error performing I/O (e.g., while connecting to a bus)."];
     }
 
     errors {
+        /// An error message from the underlying D-Bus communication.
+        ///
+        /// The error itself is available as the source of the error.
+        DBusMessage(desc: String) {
+            description("D-Bus communication error")
+            display("{}", desc)
+        }
+
         /// An invalid reply was received from a method call.
         InvalidReply(desc: String) {
             description("invalid reply")
             display("invalid reply: {}", desc)
         }
 
+        /// A method call failed with an error reply.
+        RemoteError(name: String, message: String) {
+            description("remote error")
+            display("{}: {}", name, message)
+        }
+
+        /// No reply to a method call was received in time.
+        Timeout(method: String, timeout: Duration) {
+            description("method call timed out")
+            display("no reply to {} within {}ms",
+                    method,
+                    timeout.as_secs() * 1000 + (timeout.subsec_nanos() / 1_000_000) as u64)
+        }
+
+        /// A reply was requested for a method call which is not being waited for.
+        NoPendingReply(serial: u32) {
+            description("no pending reply")
+            display("no method call with serial {} is waiting for a reply", serial)
+        }
+
         /// An object was added to a signal-receiver server.
         NoServerName {
             description("listening server cannot handle methods")
@@ -52,10 +83,142 @@ error_chain! {
             display("failed to extract arguments: {}", err)
         }
 
+        /// A message carried more file descriptors than the connection allows.
+        TooManyFds(count: usize, max: u32) {
+            description("too many file descriptors")
+            display("too many file descriptors: {} (limit {})", count, max)
+        }
+
+        /// A signature is not valid.
+        InvalidSignature(signature: String, reason: String) {
+            description("invalid signature")
+            display("invalid signature '{}': {}", signature, reason)
+        }
+
+        /// Data in the D-Bus wire format could not be read.
+        InvalidWireData(desc: String) {
+            description("invalid wire data")
+            display("invalid wire data: {}", desc)
+        }
+
+        /// An introspection document could not be parsed.
+        InvalidXml(desc: String) {
+            description("invalid introspection xml")
+            display("invalid introspection xml: {}", desc)
+        }
+
+        /// An introspection document does not follow the introspection format.
+        InvalidIntrospection(violations: Vec<String>) {
+            description("invalid introspection document")
+            display("invalid introspection document: {}", violations.join("; "))
+        }
+
+        /// A value is not of the expected type.
+        UnexpectedValue(expected: String, actual: String) {
+            description("unexpected value")
+            display("unexpected value: expected type '{}', found {}", expected, actual)
+        }
+
+        /// The value of a property could not be read.
+        PropertyRead(name: String, desc: String) {
+            description("failed to read property")
+            display("failed to read property {}: {}", name, desc)
+        }
+
         /// An attempt to redefine an interface for an object was made.
         InterfaceAlreadyRegistered(name: String) {
             description("interface already registered")
             display("interface already registered: {}", name)
         }
+
+        /// A standard interface was added manually.
+        ReservedInterface(name: String) {
+            description("reserved interface")
+            display("the {} interface is reserved; it is added automatically when the interfaces \
+                     are finalized",
+                    name)
+        }
+
+        /// An interface was requested to be removed, but it does not exist.
+        NoSuchInterface(name: String) {
+            description("no such interface")
+            display("no such interface: {}", name)
+        }
+
+        /// An attempt to redefine a method, property, or signal of an interface was made.
+        MemberAlreadyRegistered(name: String) {
+            description("member already registered")
+            display("member already registered: {}", name)
+        }
+
+        /// Communicating with the bus or peer failed.
+        Transport(desc: String) {
+            description("transport error")
+            display("transport error: {}", desc)
+        }
+
+        /// A signal was emitted which is not declared by the interface.
+        NoSuchSignal(name: String) {
+            description("no such signal")
+            display("no such signal: {}", name)
+        }
+
+        /// A signal was emitted with arguments which do not match its declaration.
+        InvalidSignalArguments(name: String, expected: String, actual: String) {
+            description("invalid signal arguments")
+            display("invalid arguments for signal {}: expected '{}', got '{}'",
+                    name, expected, actual)
+        }
+
+        /// A deferred reply does not match the results declared by the method.
+        InvalidReplyArguments(name: String, expected: String, actual: String) {
+            description("invalid reply arguments")
+            display("invalid reply for method {}: expected '{}', got '{}'",
+                    name, expected, actual)
+        }
+    }
+}
+
+// Foreign links only expose the source of the wrapped error, so the D-Bus error is chained
+// instead.
+impl From<connection::Error> for Error {
+    fn from(err: connection::Error) -> Self {
+        let desc = err.to_string();
+
+        Error::with_chain(err, ErrorKind::DBusMessage(desc))
+    }
+}
+
+#[test]
+fn io_error() {
+    fn connect() -> Result<()> {
+        Err(io::Error::new(io::ErrorKind::NotFound, "no such socket"))?;
+        Ok(())
+    }
+
+    let err = connect().unwrap_err();
+    match *err.kind() {
+        ErrorKind::Io(ref err) => assert_eq!(err.kind(), io::ErrorKind::NotFound),
+        ref kind => panic!("unexpected error: {}", kind),
     }
+    assert_eq!(format!("{}", err), "no such socket");
+}
+
+#[test]
+fn error_source() {
+    use std::error::Error as StdError;
+
+    let err = Error::from_kind(ErrorKind::NoServerName);
+    assert!(err.source().is_none());
+
+    let err = Error::from(connection::Error::AuthFailed);
+    match *err.kind() {
+        ErrorKind::DBusMessage(ref desc) => assert_eq!(*desc, err.source().unwrap().to_string()),
+        ref kind => panic!("unexpected error: {}", kind),
+    }
+
+    let res: Result<()> = Err(io::Error::new(io::ErrorKind::NotFound, "no such socket").into());
+    let err = res.chain_err(|| ErrorKind::NoServerName).unwrap_err();
+    assert_eq!(err.source().map(|err| err.to_string()),
+               Some("no such socket".to_string()));
 }
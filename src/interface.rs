@@ -5,21 +5,87 @@ use crates::core::ops::DerefMut;
 use crates::machine_id::MachineId;
 
 use arguments::Arguments;
-use connection::Connection;
+use connection::{Connection, ErrorLogger};
 use error::*;
 use message::{Message, MessageType};
-use value::{BasicValue, Dictionary, Signature, Value};
-
-use std::cell::{Ref, RefCell};
+use introspection::Element;
+use names;
+use signature;
+use value::{Array, ArrayStream, BasicValue, Dictionary, FromValue, HasSignature, IntoValue,
+            Marshal, Path, Signature, Value, ValueExt, Variant};
+
+use std::any::{Any, TypeId};
+use std::cell::{Ref, RefCell, RefMut};
+use std::collections::{HashMap, VecDeque};
 use std::collections::btree_map::{BTreeMap, Entry};
+use std::fmt;
 use std::rc::{Rc, Weak};
+use std::time::{Duration, Instant};
 
 type Map<T> = BTreeMap<String, T>;
 
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// A problem with the definition of an interface.
+pub struct ValidationError {
+    /// The interface with the problem.
+    pub interface: String,
+    /// The member of the interface with the problem, if any.
+    pub member: Option<String>,
+    /// A description of the problem.
+    pub reason: String,
+}
+
+impl ValidationError {
+    fn new<R>(interface: &str, member: Option<&str>, reason: R) -> Self
+        where R: ToString,
+    {
+        ValidationError {
+            interface: interface.to_string(),
+            member: member.map(ToString::to_string),
+            reason: reason.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.member {
+            Some(ref member) => write!(f, "{}.{}: {}", self.interface, member, self.reason),
+            None => write!(f, "{}: {}", self.interface, self.reason),
+        }
+    }
+}
+
+fn variant(value: Value) -> Value {
+    let signature = value.get_signature().to_string();
+    Value::Variant(Variant::new(value, &signature))
+}
+
+fn merge_members<T>(into: &mut Map<T>, from: Map<T>) -> Result<()> {
+    for (name, member) in from {
+        match into.entry(name) {
+            Entry::Vacant(v) => {
+                v.insert(member);
+            },
+            Entry::Occupied(o) => bail!(ErrorKind::MemberAlreadyRegistered(o.key().clone())),
+        }
+    }
+
+    Ok(())
+}
+
+/// The document type declaration of introspection documents.
+const INTROSPECTION_DOCTYPE: &'static str =
+    concat!(r#"<!DOCTYPE node PUBLIC "-//freedesktop//DTD D-BUS Object Introspection 1.0//EN""#,
+            "\n",
+            r#" "http://www.freedesktop.org/standards/dbus/1.0/introspect.dtd">"#,
+            "\n");
+
 /// An argument to a method or signal.
 pub struct Argument {
     name: String,
     signature: String,
+    description: Option<String>,
 }
 
 impl Argument {
@@ -35,8 +101,21 @@ impl Argument {
         Argument {
             name: name.to_string(),
             signature: sig.to_string(),
+            description: None,
         }
     }
+
+    /// Describe the argument.
+    ///
+    /// The description is added to the introspection data as an `org.gtk.GDBus.DocString`
+    /// annotation on the argument.
+    pub fn describe<D>(mut self, text: D) -> Self
+        where D: ToString,
+    {
+        self.description = Some(text.to_string());
+
+        self
+    }
 }
 
 /// Metadata to attach to methods, signals, and properties.
@@ -66,10 +145,39 @@ impl Annotation {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// How changes to a property are announced to clients.
+///
+/// See the `org.freedesktop.DBus.Property.EmitsChangedSignal` annotation in the [D-Bus
+/// specification](https://dbus.freedesktop.org/doc/dbus-specification.html#introspection-format).
+pub enum EmitsChangedSignal {
+    /// The new value is sent with the `PropertiesChanged` signal.
+    True,
+    /// The property is listed as invalidated in the `PropertiesChanged` signal.
+    Invalidates,
+    /// The property never changes during the lifetime of the object.
+    Const,
+    /// No signal is emitted when the property changes.
+    False,
+}
+
+impl EmitsChangedSignal {
+    /// The value of the annotation.
+    fn value(&self) -> &'static str {
+        match *self {
+            EmitsChangedSignal::True => "true",
+            EmitsChangedSignal::Invalidates => "invalidates",
+            EmitsChangedSignal::Const => "const",
+            EmitsChangedSignal::False => "false",
+        }
+    }
+}
+
 /// An error message from a method call.
 pub struct ErrorMessage {
     name: String,
     message: String,
+    args: Vec<Value>,
 }
 
 impl ErrorMessage {
@@ -83,38 +191,412 @@ impl ErrorMessage {
         ErrorMessage {
             name: name.to_string(),
             message: message.to_string(),
+            args: vec![],
         }
     }
 
+    /// Create a new error message with additional arguments.
+    ///
+    /// The arguments are sent after the message (see `add_argument`).
+    pub fn with_args<N, M>(name: N, message: M, args: Vec<Value>) -> Self
+        where N: ToString,
+              M: ToString,
+    {
+        args.into_iter().fold(Self::new(name, message), Self::add_argument)
+    }
+
+    /// An error denying the caller access to the method.
+    ///
+    /// Handlers may use `Message::sender` to decide whether the caller is allowed to call the
+    /// method. Returning this error sends an `org.freedesktop.DBus.Error.AccessDenied` reply.
+    pub fn access_denied<R>(reason: R) -> Self
+        where R: ToString,
+    {
+        Self::new("org.freedesktop.DBus.Error.AccessDenied", reason)
+    }
+
+    /// Add a machine-readable argument to the error.
+    ///
+    /// Additional arguments are sent after the human-readable message so that clients which only
+    /// read the first argument still see the description of the error.
+    pub fn add_argument(mut self, arg: Value) -> Self {
+        self.args.push(arg);
+
+        self
+    }
+
+    /// Add a numeric error code to the error.
+    pub fn with_code(self, code: i32) -> Self {
+        self.add_argument(Value::BasicValue(BasicValue::Int32(code)))
+    }
+
+    /// The name of the error.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The human-readable description of the error.
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    /// Replace the description of the error with a generic one.
+    ///
+    /// The name of the error is kept, but its description and any additional arguments are
+    /// replaced so that internal details are not sent to the caller.
+    pub fn sanitized(self) -> Self {
+        ErrorMessage::new(self.name, "an internal error occurred")
+    }
+
+    /// Additional arguments of the error.
+    pub fn args(&self) -> &[Value] {
+        &self.args
+    }
+
     fn into_message(self, msg: &Message) -> Message {
-        msg.error_message(&self.name)
-            .add_argument(&self.message)
+        let err = msg.error_message(&self.name)
+            .add_argument(&self.message);
+
+        self.args.iter().fold(err, |err, arg| err.add_argument(arg))
+    }
+}
+
+/// Errors may be converted into `org.freedesktop.DBus.Error.Failed` errors.
+///
+/// This allows handlers to use `?` on arbitrary errors. Note that the error's description is sent
+/// to the caller as-is, so errors which may contain sensitive information (e.g., file paths or
+/// credentials) should be mapped to a more appropriate error message instead.
+impl<E> From<E> for ErrorMessage
+    where E: ::std::error::Error,
+{
+    fn from(err: E) -> Self {
+        ErrorMessage::new("org.freedesktop.DBus.Error.Failed", err)
+    }
+}
+
+#[derive(Clone, Default)]
+/// State shared between the handlers of a server.
+///
+/// Values are keyed by their type, so at most one value of each type may be stored.
+pub struct SharedState {
+    values: Rc<RefCell<HashMap<TypeId, Box<Any>>>>,
+}
+
+impl SharedState {
+    /// Create a new, empty state.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Store a value in the state.
+    ///
+    /// Any existing value of the same type is replaced.
+    pub fn insert<T>(&self, value: T) -> &Self
+        where T: Any,
+    {
+        self.values.borrow_mut().insert(TypeId::of::<T>(), Box::new(value));
+
+        self
+    }
+
+    /// Access the value of the given type.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the state is already being accessed.
+    pub fn get<T>(&self) -> Option<RefMut<T>>
+        where T: Any,
+    {
+        let values = self.values.borrow_mut();
+        if !values.contains_key(&TypeId::of::<T>()) {
+            return None;
+        }
+
+        Some(RefMut::map(values, |values| {
+            values.get_mut(&TypeId::of::<T>())
+                .and_then(|value| value.downcast_mut())
+                .expect("state values are keyed by their type")
+        }))
+    }
+}
+
+/// Information about the method call being handled.
+pub struct CallContext {
+    received: Instant,
+    state: SharedState,
+    signals: RefCell<Vec<Message>>,
+}
+
+impl CallContext {
+    fn new(state: SharedState) -> Self {
+        CallContext {
+            received: Instant::now(),
+            state: state,
+            signals: RefCell::new(vec![]),
+        }
+    }
+
+    /// Queue a signal to be sent once the reply to the method call has been sent.
+    pub fn emit(&self, signal: Message) {
+        self.signals.borrow_mut().push(signal);
+    }
+
+    /// Access the value of the given type in the state shared by the handlers of the server.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the state is already being accessed (e.g., by the calling handler).
+    pub fn state<T>(&self) -> Option<RefMut<T>>
+        where T: Any,
+    {
+        self.state.get()
+    }
+
+    /// When the method call was received.
+    ///
+    /// Method calls do not carry the timeout the client is using, so handlers which perform
+    /// long-running work may use this to abort early if the client has likely given up already.
+    pub fn received(&self) -> Instant {
+        self.received
     }
 }
 
 /// The result of a method call.
 pub type MethodResult = ::std::result::Result<Vec<Value>, ErrorMessage>;
+
+/// A result indicating that the method is not supported by the object.
+///
+/// This is meant for methods which are part of an interface but which are optional for a given
+/// object. The result is an `org.freedesktop.DBus.Error.NotSupported` error.
+pub fn not_supported<M>(message: M) -> MethodResult
+    where M: ToString,
+{
+    Err(ErrorMessage::new("org.freedesktop.DBus.Error.NotSupported", message))
+}
+
 /// A holder for method closures.
-pub type MethodHandler = Box<RefCell<FnMut(&mut Message) -> MethodResult>>;
+pub type MethodHandler = Rc<RefCell<FnMut(&CallContext, &mut Message) -> MethodResult>>;
+/// The result of a method call which streams its result.
+pub type StreamResult = ::std::result::Result<ArrayStream, ErrorMessage>;
+/// A holder for method closures which stream their result.
+pub type StreamHandler = Rc<RefCell<FnMut(&CallContext, &mut Message) -> StreamResult>>;
+/// A holder for method closures which reply later.
+pub type DeferredHandler = Rc<RefCell<FnMut(&CallContext, &mut Message, DeferredReply)>>;
+
+#[derive(Clone)]
+enum MethodCallback {
+    Values(MethodHandler),
+    Stream(StreamHandler),
+    Deferred(DeferredHandler),
+}
+
+/// How errors from method handlers are treated before being sent to the caller.
+#[derive(Clone, Default)]
+struct ErrorPolicy {
+    sanitize: bool,
+    logger: Option<ErrorLogger>,
+}
+
+impl ErrorPolicy {
+    fn new(conn: &Connection) -> Self {
+        ErrorPolicy {
+            sanitize: conn.sanitizes_errors(),
+            logger: conn.error_logger(),
+        }
+    }
+
+    fn apply(&self, err: ErrorMessage) -> ErrorMessage {
+        if !self.sanitize {
+            return err;
+        }
+
+        if let Some(ref logger) = self.logger {
+            logger(&err);
+        }
+
+        err.sanitized()
+    }
+}
+
+/// A pending reply to a method call.
+///
+/// The reply refers to the serial of the method call, so it may be completed from any context
+/// which has access to the connection. Dropping the token without completing it leaves the
+/// caller waiting until its call times out.
+pub struct DeferredReply {
+    call: Message,
+    method: String,
+    out_signature: String,
+    errors: ErrorPolicy,
+}
+
+impl DeferredReply {
+    fn new(msg: &Message, out_signature: String, errors: ErrorPolicy) -> Self {
+        let mut call = Message::new(msg.message.clone());
+        // Only the headers are needed to address the reply.
+        call.message.body = vec![];
+
+        DeferredReply {
+            method: call.member().unwrap_or_else(String::new),
+            call: call,
+            out_signature: out_signature,
+            errors: errors,
+        }
+    }
+
+    /// The serial of the method call being replied to.
+    pub fn serial(&self) -> u32 {
+        self.call.serial()
+    }
+
+    /// The unique name of the caller.
+    pub fn destination(&self) -> Option<String> {
+        self.call.sender()
+    }
+
+    /// Create the reply message for the result of the method call.
+    ///
+    /// Successful results must match the declared results of the method.
+    pub fn into_message(self, res: MethodResult) -> Result<Message> {
+        match res {
+            Ok(vals) => {
+                let actual = vals.iter()
+                    .map(|v| v.get_signature().to_string())
+                    .collect::<Vec<_>>()
+                    .join("");
+                if actual != self.out_signature {
+                    bail!(ErrorKind::InvalidReplyArguments(self.method,
+                                                           self.out_signature,
+                                                           actual));
+                }
+
+                Ok(vals.iter().fold(self.call.return_message(), |msg, val| msg.add_argument(val)))
+            },
+            Err(err) => Ok(self.errors.apply(err).into_message(&self.call)),
+        }
+    }
+
+    /// Complete the method call by sending its reply.
+    ///
+    /// Nothing is sent if the caller does not expect a reply.
+    pub fn complete(self, conn: &Connection, res: MethodResult) -> Result<()> {
+        if !self.call.expects_reply() {
+            return Ok(());
+        }
+
+        conn.send(self.into_message(res)?).map(|_| ())
+    }
+}
+
+/// Bookkeeping for limiting the rate of calls to a method.
+struct RateLimit {
+    per_second: u32,
+    calls: RefCell<HashMap<String, VecDeque<Instant>>>,
+}
+
+impl RateLimit {
+    fn new(per_second: u32) -> Self {
+        RateLimit {
+            per_second: per_second,
+            calls: RefCell::new(HashMap::new()),
+        }
+    }
+
+    /// Record a call from a sender, returning whether it is within the limit.
+    fn allow(&self, sender: &str, now: Instant) -> bool {
+        let window = Duration::from_secs(1);
+        let mut calls = self.calls.borrow_mut();
+
+        // Forget about senders which have not called within the window.
+        calls.retain(|_, times| times.back().map_or(false, |&last| now - last < window));
+
+        let times = calls.entry(sender.to_string()).or_insert_with(VecDeque::new);
+        while times.front().map_or(false, |&first| now - first >= window) {
+            times.pop_front();
+        }
+
+        if times.len() < self.per_second as usize {
+            times.push_back(now);
+            true
+        } else {
+            false
+        }
+    }
+}
 
 /// A representation of a method call.
 pub struct Method {
     in_args: Vec<Argument>,
     out_args: Vec<Argument>,
-    cb: MethodHandler,
+    cb: MethodCallback,
     anns: Annotations,
+    strict_arguments: bool,
+    rate_limit: Option<RateLimit>,
 }
 
 impl Method {
     /// Create a new `Method` with the given function.
-    pub fn new<F>(cb: F) -> Self
+    pub fn new<F>(mut cb: F) -> Self
         where F: FnMut(&mut Message) -> MethodResult + 'static
     {
+        Self::with_context(move |_, m| cb(m))
+    }
+
+    /// Create a new `Method` with a function which also receives the context of the call.
+    pub fn with_context<F>(cb: F) -> Self
+        where F: FnMut(&CallContext, &mut Message) -> MethodResult + 'static
+    {
+        Self::_new(MethodCallback::Values(Rc::new(RefCell::new(cb))))
+    }
+
+    /// Create a new `Method` which returns a single array produced lazily.
+    ///
+    /// The elements of the array are marshaled directly into the reply as they are produced
+    /// rather than being collected into a `Vec` first. This is useful for methods which return
+    /// very large arrays.
+    pub fn streaming<F>(cb: F) -> Self
+        where F: FnMut(&CallContext, &mut Message) -> StreamResult + 'static
+    {
+        Self::_new(MethodCallback::Stream(Rc::new(RefCell::new(cb))))
+    }
+
+    /// Create a new `Method` which replies after its function has returned.
+    ///
+    /// This is useful for methods which wait on other events (e.g., hardware or another bus
+    /// call) before they can reply. The function receives a `DeferredReply` which is completed
+    /// once the result is available; no reply is sent when the function returns.
+    ///
+    /// ```no_run
+    /// # extern crate rust_bus;
+    /// # use rust_bus::{DeferredReply, Method};
+    /// # use std::cell::RefCell;
+    /// # use std::rc::Rc;
+    /// # fn main() {
+    /// let pending: Rc<RefCell<Vec<DeferredReply>>> = Rc::new(RefCell::new(vec![]));
+    ///
+    /// let queue = pending.clone();
+    /// let method = Method::deferred(move |_, _, reply| queue.borrow_mut().push(reply));
+    ///
+    /// // Later, once the result is ready:
+    /// // for reply in pending.borrow_mut().drain(..) {
+    /// //     reply.complete(&conn, Ok(vec![])).unwrap();
+    /// // }
+    /// # }
+    /// ```
+    pub fn deferred<F>(cb: F) -> Self
+        where F: FnMut(&CallContext, &mut Message, DeferredReply) + 'static
+    {
+        Self::_new(MethodCallback::Deferred(Rc::new(RefCell::new(cb))))
+    }
+
+    fn _new(cb: MethodCallback) -> Self {
         Method {
             in_args: vec![],
             out_args: vec![],
-            cb: Box::new(RefCell::new(cb)),
+            cb: cb,
             anns: vec![],
+            strict_arguments: false,
+            rate_limit: None,
         }
     }
 
@@ -132,12 +614,68 @@ impl Method {
         self
     }
 
+    /// Limit the number of calls each sender may make to the method per second.
+    ///
+    /// Calls over the limit are replied to with an `org.freedesktop.DBus.Error.LimitsExceeded`
+    /// error without calling the handler. Calls from peer-to-peer connections (which have no
+    /// sender) share a single limit.
+    pub fn rate_limit(mut self, per_sender_per_second: u32) -> Self {
+        self.rate_limit = Some(RateLimit::new(per_sender_per_second));
+
+        self
+    }
+
+    fn _check_rate_limit(&self, ctx: &CallContext, msg: &Message)
+                         -> ::std::result::Result<(), ErrorMessage> {
+        match self.rate_limit {
+            Some(ref limit) => {
+                let sender = msg.sender().unwrap_or_else(String::new);
+
+                if limit.allow(&sender, ctx.received()) {
+                    Ok(())
+                } else {
+                    Err(ErrorMessage::new("org.freedesktop.DBus.Error.LimitsExceeded",
+                                          &format!("too many calls from {}", sender)))
+                }
+            },
+            None => Ok(()),
+        }
+    }
+
+    /// The signature of the arguments of the method.
+    pub fn in_signature(&self) -> String {
+        Interfaces::_signature(&self.in_args)
+    }
+
+    /// The signature of the results of the method.
+    pub fn out_signature(&self) -> String {
+        Interfaces::_signature(&self.out_args)
+    }
+
     /// Add an annotation to the method.
     pub fn annotate(mut self, ann: Annotation) -> Self {
         self.anns.push(ann);
 
         self
     }
+
+    /// Mark the method as deprecated.
+    ///
+    /// This adds an `org.freedesktop.DBus.Deprecated` annotation to the method.
+    pub fn deprecated(self) -> Self {
+        self.annotate(Annotation::new("org.freedesktop.DBus.Deprecated", "true"))
+    }
+
+    /// Reject calls which pass more arguments than the method declares.
+    ///
+    /// By default, the declared arguments must be a prefix of the passed arguments and any
+    /// trailing arguments are ignored. With this set, calls must match the declared arguments
+    /// exactly and are rejected with `InvalidArgs` otherwise.
+    pub fn strict_arguments(mut self) -> Self {
+        self.strict_arguments = true;
+
+        self
+    }
 }
 
 /// The result of a property query.
@@ -165,6 +703,153 @@ pub trait PropertyReadWriteHandler {
     fn set(&self, &Value) -> PropertySetResult;
 }
 
+#[derive(Clone)]
+/// A read-only property handler which caches the value of the property.
+///
+/// The getter is only called when the value is not cached. Clones share the same cache, so a
+/// clone may be kept to invalidate the value once the underlying data changes.
+pub struct CachedProperty {
+    getter: Rc<Fn() -> PropertyGetResult>,
+    cache: Rc<RefCell<Option<Value>>>,
+}
+
+impl CachedProperty {
+    /// Create a new cached property with the given getter.
+    pub fn new<F>(getter: F) -> Self
+        where F: Fn() -> PropertyGetResult + 'static,
+    {
+        CachedProperty {
+            getter: Rc::new(getter),
+            cache: Rc::new(RefCell::new(None)),
+        }
+    }
+
+    /// Discard the cached value so that it is computed on the next read.
+    pub fn invalidate(&self) {
+        *self.cache.borrow_mut() = None;
+    }
+
+    /// Discard the cached value and create a `PropertiesChanged` signal announcing it.
+    ///
+    /// The property is listed as invalidated so that the value is not computed until a client
+    /// asks for it.
+    pub fn invalidate_signal(&self, path: &str, iface: &str, name: &str) -> Message {
+        self.invalidate();
+
+        properties_changed(path,
+                           iface,
+                           Dictionary::new_with_sig(HashMap::new(), "a{sv}".to_string()),
+                           &[name])
+    }
+}
+
+impl PropertyReadHandler for CachedProperty {
+    fn get(&self) -> PropertyGetResult {
+        if let Some(ref value) = *self.cache.borrow() {
+            return Ok(value.clone());
+        }
+
+        // Errors are not cached.
+        let value = (self.getter)()?;
+        *self.cache.borrow_mut() = Some(value.clone());
+
+        Ok(value)
+    }
+}
+
+/// A read-only property handler which produces values of a Rust type.
+pub struct ReadProperty<T> {
+    getter: Box<Fn() -> T>,
+}
+
+impl<T> ReadProperty<T>
+    where T: IntoValue,
+{
+    /// Create a new property handler with the given getter.
+    pub fn new<G>(getter: G) -> Self
+        where G: Fn() -> T + 'static,
+    {
+        ReadProperty {
+            getter: Box::new(getter),
+        }
+    }
+}
+
+impl<T> PropertyReadHandler for ReadProperty<T>
+    where T: IntoValue,
+{
+    fn get(&self) -> PropertyGetResult {
+        Ok((self.getter)().into_value())
+    }
+}
+
+/// A read-write property handler which works with values of a Rust type.
+///
+/// Values which are set are checked against the type before the setter is called.
+pub struct ReadWriteProperty<T> {
+    getter: Box<Fn() -> T>,
+    setter: Box<Fn(T)>,
+}
+
+impl<T> ReadWriteProperty<T>
+    where T: IntoValue + FromValue,
+{
+    /// Create a new property handler with the given getter and setter.
+    pub fn new<G, S>(getter: G, setter: S) -> Self
+        where G: Fn() -> T + 'static,
+              S: Fn(T) + 'static,
+    {
+        ReadWriteProperty {
+            getter: Box::new(getter),
+            setter: Box::new(setter),
+        }
+    }
+}
+
+impl<T> PropertyReadWriteHandler for ReadWriteProperty<T>
+    where T: IntoValue + FromValue,
+{
+    fn get(&self) -> PropertyGetResult {
+        Ok((self.getter)().into_value())
+    }
+
+    fn set(&self, value: &Value) -> PropertySetResult {
+        (self.setter)(T::from_value(value).ok_or_else(Arguments::invalid_arguments)?);
+
+        Ok(())
+    }
+}
+
+/// A write-only property handler which accepts values of a Rust type.
+///
+/// Values which are set are checked against the type before the setter is called.
+pub struct WriteProperty<T> {
+    setter: Box<Fn(T)>,
+}
+
+impl<T> WriteProperty<T>
+    where T: FromValue,
+{
+    /// Create a new property handler with the given setter.
+    pub fn new<S>(setter: S) -> Self
+        where S: Fn(T) + 'static,
+    {
+        WriteProperty {
+            setter: Box::new(setter),
+        }
+    }
+}
+
+impl<T> PropertyWriteHandler for WriteProperty<T>
+    where T: FromValue,
+{
+    fn set(&self, value: &Value) -> PropertySetResult {
+        (self.setter)(T::from_value(value).ok_or_else(Arguments::invalid_arguments)?);
+
+        Ok(())
+    }
+}
+
 enum PropertyAccess {
     RO(Box<PropertyReadHandler>),
     RW(Box<PropertyReadWriteHandler>),
@@ -202,16 +887,91 @@ impl Property {
         Property::new(sig, PropertyAccess::WO(access))
     }
 
-    /// Add an annotation to the property.
-    pub fn annotate(mut self, ann: Annotation) -> Self {
+    fn _new_typed<T>(sig: Signature, access: PropertyAccess) -> Result<Self>
+        where T: HasSignature,
+    {
+        if !T::matches_signature(&sig) {
+            bail!(ErrorKind::InvalidSignature(sig.0,
+                                              format!("does not match the handler's type '{}'",
+                                                      T::signature().0)));
+        }
+
+        Ok(Property::new(sig, access))
+    }
+
+    /// Create a new read-only property whose handler produces values of type `T`.
+    ///
+    /// The signature must match the signature of `T`.
+    pub fn new_ro_typed<T>(sig: Signature, access: ReadProperty<T>) -> Result<Self>
+        where T: HasSignature + IntoValue + 'static,
+    {
+        Self::_new_typed::<T>(sig, PropertyAccess::RO(Box::new(access)))
+    }
+
+    /// Create a new read-write property whose handler works with values of type `T`.
+    ///
+    /// The signature must match the signature of `T`.
+    pub fn new_rw_typed<T>(sig: Signature, access: ReadWriteProperty<T>) -> Result<Self>
+        where T: HasSignature + IntoValue + FromValue + 'static,
+    {
+        Self::_new_typed::<T>(sig, PropertyAccess::RW(Box::new(access)))
+    }
+
+    /// Create a new write-only property whose handler accepts values of type `T`.
+    ///
+    /// The signature must match the signature of `T`.
+    pub fn new_wo_typed<T>(sig: Signature, access: WriteProperty<T>) -> Result<Self>
+        where T: HasSignature + FromValue + 'static,
+    {
+        Self::_new_typed::<T>(sig, PropertyAccess::WO(Box::new(access)))
+    }
+
+    /// Add an annotation to the property.
+    pub fn annotate(mut self, ann: Annotation) -> Self {
         self.anns.push(ann);
 
         self
     }
 
+    /// Mark the property as deprecated.
+    ///
+    /// This adds an `org.freedesktop.DBus.Deprecated` annotation to the property.
+    pub fn deprecated(self) -> Self {
+        self.annotate(Annotation::new("org.freedesktop.DBus.Deprecated", "true"))
+    }
+
+    /// Announce how changes to the property are signaled.
+    ///
+    /// This adds an `org.freedesktop.DBus.Property.EmitsChangedSignal` annotation to the
+    /// property.
+    pub fn emits_changed(self, mode: EmitsChangedSignal) -> Self {
+        self.annotate(Annotation::new("org.freedesktop.DBus.Property.EmitsChangedSignal",
+                                      mode.value()))
+    }
+
     fn _check_signature(&self, value: &Value) -> bool {
         self.signature.0 == value.get_signature()
     }
+
+    /// How changes to the property are announced.
+    ///
+    /// This is the value of the `org.freedesktop.DBus.Property.EmitsChangedSignal` annotation
+    /// (`true` if it is not present).
+    fn _emits_changed_signal(&self) -> &str {
+        self.anns
+            .iter()
+            .rev()
+            .find(|ann| ann.name == "org.freedesktop.DBus.Property.EmitsChangedSignal")
+            .map_or("true", |ann| ann.value.as_str())
+    }
+
+    fn _get(&self) -> Option<PropertyGetResult> {
+        match self.access {
+            PropertyAccess::RO(ref ro) => Some(ro.get()),
+            PropertyAccess::RW(ref rw) => Some(rw.get()),
+            PropertyAccess::WO(_) => None,
+        }
+    }
 }
 
 #[derive(Default)]
@@ -243,6 +1003,13 @@ impl Signal {
 
         self
     }
+
+    /// Mark the signal as deprecated.
+    ///
+    /// This adds an `org.freedesktop.DBus.Deprecated` annotation to the signal.
+    pub fn deprecated(self) -> Self {
+        self.annotate(Annotation::new("org.freedesktop.DBus.Deprecated", "true"))
+    }
 }
 
 #[derive(Default)]
@@ -306,6 +1073,89 @@ impl Interface {
         self
     }
 
+    /// Merge the members of another interface into this one.
+    ///
+    /// This may be used to compose interfaces from reusable fragments. It is an error for both
+    /// interfaces to have a method, property, or signal with the same name.
+    pub fn merge(mut self, other: Interface) -> Result<Self> {
+        merge_members(&mut self.methods, other.methods)?;
+        merge_members(&mut self.properties, other.properties)?;
+        merge_members(&mut self.signals, other.signals)?;
+        self.anns.extend(other.anns);
+
+        Ok(self)
+    }
+
+    /// Check the signatures of the arguments and properties of the interface.
+    ///
+    /// Arguments and properties are created infallibly, so their signatures are checked when the
+    /// interface is added to a set of interfaces.
+    fn _check_signatures(&self) -> Result<()> {
+        let args = self.methods
+            .values()
+            .flat_map(|method| method.in_args.iter().chain(method.out_args.iter()))
+            .chain(self.signals.values().flat_map(|signal| signal.args.iter()));
+        for arg in args {
+            signature::parse_single(&arg.signature)?;
+        }
+
+        for property in self.properties.values() {
+            signature::parse_single(&property.signature.0)?;
+        }
+
+        Ok(())
+    }
+
+    fn _validate(&self, name: &str, errors: &mut Vec<ValidationError>) {
+        if !names::is_valid_interface_name(name) {
+            errors.push(ValidationError::new(name, None, "invalid interface name"));
+        }
+
+        let check_args = |member: &str, args: &[Argument], errors: &mut Vec<ValidationError>| {
+            for arg in args {
+                if let Err(err) = signature::parse_single(&arg.signature) {
+                    errors.push(ValidationError::new(name,
+                                                     Some(member),
+                                                     format!("argument '{}': {}", arg.name, err)));
+                }
+            }
+        };
+
+        let mut seen = Vec::new();
+        let members = self.methods
+            .keys()
+            .chain(self.properties.keys())
+            .chain(self.signals.keys());
+        for member in members {
+            if !names::is_valid_member_name(member) {
+                errors.push(ValidationError::new(name,
+                                                 Some(member.as_str()),
+                                                 "invalid member name"));
+            }
+            if seen.contains(&member) {
+                errors.push(ValidationError::new(name,
+                                                 Some(member.as_str()),
+                                                 "duplicate member name"));
+            }
+            seen.push(member);
+        }
+
+        for (member, method) in &self.methods {
+            check_args(member, &method.in_args, errors);
+            check_args(member, &method.out_args, errors);
+        }
+
+        for (member, property) in &self.properties {
+            if let Err(err) = signature::parse_single(&property.signature.0) {
+                errors.push(ValidationError::new(name, Some(member.as_str()), err));
+            }
+        }
+
+        for (member, signal) in &self.signals {
+            check_args(member, &signal.args, errors);
+        }
+    }
+
     fn _require_property(&self, name: &str) -> ::std::result::Result<&Property, ErrorMessage> {
         self.properties.get(name).ok_or_else(|| {
             ErrorMessage::new("org.freedesktop.DBus.Error.UnknownProperty",
@@ -320,20 +1170,18 @@ impl Interface {
                 PropertyAccess::RO(ref ro) => ro.get(),
                 PropertyAccess::RW(ref rw) => rw.get(),
                 PropertyAccess::WO(_) => {
-                    Err(ErrorMessage {
-                        name: "org.freedesktop.DBus.Error.Failed".to_string(),
-                        message: format!("property is write-only: {}", name),
-                    })
+                    Err(ErrorMessage::new("org.freedesktop.DBus.Error.Failed",
+                                          &format!("property is write-only: {}", name)))
                 },
             };
 
             if let Ok(value) = res.as_ref() {
-                if prop._check_signature(value) {
+                if !prop._check_signature(value) {
                     panic!("invalid property return type for: \
                             property: '{}' expected: '{}' actual: '{}'",
                            name,
-                           value.get_signature(),
-                           prop.signature.0)
+                           prop.signature.0,
+                           value.get_signature())
                 }
             }
 
@@ -342,9 +1190,13 @@ impl Interface {
     }
 
     /// Set a property value.
+    ///
+    /// Values which do not match the signature of the property are rejected with an
+    /// `org.freedesktop.DBus.Error.InvalidArgs` error. The value is not unwrapped from a variant
+    /// since properties may themselves be variants.
     pub fn set_property_value(&self, name: &str, value: &Value) -> MethodResult {
         self._require_property(name).and_then(|prop| {
-            if prop._check_signature(value) {
+            if !prop._check_signature(value) {
                 return Err(Arguments::invalid_arguments());
             }
 
@@ -359,23 +1211,353 @@ impl Interface {
         })
     }
 
+    /// A `PropertiesChanged` signal carrying the current value of a property.
+    ///
+    /// The value is read using the property's getter.
+    pub fn property_changed_signal(&self, path: &str, iface: &str, name: &str) -> Result<Message> {
+        let value = self._read_property(name)?;
+
+        let mut changed = HashMap::new();
+        changed.insert(BasicValue::String(name.to_string()), variant(value));
+
+        Ok(properties_changed(path,
+                              iface,
+                              Dictionary::new_with_sig(changed, "a{sv}".to_string()),
+                              &[]))
+    }
+
+    /// Emit a `PropertiesChanged` signal carrying the current value of a property.
+    ///
+    /// This is meant to be called after a property has been changed by the service.
+    pub fn emit_property_changed_value(&self, conn: &Connection, path: &str, iface: &str,
+                                       name: &str)
+                                       -> Result<()> {
+        conn.send(self.property_changed_signal(path, iface, name)?)?;
+
+        Ok(())
+    }
+
+    /// The `PropertiesChanged` signal to emit after a property has been set by a client.
+    ///
+    /// The signal depends on the property's `org.freedesktop.DBus.Property.EmitsChangedSignal`
+    /// annotation. Properties which cannot be read are announced as invalidated.
+    fn _set_changed_signal(&self, path: &str, iface: &str, name: &str) -> Option<Message> {
+        let prop = match self.properties.get(name) {
+            Some(prop) => prop,
+            None => return None,
+        };
+
+        let emit_value = match prop._emits_changed_signal() {
+            "true" => true,
+            "invalidates" => false,
+            _ => return None,
+        };
+
+        let mut changed = HashMap::new();
+        let invalidated = match prop._get() {
+            Some(Ok(value)) if emit_value => {
+                changed.insert(BasicValue::String(name.to_string()), variant(value));
+                vec![]
+            },
+            _ => vec![name],
+        };
+
+        Some(properties_changed(path,
+                                iface,
+                                Dictionary::new_with_sig(changed, "a{sv}".to_string()),
+                                &invalidated))
+    }
+
+    /// Queue a `PropertiesChanged` signal carrying the current value of a property.
+    ///
+    /// The signal is emitted by the debouncer once its window has passed.
+    pub fn debounce_property_changed(&self, debouncer: &PropertyDebouncer, path: &str,
+                                     iface: &str, name: &str)
+                                     -> Result<()> {
+        debouncer.property_changed(path, iface, name, self._read_property(name)?);
+
+        Ok(())
+    }
+
+    fn _read_property(&self, name: &str) -> Result<Value> {
+        let read = self._require_property(name).and_then(|prop| {
+            prop._get().unwrap_or_else(|| {
+                Err(ErrorMessage::new("org.freedesktop.DBus.Error.Failed",
+                                      &format!("property is write-only: {}", name)))
+            })
+        });
+
+        match read {
+            Ok(value) => Ok(value),
+            Err(err) => bail!(ErrorKind::PropertyRead(name.to_string(), err.message)),
+        }
+    }
+
+    /// A message for a signal declared by the interface.
+    ///
+    /// The arguments must match the declared arguments of the signal.
+    pub fn signal_message(&self, path: &str, iface: &str, name: &str, args: &[Value])
+                          -> Result<Message> {
+        let signal = match self.signals.get(name) {
+            Some(signal) => signal,
+            None => bail!(ErrorKind::NoSuchSignal(name.to_string())),
+        };
+
+        let expected = Interfaces::_signature(&signal.args);
+        let actual = args.iter()
+            .map(|arg| arg.get_signature())
+            .collect::<Vec<_>>()
+            .join("");
+        if expected != actual {
+            bail!(ErrorKind::InvalidSignalArguments(name.to_string(), expected, actual));
+        }
+
+        Ok(args.iter()
+            .fold(Message::new_signal(path, iface, name), |msg, arg| msg.add_argument(arg)))
+    }
+
+    /// Emit a signal declared by the interface.
+    ///
+    /// The arguments must match the declared arguments of the signal.
+    pub fn emit_signal(&self, conn: &Connection, path: &str, iface: &str, name: &str,
+                       args: &[Value])
+                       -> Result<()> {
+        conn.send(self.signal_message(path, iface, name, args)?)?;
+
+        Ok(())
+    }
+
     /// Get a map of all (readable) property values.
+    ///
+    /// The values are wrapped in variants.
     pub fn get_property_map(&self) -> Dictionary {
-        Dictionary::new(self.properties
-            .iter()
-            .map(|(k, v)| {
-                match v.access {
-                        PropertyAccess::RO(ref ro) => ro.get().ok(),
-                        PropertyAccess::RW(ref rw) => rw.get().ok(),
-                        PropertyAccess::WO(_) => None,
-                    }
-                    .map(|v| (BasicValue::String(k.clone()), v))
+        Dictionary::new_with_sig(self.properties
+                                     .iter()
+                                     .map(|(k, v)| {
+                                         v._get()
+                                             .and_then(|res| res.ok())
+                                             .map(|v| (BasicValue::String(k.clone()), variant(v)))
+                                     })
+                                     .filter_map(|a| a)
+                                     .collect(),
+                                 "a{sv}".to_string())
+    }
+}
+
+fn properties_changed(path: &str, iface: &str, changed: Dictionary, invalidated: &[&str])
+                      -> Message {
+    let invalidated = invalidated.iter()
+        .map(|name| Value::BasicValue(BasicValue::String(name.to_string())))
+        .collect();
+
+    Message::new_signal(path, "org.freedesktop.DBus.Properties", "PropertiesChanged")
+        .add_argument(&iface)
+        .add_argument(&Value::Dictionary(changed))
+        .add_argument(&Value::Array(Array::new_with_sig(invalidated, "as".to_string())))
+}
+
+/// A set of property changes on the interfaces of a single object.
+///
+/// One `PropertiesChanged` signal is emitted for each interface with changes. All of the signals
+/// are sent together so that clients see the changes without other messages in between.
+pub struct PropertyChanges {
+    path: String,
+    interfaces: Map<(Map<Value>, Vec<String>)>,
+}
+
+impl PropertyChanges {
+    /// Create a new set of changes for the object at the given path.
+    pub fn new<P>(path: P) -> Self
+        where P: ToString,
+    {
+        PropertyChanges {
+            path: path.to_string(),
+            interfaces: Map::new(),
+        }
+    }
+
+    /// Record the new value of a property.
+    pub fn changed<I, N>(mut self, iface: I, name: N, value: Value) -> Self
+        where I: ToString,
+              N: ToString,
+    {
+        self.interfaces
+            .entry(iface.to_string())
+            .or_insert_with(Default::default)
+            .0
+            .insert(name.to_string(), value);
+
+        self
+    }
+
+    /// Record that a property has changed without sending its new value.
+    pub fn invalidated<I, N>(mut self, iface: I, name: N) -> Self
+        where I: ToString,
+              N: ToString,
+    {
+        self.interfaces
+            .entry(iface.to_string())
+            .or_insert_with(Default::default)
+            .1
+            .push(name.to_string());
+
+        self
+    }
+
+    /// The `PropertiesChanged` signals for the changes.
+    pub fn messages(self) -> Vec<Message> {
+        let path = self.path;
+
+        self.interfaces
+            .into_iter()
+            .map(|(iface, (values, invalidated))| {
+                let changed = values.into_iter()
+                    .map(|(name, value)| (BasicValue::String(name), variant(value)))
+                    .collect();
+                let invalidated = invalidated.iter().map(String::as_str).collect::<Vec<_>>();
+
+                properties_changed(&path,
+                                   &iface,
+                                   Dictionary::new_with_sig(changed, "a{sv}".to_string()),
+                                   &invalidated)
+            })
+            .collect()
+    }
+
+    /// Emit the `PropertiesChanged` signals for the changes.
+    pub fn emit(self, conn: &Connection) -> Result<()> {
+        for msg in self.messages() {
+            conn.send(msg)?;
+        }
+
+        Ok(())
+    }
+}
+
+type PendingChanges = BTreeMap<(String, String), (Instant, Map<Value>)>;
+
+/// Coalesces property changes into a single `PropertiesChanged` signal per interface.
+///
+/// Changes to the properties of an interface on an object are collected until the window has
+/// passed since the first of them. A single signal carrying the latest value of each changed
+/// property is then emitted.
+pub struct PropertyDebouncer {
+    window: Duration,
+    pending: RefCell<PendingChanges>,
+}
+
+impl PropertyDebouncer {
+    /// Create a new debouncer which coalesces changes within the given window.
+    pub fn new(window: Duration) -> Self {
+        PropertyDebouncer {
+            window: window,
+            pending: RefCell::new(PendingChanges::new()),
+        }
+    }
+
+    /// The window within which changes are coalesced.
+    pub fn window(&self) -> Duration {
+        self.window
+    }
+
+    /// Record a new value for a property.
+    ///
+    /// Any pending value for the same property is replaced.
+    pub fn property_changed(&self, path: &str, iface: &str, name: &str, value: Value) {
+        let now = Instant::now();
+
+        self.pending
+            .borrow_mut()
+            .entry((path.to_string(), iface.to_string()))
+            .or_insert_with(|| (now, Map::new()))
+            .1
+            .insert(name.to_string(), value);
+    }
+
+    /// Whether there are changes which have not been emitted yet.
+    pub fn is_pending(&self) -> bool {
+        !self.pending.borrow().is_empty()
+    }
+
+    /// When the window of the oldest pending change passes.
+    ///
+    /// Returns `None` if no changes are pending.
+    pub fn next_due(&self) -> Option<Instant> {
+        self.pending
+            .borrow()
+            .values()
+            .map(|&(first, _)| first + self.window)
+            .min()
+    }
+
+    /// Take the signals for changes whose window has passed at the given time.
+    fn _due(&self, now: Instant) -> Vec<Message> {
+        let mut pending = self.pending.borrow_mut();
+        let due = pending.iter()
+            .filter(|&(_, &(first, _))| now.duration_since(first) >= self.window)
+            .map(|(key, _)| key.clone())
+            .collect::<Vec<_>>();
+
+        due.into_iter()
+            .filter_map(|key| pending.remove(&key).map(|(_, values)| (key, values)))
+            .map(|((path, iface), values)| {
+                let changed = values.into_iter()
+                    .map(|(name, value)| (BasicValue::String(name), variant(value)))
+                    .collect();
+
+                properties_changed(&path,
+                                   &iface,
+                                   Dictionary::new_with_sig(changed, "a{sv}".to_string()),
+                                   &[])
             })
-            .filter_map(|a| a)
-            .collect())
+            .collect()
+    }
+
+    /// Emit the signals for changes whose window has passed.
+    pub fn emit_due(&self, conn: &Connection) -> Result<()> {
+        for msg in self._due(Instant::now()) {
+            conn.send(msg)?;
+        }
+
+        Ok(())
     }
 }
 
+fn interfaces_and_properties<'a, I>(ifaces: I) -> Dictionary
+    where I: Iterator<Item = (&'a String, &'a Interface)>,
+{
+    Dictionary::new_with_sig(ifaces.map(|(k, v)| {
+                                     (BasicValue::String(k.clone()),
+                                      Value::Dictionary(v.get_property_map()))
+                                 })
+                                 .collect(),
+                             "a{sa{sv}}".to_string())
+}
+
+/// A handler which intercepts calls to the `org.freedesktop.DBus.Properties` interface.
+///
+/// The handler is given the name of the method being called and a function which performs the
+/// default behavior for the call.
+pub type PropertiesHandler = Rc<RefCell<FnMut(&CallContext,
+                                              &str,
+                                              &mut Message,
+                                              &mut FnMut(&mut Message) -> MethodResult)
+                                              -> MethodResult>>;
+
+/// The standard interfaces which are added to every object.
+const RESERVED_INTERFACES: &'static [&'static str] = &[
+    "org.freedesktop.DBus.Introspectable",
+    "org.freedesktop.DBus.Peer",
+    "org.freedesktop.DBus.Properties",
+];
+
+fn is_reserved_interface(name: &str) -> bool {
+    RESERVED_INTERFACES.contains(&name)
+}
+
+/// Generated introspection data along with the children it was generated for.
+type IntrospectionCache = Rc<RefCell<Option<(Vec<String>, String)>>>;
+
 type InterfaceMap = Rc<RefCell<Map<Interface>>>;
 type InterfaceMapRef = Weak<RefCell<Map<Interface>>>;
 /// A list of child objects for an object.
@@ -384,20 +1566,89 @@ type ChildrenListRef = Weak<RefCell<Vec<String>>>;
 
 fn require_interface<'a>(map: &'a Ref<'a, Map<Interface>>, name: &str)
                          -> ::std::result::Result<&'a Interface, ErrorMessage> {
-    map.get(name).ok_or(ErrorMessage {
-        name: "org.freedesktop.DBus.Error.UnknownInterface".to_string(),
-        message: format!("unknown interface: {}", name),
+    map.get(name).ok_or_else(|| {
+        ErrorMessage::new("org.freedesktop.DBus.Error.UnknownInterface",
+                          &format!("unknown interface: {}", name))
     })
 }
 
+#[derive(Clone, Default)]
+/// The objects managed by an `org.freedesktop.DBus.ObjectManager` interface.
+///
+/// Objects are tracked by their path and are not kept alive by the manager.
+pub struct ManagedObjects {
+    objects: Rc<RefCell<Map<InterfaceMapRef>>>,
+}
+
+impl ManagedObjects {
+    /// Create a new, empty set of managed objects.
+    pub fn new() -> Self {
+        ManagedObjects {
+            objects: Rc::new(RefCell::new(Map::new())),
+        }
+    }
+
+    /// Manage the object at the given path.
+    pub fn add<P>(&self, path: P, ifaces: &Interfaces)
+        where P: ToString,
+    {
+        self.objects.borrow_mut().insert(path.to_string(), Rc::downgrade(&ifaces.map));
+    }
+
+    /// Stop managing the object at the given path.
+    ///
+    /// Returns `false` if the path was not managed.
+    pub fn remove<P>(&self, path: P) -> bool
+        where P: AsRef<str>,
+    {
+        self.objects.borrow_mut().remove(path.as_ref()).is_some()
+    }
+
+    /// The paths of the managed objects.
+    pub fn paths(&self) -> Vec<String> {
+        self.objects.borrow().keys().cloned().collect()
+    }
+
+    fn managed_objects(&self) -> Dictionary {
+        Dictionary::new_with_sig(self.objects
+                                     .borrow()
+                                     .iter()
+                                     .filter_map(|(path, map)| {
+                                         map.upgrade().map(|map| {
+                                             let map = map.borrow();
+                                             (BasicValue::ObjectPath(Path(path.clone())),
+                                              Value::Dictionary(interfaces_and_properties(map.iter())))
+                                         })
+                                     })
+                                     .collect(),
+                                 "a{oa{sa{sv}}}".to_string())
+    }
+}
+
 /// A builder for a set of interfaces that an object implements.
 pub struct InterfacesBuilder {
     map: InterfaceMap,
+    machine_id: Option<String>,
+    indent: String,
+    properties_handler: Option<PropertiesHandler>,
+    managed_objects: Option<ManagedObjects>,
+    state: SharedState,
 }
 
 /// A set of interfaces that an object implements.
 pub struct Interfaces {
     map: InterfaceMap,
+    indent: String,
+    introspection: IntrospectionCache,
+    state: SharedState,
+}
+
+/// The machine ID of the host.
+///
+/// This is the ID reported by the `org.freedesktop.DBus.Peer` interface unless another one is
+/// set using `InterfacesBuilder::with_machine_id`.
+pub fn machine_id() -> String {
+    format!("{}", MachineId::get())
 }
 
 struct PeerInterface;
@@ -407,16 +1658,16 @@ impl PeerInterface {
         Ok(vec![])
     }
 
-    fn get_machine_id() -> MethodResult {
-        let mid = format!("{}", MachineId::get());
+    fn get_machine_id(machine_id: &Option<String>) -> MethodResult {
+        let mid = machine_id.clone().unwrap_or_else(self::machine_id);
         Ok(vec![Value::BasicValue(BasicValue::String(mid))])
     }
 
-    pub fn new() -> Interface {
+    pub fn new(machine_id: Option<String>) -> Interface {
         Interface::new()
             .add_method("Ping", Method::new(|_| Self::ping()))
             .add_method("GetMachineId",
-                        Method::new(|_| Self::get_machine_id())
+                        Method::new(move |_| Self::get_machine_id(&machine_id))
                             .add_result(Argument::new("machine_uuid", "s")))
     }
 }
@@ -435,17 +1686,27 @@ impl PropertyInterface {
         require_interface(smap_ref, iface).and_then(|iface| iface.get_property_value(property))
     }
 
-    fn set_property(map: InterfaceMapRef, m: &mut Message) -> MethodResult {
+    fn set_property(map: InterfaceMapRef, ctx: &CallContext, m: &mut Message) -> MethodResult {
         let values = Arguments::new(m)?;
-        let iface = values.extract_string(0)?;
+        let iface_name = values.extract_string(0)?;
         let property = values.extract_string(1)?;
+        // Handlers expect the value itself rather than the variant it is sent within.
         let value = values.extract(2)?;
+        let value = value.unwrap_variant().unwrap_or(value);
 
         let smap = map.upgrade().expect("get_property: interface map no longer exists?");
         let smap_ref = &smap.borrow();
 
-        require_interface(smap_ref, iface)
-            .and_then(|iface| iface.set_property_value(property, value))
+        require_interface(smap_ref, iface_name).and_then(|iface| {
+            let res = iface.set_property_value(property, value)?;
+
+            let path = m.path().unwrap_or_else(String::new);
+            if let Some(signal) = iface._set_changed_signal(&path, iface_name, property) {
+                ctx.emit(signal);
+            }
+
+            Ok(res)
+        })
     }
 
     fn get_all_properties(map: InterfaceMapRef, m: &mut Message) -> MethodResult {
@@ -459,142 +1720,195 @@ impl PropertyInterface {
             .map(|iface| vec![Value::Dictionary(iface.get_property_map())])
     }
 
-    pub fn new(map: InterfaceMapRef) -> Interface {
+    fn _call<F>(handler: &Option<PropertiesHandler>, ctx: &CallContext, method: &str,
+                m: &mut Message, mut default: F)
+                -> MethodResult
+        where F: FnMut(&mut Message) -> MethodResult,
+    {
+        match *handler {
+            Some(ref handler) => handler.borrow_mut().deref_mut()(ctx, method, m, &mut default),
+            None => default(m),
+        }
+    }
+
+    pub fn new(map: InterfaceMapRef, handler: Option<PropertiesHandler>) -> Interface {
         let get_map = map.clone();
         let set_map = map.clone();
         let get_all_map = map.clone();
+        let get_handler = handler.clone();
+        let set_handler = handler.clone();
+        let get_all_handler = handler;
 
         Interface::new()
             .add_method("Get",
-                        Method::new(move |m| Self::get_property(get_map.clone(), m))
+                        Method::with_context(move |ctx, m| {
+                                Self::_call(&get_handler, ctx, "Get", m, |m| {
+                                    Self::get_property(get_map.clone(), m)
+                                })
+                            })
                             .add_argument(Argument::new("interface_name", "s"))
                             .add_argument(Argument::new("property_name", "s"))
                             .add_result(Argument::new("value", "v")))
             .add_method("Set",
-                        Method::new(move |m| Self::set_property(set_map.clone(), m))
+                        Method::with_context(move |ctx, m| {
+                                Self::_call(&set_handler, ctx, "Set", m, |m| {
+                                    Self::set_property(set_map.clone(), ctx, m)
+                                })
+                            })
                             .add_argument(Argument::new("interface_name", "s"))
                             .add_argument(Argument::new("property_name", "s"))
-                            .add_result(Argument::new("value", "v")))
+                            .add_argument(Argument::new("value", "v")))
             .add_method("GetAll",
-                        Method::new(move |m| Self::get_all_properties(get_all_map.clone(), m))
+                        Method::with_context(move |ctx, m| {
+                                Self::_call(&get_all_handler, ctx, "GetAll", m, |m| {
+                                    Self::get_all_properties(get_all_map.clone(), m)
+                                })
+                            })
                             .add_argument(Argument::new("interface_name", "s"))
-                            .add_result(Argument::new("props", "{sv}")))
+                            .add_result(Argument::new("props", "a{sv}")))
+    }
+}
+
+struct ObjectManagerInterface;
+
+impl ObjectManagerInterface {
+    fn get_managed_objects(objects: &ManagedObjects) -> MethodResult {
+        Ok(vec![Value::Dictionary(objects.managed_objects())])
+    }
+
+    pub fn new(objects: ManagedObjects) -> Interface {
+        Interface::new()
+            .add_method("GetManagedObjects",
+                        Method::new(move |_| Self::get_managed_objects(&objects))
+                            .add_result(Argument::new("objpath_interfaces_and_properties",
+                                                      "a{oa{sa{sv}}}")))
+            .add_signal::<&str>("InterfacesAdded",
+                                Signal::new()
+                                    .add_argument(Argument::new("object_path", "o"))
+                                    .add_argument(Argument::new("interfaces_and_properties",
+                                                                "a{sa{sv}}")))
+            .add_signal::<&str>("InterfacesRemoved",
+                                Signal::new()
+                                    .add_argument(Argument::new("object_path", "o"))
+                                    .add_argument(Argument::new("interfaces", "as")))
     }
 }
 
 struct IntrospectableInterface;
 
 impl IntrospectableInterface {
-    fn introspect(map: InterfaceMapRef, children: ChildrenListRef, _: &mut Message) -> MethodResult {
+    fn introspect(map: InterfaceMapRef, children: ChildrenListRef, indent: &str,
+                  cache: &IntrospectionCache, _: &mut Message)
+                  -> MethodResult {
         let smap = map.upgrade().unwrap();
         let schildren = children.upgrade().unwrap();
 
-        let xml = format!(concat!(
-            r#"<!DOCTYPE node PUBLIC "-//freedesktop//DTD D-BUS Object Introspection 1.0//EN"\n"#,
-            r#" "http://www.freedesktop.org/standards/dbus/1.0/introspect.dtd">\n"#,
-            r#"<!-- rust-bus {} -->"#,
-            r#"<node>\n"#,
-            r#"{}"#, // interface
-            r#"{}"#, // children
-            r#"</node>\n"#),
-                          env!("CARGO_PKG_VERSION"),
-                          Self::_to_string_map(&*smap.borrow(),
-                                               |k, v| Self::_introspect_interface(" ", k, v)),
-                          schildren.borrow().iter().fold(String::new(), |p, name| {
-                              format!(r#"{} <node name="{}" />"#, p, name)
-                          }));
-        Ok(vec![Value::BasicValue(BasicValue::String(xml))])
-    }
+        if let Some((ref cached_children, ref xml)) = *cache.borrow() {
+            if *cached_children == *schildren.borrow() {
+                return Ok(vec![Value::BasicValue(BasicValue::String(xml.clone()))]);
+            }
+        }
 
-    fn _to_string_map<K, V, F>(map: &BTreeMap<K, V>, f: F) -> String
-        where F: Fn(&K, &V) -> String
-    {
-        map.iter().fold(String::new(), |p, (k, v)| format!("{}{}", p, f(k, v)))
-    }
+        let root = Element::new("node")
+            .with_children(smap.borrow()
+                .iter()
+                .map(|(name, iface)| Self::_introspect_interface(name, iface)))
+            .with_children(schildren.borrow()
+                .iter()
+                .map(|name| Element::new("node").with_attribute("name", name)));
+        let xml = format!("{}<!-- rust-bus {} -->\n{}",
+                          INTROSPECTION_DOCTYPE,
+                          env!("CARGO_PKG_VERSION"),
+                          root.to_xml(indent));
+        *cache.borrow_mut() = Some((schildren.borrow().clone(), xml.clone()));
 
-    fn _to_string_list<T, F>(map: &[T], f: F) -> String
-        where F: Fn(&T) -> String
-    {
-        map.iter().fold(String::new(), |p, t| format!("{}{}", p, f(t)))
+        Ok(vec![Value::BasicValue(BasicValue::String(xml))])
     }
 
-    fn _introspect_annotation(indent: &str, ann: &Annotation) -> String {
-        format!(r#"{}<annotation name="{}" value="{}" />\n"#,
-                indent,
-                ann.name,
-                ann.value)
+    fn _introspect_annotations(anns: &[Annotation]) -> Vec<Element> {
+        anns.iter()
+            .map(|ann| {
+                Element::new("annotation")
+                    .with_attribute("name", &ann.name)
+                    .with_attribute("value", &ann.value)
+            })
+            .collect()
     }
 
-    fn _introspect_arg(indent: &str, direction: &str, arg: &Argument) -> String {
-        format!(r#"{}<arg name="{}" type="{}" direction="{}" />\n"#,
-                indent,
-                arg.name,
-                arg.signature,
-                direction)
+    fn _introspect_args(direction: &str, args: &[Argument]) -> Vec<Element> {
+        args.iter()
+            .map(|arg| {
+                let elem = Element::new("arg")
+                    .with_attribute("name", &arg.name)
+                    .with_attribute("type", &arg.signature)
+                    .with_attribute("direction", direction);
+
+                match arg.description {
+                    Some(ref description) => {
+                        elem.with_child(Element::new("annotation")
+                            .with_attribute("name", "org.gtk.GDBus.DocString")
+                            .with_attribute("value", description))
+                    },
+                    None => elem,
+                }
+            })
+            .collect()
     }
 
-    fn _introspect_property(indent: &str, name: &str, prop: &Property) -> String {
-        let new_indent = format!("{} ", indent);
+    fn _introspect_property(name: &str, prop: &Property) -> Element {
         let access = match prop.access {
             PropertyAccess::RO(_) => "read",
             PropertyAccess::RW(_) => "readwrite",
             PropertyAccess::WO(_) => "write",
         };
-        let sig = match prop.signature {
-            Signature(ref s) => s,
-        };
-        format!(r#"{}<property name="" type="{}" access="{}">\n{}{}</property>\n"#,
-                name,
-                sig,
-                access,
-                Self::_to_string_list(&prop.anns, |t| Self::_introspect_annotation(&new_indent, t)),
-                indent)
-    }
-
-    fn _introspect_method(indent: &str, name: &str, method: &Method) -> String {
-        let new_indent = format!("{} ", indent);
-        format!(r#"{}<method name="">\n{}{}{}{}</method>\n"#,
-                name,
-                Self::_to_string_list(&method.in_args,
-                                      |t| Self::_introspect_arg(&new_indent, "in", t)),
-                Self::_to_string_list(&method.out_args,
-                                      |t| Self::_introspect_arg(&new_indent, "out", t)),
-                Self::_to_string_list(&method.anns,
-                                      |t| Self::_introspect_annotation(&new_indent, t)),
-                indent)
-    }
-
-    fn _introspect_signal(indent: &str, name: &str, signal: &Signal) -> String {
-        let new_indent = format!("{} ", indent);
-        format!(r#"{}<signal name="">\n{}{}{}</signal>\n"#,
-                name,
-                Self::_to_string_list(&signal.args,
-                                      |t| Self::_introspect_arg(&new_indent, "out", t)),
-                Self::_to_string_list(&signal.anns,
-                                      |t| Self::_introspect_annotation(&new_indent, t)),
-                indent)
-    }
-
-    fn _introspect_interface(indent: &str, name: &str, iface: &Interface) -> String {
-        let new_indent = format!("{} ", indent);
-        format!(r#"{}<interface name="{}">\n{}{}{}{}{}</interface>\n"#,
-                indent,
-                name,
-                Self::_to_string_map(&iface.properties,
-                                     |k, v| Self::_introspect_property(&new_indent, k, v)),
-                Self::_to_string_map(&iface.methods,
-                                     |k, v| Self::_introspect_method(&new_indent, k, v)),
-                Self::_to_string_map(&iface.signals,
-                                     |k, v| Self::_introspect_signal(&new_indent, k, v)),
-                Self::_to_string_list(&iface.anns,
-                                      |t| Self::_introspect_annotation(&new_indent, t)),
-                indent)
-    }
-
-    pub fn new(map: InterfaceMapRef, children: ChildrenListRef) -> Interface {
+
+        Element::new("property")
+            .with_attribute("name", name)
+            .with_attribute("type", &prop.signature.0)
+            .with_attribute("access", access)
+            .with_children(Self::_introspect_annotations(&prop.anns))
+    }
+
+    fn _introspect_method(name: &str, method: &Method) -> Element {
+        Element::new("method")
+            .with_attribute("name", name)
+            .with_children(Self::_introspect_args("in", &method.in_args))
+            .with_children(Self::_introspect_args("out", &method.out_args))
+            .with_children(Self::_introspect_annotations(&method.anns))
+    }
+
+    fn _introspect_signal(name: &str, signal: &Signal) -> Element {
+        Element::new("signal")
+            .with_attribute("name", name)
+            .with_children(Self::_introspect_args("out", &signal.args))
+            .with_children(Self::_introspect_annotations(&signal.anns))
+    }
+
+    fn _introspect_interface(name: &str, iface: &Interface) -> Element {
+        Element::new("interface")
+            .with_attribute("name", name)
+            .with_children(iface.properties
+                .iter()
+                .map(|(name, prop)| Self::_introspect_property(name, prop)))
+            .with_children(iface.methods
+                .iter()
+                .map(|(name, method)| Self::_introspect_method(name, method)))
+            .with_children(iface.signals
+                .iter()
+                .map(|(name, signal)| Self::_introspect_signal(name, signal)))
+            .with_children(Self::_introspect_annotations(&iface.anns))
+    }
+
+    pub fn new(map: InterfaceMapRef, children: ChildrenListRef, indent: String,
+               cache: IntrospectionCache)
+               -> Interface {
         Interface::new().add_method("Introspect",
                                     Method::new(move |m| {
-                                            Self::introspect(map.clone(), children.clone(), m)
+                                            Self::introspect(map.clone(),
+                                                             children.clone(),
+                                                             &indent,
+                                                             &cache,
+                                                             m)
                                         })
                                         .add_result(Argument::new("xml_data", "s")))
     }
@@ -619,10 +1933,25 @@ impl CallHeaders {
 }
 
 impl InterfacesBuilder {
+    /// Add an interface to the set.
+    ///
+    /// The standard `Peer`, `Properties`, and `Introspectable` interfaces are reserved since they
+    /// are added by `finalize`.
+    pub fn add_interface<N>(self, name: N, iface: Interface) -> Result<Self>
+        where N: ToString,
+    {
+        let name = name.to_string();
+        if is_reserved_interface(&name) {
+            bail!(ErrorKind::ReservedInterface(name));
+        }
+        iface._check_signatures()?;
+
+        self._add_interface(name, iface)
+    }
+
     // Marked as mut for intent; Rc<> doesn't require it though.
     #[allow(unused_mut)]
-    /// Add an interface to the set.
-    pub fn add_interface<N>(mut self, name: N, iface: Interface) -> Result<Self>
+    fn _add_interface<N>(mut self, name: N, iface: Interface) -> Result<Self>
         where N: ToString,
     {
         {
@@ -640,31 +1969,102 @@ impl InterfacesBuilder {
         .map(|_| self)
     }
 
-    /// Finalize the interface set.
+    /// Use the given machine ID for the `org.freedesktop.DBus.Peer` interface.
     ///
-    /// Once this is called, the interfaces may be used fully. Calling this adds the
-    /// `org.freedesktop.DBus.Peer`, `org.freedesktop.DBus.Properties`, and
-    /// `org.freedesktop.DBus.Introspectable` standard interfaces to the object.
-    ///
-    /// Once this is called, further interfaces may not be added once this is called.
-    pub fn finalize(mut self, children: &ChildrenList) -> Result<Interfaces> {
-        self = Ok(self)
-            .and_then(|this| {
-                this.add_interface("org.freedesktop.DBus.Peer", PeerInterface::new())
-            })
-            .and_then(|this| {
-                let map_ref = Rc::downgrade(&this.map);
-                this.add_interface("org.freedesktop.DBus.Properties",
-                                   PropertyInterface::new(map_ref))
-            })
-            .and_then(|this| {
-                let map_ref = Rc::downgrade(&this.map);
-                this.add_interface("org.freedesktop.DBus.Introspectable",
-                                   IntrospectableInterface::new(map_ref, Rc::downgrade(children)))
-            })?;
+    /// By default, the machine ID of the host is used. This is useful in containers or tests
+    /// where the host's machine ID is not the one which should be reported.
+    pub fn with_machine_id<I>(mut self, machine_id: I) -> Self
+        where I: ToString,
+    {
+        self.machine_id = Some(machine_id.to_string());
 
-        Ok(Interfaces {
+        self
+    }
+
+    /// Use the given string to indent each level of the introspection data.
+    ///
+    /// By default, a single space is used.
+    pub fn introspection_indent<I>(mut self, indent: I) -> Self
+        where I: ToString,
+    {
+        self.indent = indent.to_string();
+
+        self
+    }
+
+    /// Intercept calls to the `org.freedesktop.DBus.Properties` interface.
+    ///
+    /// The handler is called for the `Get`, `Set`, and `GetAll` methods with the name of the
+    /// method and a function which performs the default behavior. This may be used to add access
+    /// control or to load property values lazily. The handler takes precedence over the default
+    /// implementation, so it must call the given function itself if the default behavior is
+    /// wanted.
+    pub fn properties_handler<F>(mut self, handler: F) -> Self
+        where F: FnMut(&CallContext, &str, &mut Message, &mut FnMut(&mut Message) -> MethodResult)
+                       -> MethodResult + 'static,
+    {
+        self.properties_handler = Some(Rc::new(RefCell::new(handler)));
+
+        self
+    }
+
+    /// Implement the `org.freedesktop.DBus.ObjectManager` interface for the given objects.
+    pub fn object_manager(mut self, objects: ManagedObjects) -> Self {
+        self.managed_objects = Some(objects);
+
+        self
+    }
+
+    /// Use the given state for the handlers of the interfaces.
+    ///
+    /// Servers use this to share their state with all of their objects.
+    pub fn shared_state(mut self, state: SharedState) -> Self {
+        self.state = state;
+
+        self
+    }
+
+    /// Finalize the interface set.
+    ///
+    /// Once this is called, the interfaces may be used fully. Calling this adds the
+    /// `org.freedesktop.DBus.Peer`, `org.freedesktop.DBus.Properties`, and
+    /// `org.freedesktop.DBus.Introspectable` standard interfaces to the object. If requested, the
+    /// `org.freedesktop.DBus.ObjectManager` interface is added as well.
+    ///
+    /// Once this is called, further interfaces may not be added once this is called.
+    pub fn finalize(mut self, children: &ChildrenList) -> Result<Interfaces> {
+        let machine_id = self.machine_id.take();
+        let indent = self.indent.clone();
+        let properties_handler = self.properties_handler.take();
+        if let Some(objects) = self.managed_objects.take() {
+            self = self._add_interface("org.freedesktop.DBus.ObjectManager",
+                                       ObjectManagerInterface::new(objects))?;
+        }
+        let introspection = Rc::new(RefCell::new(None));
+        let introspection_cache = introspection.clone();
+        self = Ok(self)
+            .and_then(|this| {
+                this._add_interface("org.freedesktop.DBus.Peer", PeerInterface::new(machine_id))
+            })
+            .and_then(|this| {
+                let map_ref = Rc::downgrade(&this.map);
+                this._add_interface("org.freedesktop.DBus.Properties",
+                                    PropertyInterface::new(map_ref, properties_handler))
+            })
+            .and_then(|this| {
+                let map_ref = Rc::downgrade(&this.map);
+                this._add_interface("org.freedesktop.DBus.Introspectable",
+                                    IntrospectableInterface::new(map_ref,
+                                                                 Rc::downgrade(children),
+                                                                 indent,
+                                                                 introspection_cache))
+            })?;
+
+        Ok(Interfaces {
             map: self.map,
+            indent: self.indent,
+            introspection: introspection,
+            state: self.state,
         })
     }
 }
@@ -674,6 +2074,11 @@ impl Interfaces {
     pub fn new() -> InterfacesBuilder {
         InterfacesBuilder {
             map: Rc::new(RefCell::new(Map::new())),
+            machine_id: None,
+            indent: " ".to_string(),
+            properties_handler: None,
+            managed_objects: None,
+            state: SharedState::new(),
         }
     }
 
@@ -684,33 +2089,136 @@ impl Interfaces {
             .join("")
     }
 
-    fn _msg_signature(msg: &Message) -> String {
-        msg.values()
-            .unwrap()
-            .map_or_else(String::new, |vs| {
-                vs.iter()
-                    .map(|v| v.get_signature().to_string())
-                    .collect::<Vec<_>>()
-                    .join("")
+    fn _check_signature(method: &Method, msg: &Message) -> bool {
+        // The signature is checked without unpacking the arguments; handlers unpack them.
+        let actual_sig = msg.body_signature();
+
+        if method.strict_arguments {
+            method.in_signature() == actual_sig
+        } else {
+            signature::parse(&actual_sig).ok().map_or(false, |types| {
+                types.len() >= method.in_args.len() &&
+                method.in_args
+                    .iter()
+                    .zip(types.iter())
+                    .all(|(arg, ty)| arg.signature == ty.to_string())
             })
-    }
-
-    fn _check_signature(args: &[Argument], msg: &Message) -> bool {
-        let expect_sig = Self::_signature(args);
-        let actual_sig = Self::_msg_signature(msg);
-
-        expect_sig == actual_sig
+        }
     }
 
     /// Return a dictionary of interfaces and properties on the interface.
     ///
     /// This is meant to be used by an ObjectManager interface.
     pub fn get_interfaces_and_properties(&self) -> Dictionary {
-        Dictionary::new(self.map
-            .borrow()
-            .iter()
-            .map(|(k, v)| (BasicValue::String(k.clone()), Value::Dictionary(v.get_property_map())))
-            .collect())
+        let map = self.map.borrow();
+        interfaces_and_properties(map.iter())
+    }
+
+    /// Check the definitions of the interfaces for conformance to the specification.
+    ///
+    /// Interface and member names, argument signatures, and property signatures are checked and
+    /// names which are used for more than one member of an interface are reported. All problems
+    /// which are found are returned.
+    pub fn validate(&self) -> ::std::result::Result<(), Vec<ValidationError>> {
+        let mut errors = vec![];
+
+        for (name, iface) in self.map.borrow().iter() {
+            iface._validate(name, &mut errors);
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// The introspection data for a single interface in the set.
+    ///
+    /// The interface is wrapped in a `<node>` element without any other interfaces or children.
+    pub fn introspect_interface(&self, name: &str) -> Result<String> {
+        let map = self.map.borrow();
+        let iface = match map.get(name) {
+            Some(iface) => iface,
+            None => bail!(ErrorKind::NoSuchInterface(name.to_string())),
+        };
+
+        let root = Element::new("node")
+            .with_child(IntrospectableInterface::_introspect_interface(name, iface));
+
+        Ok(format!("{}{}", INTROSPECTION_DOCTYPE, root.to_xml(&self.indent)))
+    }
+
+    /// Add an interface to the set after it has been finalized.
+    pub fn add_interface<N>(&self, name: N, iface: Interface) -> Result<()>
+        where N: ToString,
+    {
+        if is_reserved_interface(&name.to_string()) {
+            bail!(ErrorKind::ReservedInterface(name.to_string()));
+        }
+        iface._check_signatures()?;
+
+        match self.map.borrow_mut().entry(name.to_string()) {
+            Entry::Vacant(v) => {
+                v.insert(iface);
+                *self.introspection.borrow_mut() = None;
+
+                Ok(())
+            },
+            Entry::Occupied(_) => bail!(ErrorKind::InterfaceAlreadyRegistered(name.to_string())),
+        }
+    }
+
+    /// Remove an interface from the set.
+    pub fn remove_interface<N>(&self, name: N) -> Result<Interface>
+        where N: AsRef<str>,
+    {
+        match self.map.borrow_mut().remove(name.as_ref()) {
+            Some(iface) => {
+                *self.introspection.borrow_mut() = None;
+
+                Ok(iface)
+            },
+            None => bail!(ErrorKind::NoSuchInterface(name.as_ref().to_string())),
+        }
+    }
+
+    /// The names of the interfaces in the set.
+    pub fn names(&self) -> Vec<String> {
+        self.map.borrow().keys().cloned().collect()
+    }
+
+    /// Whether the set implements the `org.freedesktop.DBus.ObjectManager` interface.
+    pub fn is_object_manager(&self) -> bool {
+        self.map.borrow().contains_key("org.freedesktop.DBus.ObjectManager")
+    }
+
+    /// A signal announcing that the given interfaces have been added to an object.
+    ///
+    /// This is the `org.freedesktop.DBus.ObjectManager.InterfacesAdded` signal. It is emitted from
+    /// the path of the object manager responsible for the object at `path`.
+    pub fn interfaces_added_signal(&self, manager: &str, path: &str, names: &[&str]) -> Message {
+        let map = self.map.borrow();
+        let ifaces = interfaces_and_properties(map.iter()
+            .filter(|&(name, _)| names.contains(&name.as_str())));
+
+        Message::new_signal(manager, "org.freedesktop.DBus.ObjectManager", "InterfacesAdded")
+            .add_argument(&Value::BasicValue(BasicValue::ObjectPath(Path(path.to_string()))))
+            .add_argument(&Value::Dictionary(ifaces))
+    }
+
+    /// A signal announcing that the given interfaces have been removed from an object.
+    ///
+    /// This is the `org.freedesktop.DBus.ObjectManager.InterfacesRemoved` signal. It is emitted
+    /// from the path of the object manager responsible for the object at `path`.
+    pub fn interfaces_removed_signal(manager: &str, path: &str, names: &[&str]) -> Message {
+        let names = names.iter()
+            .map(|name| Value::BasicValue(BasicValue::String(name.to_string())))
+            .collect();
+
+        Message::new_signal(manager, "org.freedesktop.DBus.ObjectManager", "InterfacesRemoved")
+            .add_argument(&Value::BasicValue(BasicValue::ObjectPath(Path(path.to_string()))))
+            .add_argument(&Value::Array(Array::new_with_sig(names, "as".to_string())))
     }
 
     /// Parse a `Message` and call the appropriate method (if applicable).
@@ -723,78 +2231,221 @@ impl Interfaces {
     /// If the method returns values which do not match its signature, a panic will occur since
     /// this is a bug in the implementation.
     pub fn handle(&self, conn: &Connection, msg: &mut Message) -> Option<::std::result::Result<(), ()>> {
+        let errors = ErrorPolicy::new(conn);
+
+        self._dispatch_with_signals(Some(&errors), msg).map(|(res, signals)| {
+            res.into_iter()
+                .chain(signals)
+                .map(|msg| conn.send(msg).map(|_| ()).map_err(|_| ()))
+                .collect()
+        })
+    }
+
+    fn _dispatch(&self, errors: Option<&ErrorPolicy>, msg: &mut Message) -> Option<Message> {
+        self._dispatch_with_signals(errors, msg).and_then(|(reply, _)| reply)
+    }
+
+    /// Dispatch a method call, returning the reply and any signals queued by the handler.
+    ///
+    /// There is no reply for deferred methods or for calls which do not expect one.
+    fn _dispatch_with_signals(&self, errors: Option<&ErrorPolicy>, msg: &mut Message)
+                              -> Option<(Option<Message>, Vec<Message>)> {
+        let errors = errors.cloned().unwrap_or_default();
+
         CallHeaders::new(msg).map(|hdrs| {
+            let ctx = CallContext::new(self.state.clone());
             let iface_name = hdrs.interface;
             let method_name = hdrs.method;
-            let map_ref = &self.map.borrow();
-            let opt_iface = map_ref.get(&iface_name);
-            let opt_method = opt_iface.and_then(|iface| iface.methods.get(&method_name));
+            // The interfaces are not borrowed while the handler runs so that it may use them
+            // (e.g., to read properties or add another interface).
+            let lookup = {
+                let map = self.map.borrow();
+                let opt_iface = map.get(&iface_name);
+
+                match opt_iface.and_then(|iface| iface.methods.get(&method_name)) {
+                    Some(method) => {
+                        if let Err(err) = method._check_rate_limit(&ctx, msg) {
+                            Err(err.into_message(msg))
+                        } else if Self::_check_signature(method, msg) {
+                            Ok((method.cb.clone(), method.out_signature()))
+                        } else {
+                            Err(Arguments::invalid_arguments().into_message(msg))
+                        }
+                    },
+                    None if opt_iface.is_none() => {
+                        Err(msg.error_message("org.freedesktop.DBus.Error.UnknownInterface")
+                            .add_argument(&format!("unknown interface: {}", iface_name)))
+                    },
+                    None => {
+                        Err(msg.error_message("org.freedesktop.DBus.Error.UnknownMethod")
+                            .add_argument(&format!("unknown method: {}", method_name)))
+                    },
+                }
+            };
 
-            let res = if let Some(method) = opt_method {
-                let res = if Self::_check_signature(&method.in_args, msg) {
-                    let mut cb = method.cb.borrow_mut();
+            let res = match lookup {
+                Ok((cb, out_signature)) => {
+                    // Streamed results are not demarshaled again to check their signature.
+                    let (res, stream_sig) = match cb {
+                        MethodCallback::Values(ref cb) => {
+                            let mut cb = cb.borrow_mut();
+
+                            match cb.deref_mut()(&ctx, msg) {
+                                Ok(vals) => {
+                                    let res = vals.iter().fold(msg.return_message(),
+                                                               |msg, val| msg.add_argument(val));
+                                    (res, None)
+                                },
+                                Err(err) => (errors.apply(err).into_message(msg), None),
+                            }
+                        },
+                        MethodCallback::Stream(ref cb) => {
+                            let mut cb = cb.borrow_mut();
+
+                            match cb.deref_mut()(&ctx, msg) {
+                                Ok(stream) => {
+                                    let sig = stream.signature();
+
+                                    match msg.return_message().add_stream(stream) {
+                                        Ok(res) => (res, Some(sig)),
+                                        Err(err) => {
+                                            let err = ErrorMessage::from(err);
+                                            (errors.apply(err).into_message(msg), None)
+                                        },
+                                    }
+                                },
+                                Err(err) => (errors.apply(err).into_message(msg), None),
+                            }
+                        },
+                        MethodCallback::Deferred(ref cb) => {
+                            let reply = DeferredReply::new(msg, out_signature, errors);
+                            cb.borrow_mut().deref_mut()(&ctx, msg, reply);
 
-                    match cb.deref_mut()(msg) {
-                        Ok(vals) => {
-                            vals.iter().fold(msg.return_message(), |msg, val| msg.add_argument(val))
+                            return (None, ctx.signals.into_inner());
                         },
-                        Err(err) => err.into_message(msg),
-                    }
-                } else {
-                    Arguments::invalid_arguments().into_message(msg)
-                };
-
-                match res.message_type() {
-                    MessageType::Error => (),
-                    MessageType::MethodReturn => {
-                        let expect = Self::_signature(&method.out_args);
-                        let actual = Self::_msg_signature(&res);
-
-                        if expect != actual {
-                            panic!("invalid return signature for: \
-                                    path: '{:?}' interface: '{}' method: '{}' \
-                                    expected: '{}' actual: '{}'",
+                    };
+
+                    match res.message_type() {
+                        MessageType::Error => (),
+                        MessageType::MethodReturn => {
+                            let actual = stream_sig.unwrap_or_else(|| res.body_signature());
+
+                            if out_signature != actual {
+                                panic!("invalid return signature for: \
+                                        path: '{:?}' interface: '{}' method: '{}' \
+                                        expected: '{}' actual: '{}'",
+                                       msg.path(),
+                                       iface_name,
+                                       method_name,
+                                       out_signature,
+                                       actual)
+                            };
+                        },
+                        _ => {
+                            panic!("invalid return value for: \
+                                    path: '{:?}' interface: '{}' method: '{}'",
                                    msg.path(),
                                    iface_name,
-                                   method_name,
-                                   expect,
-                                   actual)
-                        };
-                    },
-                    _ => {
-                        panic!("invalid return value for: \
-                                path: '{:?}' interface: '{}' method: '{}'",
-                               msg.path(),
-                               iface_name,
-                               method_name)
-                    },
-                };
+                                   method_name)
+                        },
+                    };
 
-                res
-            } else if opt_iface.is_none() {
-                msg.error_message("org.freedesktop.DBus.Error.UnknownMethod")
-                    .add_argument(&format!("unknown interface: {}", iface_name))
+                    res
+                },
+                Err(res) => res,
+            };
+
+            // The handler still runs for its side effects, but nothing is sent back to callers
+            // which do not expect a reply.
+            let res = if msg.expects_reply() {
+                Some(res)
             } else {
-                msg.error_message("org.freedesktop.DBus.Error.UnknownMethod")
-                    .add_argument(&format!("unknown method: {}", method_name))
+                None
             };
 
-            conn.send(res)
-                .map(|_| ())
-                .map_err(|_| ())
+            (res, ctx.signals.into_inner())
         })
     }
 }
 
+#[cfg(test)]
+impl Method {
+    fn call(&self, msg: &mut Message) -> MethodResult {
+        match self.cb {
+            MethodCallback::Values(ref cb) => {
+                cb.borrow_mut().deref_mut()(&CallContext::new(SharedState::new()), msg)
+            },
+            MethodCallback::Stream(_) => panic!("streaming methods must be dispatched"),
+            MethodCallback::Deferred(_) => panic!("deferred methods must be dispatched"),
+        }
+    }
+}
+
+#[cfg(test)]
+/// Finalize interfaces for an object without children.
+///
+/// The children list is returned so that it outlives the interfaces.
+fn _finalize(ifaces: InterfacesBuilder) -> (Interfaces, ChildrenList) {
+    let children = Rc::new(RefCell::new(vec![]));
+    let ifaces = ifaces.finalize(&children).unwrap();
+
+    (ifaces, children)
+}
+
+#[cfg(test)]
+/// Finalize interfaces for an object with a single interface.
+fn _with_interface(iface_name: &str, iface: Interface) -> (Interfaces, ChildrenList) {
+    _finalize(Interfaces::new().add_interface(iface_name, iface).unwrap())
+}
+
+#[cfg(test)]
+/// Create a method call to the test service.
+fn _method_call(path: &str, iface_name: &str, method: &str, args: &[&Marshal]) -> Message {
+    let msg = Message::new_method_call("net.benboeckel.test.rustbus", path, iface_name, method);
+
+    args.iter().fold(msg, |msg, arg| msg.add_argument(*arg))
+}
+
+#[cfg(test)]
+/// Create a method call to the test service from the given sender.
+fn _method_call_from(sender: &str, path: &str, iface_name: &str, method: &str) -> Message {
+    use crates::dbus_bytestream::message;
+
+    let sender = Value::BasicValue(BasicValue::String(sender.to_string()));
+    Message::new(message::create_method_call("net.benboeckel.test.rustbus",
+                                             path,
+                                             iface_name,
+                                             method)
+        .add_header(message::HEADER_FIELD_SENDER, Variant::new(sender, "s")))
+}
+
+#[cfg(test)]
+/// Dispatch a method call to an object with a single interface.
+fn _dispatch_to(iface: Interface, path: &str, iface_name: &str, method: &str, args: &[&Marshal])
+                -> Message {
+    let (ifaces, _children) = _with_interface(iface_name, iface);
+
+    ifaces._dispatch(None, &mut _method_call(path, iface_name, method, args)).unwrap()
+}
+
+#[cfg(test)]
+/// Introspect the root object of the interfaces.
+fn _introspect(ifaces: &Interfaces) -> String {
+    let mut msg = _method_call("/", "org.freedesktop.DBus.Introspectable", "Introspect", &[]);
+    let reply = ifaces._dispatch(None, &mut msg).unwrap();
+
+    match reply.values().unwrap().unwrap()[0] {
+        Value::BasicValue(BasicValue::String(ref xml)) => xml.clone(),
+        _ => panic!("unexpected introspection result"),
+    }
+}
+
 #[test]
 fn empty_interface() {
     use super::connection::RequestNameFlags;
     use super::connection::RequestNameReply;
 
-    let ifaces = Interfaces::new();
-    let children = Rc::new(RefCell::new(vec![]));
-
-    let ifaces = ifaces.finalize(&children).unwrap();
+    let (ifaces, _children) = _finalize(Interfaces::new());
 
     {
         let map = ifaces.map.borrow();
@@ -818,3 +2469,1404 @@ fn empty_interface() {
 
     ifaces.handle(&conn, &mut msg);
 }
+
+#[test]
+fn custom_machine_id() {
+    let ifaces = Interfaces::new().with_machine_id("0123456789abcdef0123456789abcdef");
+    let (ifaces, _children) = _finalize(ifaces);
+
+    let map = ifaces.map.borrow();
+    let method = &map["org.freedesktop.DBus.Peer"].methods["GetMachineId"];
+    let mut msg = _method_call("/", "org.freedesktop.DBus.Peer", "GetMachineId", &[]);
+
+    let res = method.call(&mut msg).ok().unwrap();
+    assert_eq!(res,
+               vec![Value::BasicValue(BasicValue::String("0123456789abcdef0123456789abcdef"
+                   .to_string()))]);
+}
+
+#[test]
+fn merge_interfaces() {
+    let base = Interface::new()
+        .add_method("Ping", Method::new(|_| Ok(vec![])))
+        .add_signal::<&str>("Pinged", Signal::new());
+    let extra = Interface::new().add_method("Pong", Method::new(|_| Ok(vec![])));
+
+    let merged = base.merge(extra).unwrap();
+    assert!(merged.methods.contains_key("Ping"));
+    assert!(merged.methods.contains_key("Pong"));
+    assert!(merged.signals.contains_key("Pinged"));
+
+    let collide = Interface::new().add_method("Ping", Method::new(|_| Ok(vec![])));
+    let err = merged.merge(collide).err().unwrap();
+    assert_eq!(format!("{}", err), "member already registered: Ping");
+}
+
+#[test]
+fn call_context_received() {
+    use std::time::Duration;
+
+    let ctx = CallContext::new(SharedState::new());
+
+    assert!(ctx.received().elapsed() < Duration::from_secs(1));
+}
+
+#[test]
+fn introspection_indent() {
+    let iface = Interface::new().annotate(Annotation::new("net.benboeckel.test.Annotation", "yes"));
+    let ifaces = Interfaces::new()
+        .introspection_indent("  ")
+        .add_interface("net.benboeckel.test.Interface", iface)
+        .unwrap();
+    let (ifaces, _children) = _finalize(ifaces);
+
+    let xml = _introspect(&ifaces);
+    assert!(xml.contains(concat!("  <interface name=\"net.benboeckel.test.Interface\">\n",
+                                 "    <annotation name=\"net.benboeckel.test.Annotation\" ",
+                                 "value=\"yes\" />\n",
+                                 "  </interface>\n")));
+}
+
+#[test]
+fn interfaces_added_signal() {
+    let (ifaces, _children) = _finalize(Interfaces::new());
+    ifaces.add_interface("net.benboeckel.test.Interface", Interface::new()).unwrap();
+
+    let msg = ifaces.interfaces_added_signal("/net/benboeckel",
+                                             "/net/benboeckel/object",
+                                             &["net.benboeckel.test.Interface"]);
+    assert_eq!(msg.path(), Some("/net/benboeckel".to_string()));
+    assert_eq!(msg.member(), Some("InterfacesAdded".to_string()));
+
+    let values = msg.values().unwrap().unwrap();
+    assert_eq!(values[0],
+               Value::BasicValue(BasicValue::ObjectPath(Path("/net/benboeckel/object"
+                   .to_string()))));
+    if let Value::Dictionary(ref dict) = values[1] {
+        assert_eq!(dict.map.len(), 1);
+        assert!(dict.map
+            .contains_key(&BasicValue::String("net.benboeckel.test.Interface".to_string())));
+    } else {
+        panic!("unexpected interfaces argument");
+    }
+
+    ifaces.remove_interface("net.benboeckel.test.Interface").unwrap();
+    assert!(ifaces.remove_interface("net.benboeckel.test.Interface").is_err());
+}
+
+#[test]
+fn properties_handler() {
+    let ifaces = Interfaces::new()
+        .properties_handler(|_, method, m, default| {
+            let property = Arguments::new(m)?.extract_string(1)?.clone();
+            if method == "Get" && property == "Secret" {
+                return Err(ErrorMessage::new("org.freedesktop.DBus.Error.AccessDenied",
+                                             "access denied"));
+            }
+
+            default(m)
+        })
+        .add_interface("net.benboeckel.test.Interface", Interface::new())
+        .unwrap();
+    let (ifaces, _children) = _finalize(ifaces);
+
+    let map = ifaces.map.borrow();
+    let method = &map["org.freedesktop.DBus.Properties"].methods["Get"];
+
+    let get = |name: &str| {
+        let mut msg = _method_call("/",
+                                   "org.freedesktop.DBus.Properties",
+                                   "Get",
+                                   &[&"net.benboeckel.test.Interface", &name]);
+        method.call(&mut msg).err().unwrap().name().to_string()
+    };
+
+    assert_eq!(get("Secret"), "org.freedesktop.DBus.Error.AccessDenied");
+    assert_eq!(get("Missing"), "org.freedesktop.DBus.Error.UnknownProperty");
+}
+
+#[test]
+fn error_with_code() {
+    let msg = _method_call("/", "net.benboeckel.test.Interface", "Method", &[]);
+    let err = ErrorMessage::new("net.benboeckel.test.Error.Failed", "it broke").with_code(42);
+
+    let reply = err.into_message(&msg);
+    assert_eq!(reply.values().unwrap().unwrap(),
+               vec![Value::BasicValue(BasicValue::String("it broke".to_string())),
+                    Value::BasicValue(BasicValue::Int32(42))]);
+}
+
+#[test]
+fn extra_arguments() {
+    let iface = Interface::new()
+        .add_method("Strict",
+                    Method::new(|_| Ok(vec![]))
+                        .add_argument(Argument::new("name", "s"))
+                        .strict_arguments())
+        .add_method("Lenient",
+                    Method::new(|_| Ok(vec![])).add_argument(Argument::new("name", "s")));
+    let (ifaces, _children) = _with_interface("net.benboeckel.test.Interface", iface);
+
+    let call = |method| {
+        _method_call("/", "net.benboeckel.test.Interface", method, &[&"name", &1u32])
+    };
+
+    let mut msg = call("Strict");
+    match ifaces._dispatch(None, &mut msg).unwrap().message_type() {
+        MessageType::Error => (),
+        _ => panic!("strict method accepted extra arguments"),
+    }
+
+    let mut msg = call("Lenient");
+    match ifaces._dispatch(None, &mut msg).unwrap().message_type() {
+        MessageType::MethodReturn => (),
+        _ => panic!("lenient method rejected extra arguments"),
+    }
+}
+
+#[test]
+fn introspect_single_interface() {
+    let ifaces = Interfaces::new()
+        .add_interface("net.benboeckel.test.First", Interface::new())
+        .unwrap()
+        .add_interface("net.benboeckel.test.Second", Interface::new())
+        .unwrap();
+    let (ifaces, _children) = _finalize(ifaces);
+
+    let xml = ifaces.introspect_interface("net.benboeckel.test.First").unwrap();
+    assert!(xml.contains(r#"<interface name="net.benboeckel.test.First">"#));
+    assert!(!xml.contains("net.benboeckel.test.Second"));
+    assert!(!xml.contains("org.freedesktop.DBus.Introspectable"));
+
+    assert!(ifaces.introspect_interface("net.benboeckel.test.Missing").is_err());
+}
+
+#[test]
+fn argument_description() {
+    let iface = Interface::new()
+        .add_method("Frobnicate",
+                    Method::new(|_| Ok(vec![]))
+                        .add_argument(Argument::new("amount", "u")
+                            .describe(r#"How much to <frob> & "nicate""#)));
+    let (ifaces, _children) = _with_interface("net.benboeckel.test.Interface", iface);
+
+    let xml = ifaces.introspect_interface("net.benboeckel.test.Interface").unwrap();
+    assert!(xml.contains(concat!(r#"<annotation name="org.gtk.GDBus.DocString" "#,
+                                 r#"value="How much to &lt;frob&gt; "#,
+                                 r#"&amp; &quot;nicate&quot;" />"#)));
+}
+
+#[test]
+fn error_conversion() {
+    use std::io;
+
+    fn handler() -> MethodResult {
+        Err(io::Error::new(io::ErrorKind::Other, "disk on fire"))?;
+
+        Ok(vec![])
+    }
+
+    let err = handler().err().unwrap();
+    assert_eq!(err.name(), "org.freedesktop.DBus.Error.Failed");
+    assert_eq!(err.message(), "disk on fire");
+}
+
+#[cfg(test)]
+/// An allocator which tracks the peak memory allocated on each thread.
+struct PeakAllocator;
+
+#[cfg(test)]
+thread_local! {
+    /// The bytes currently allocated by the thread and the peak since the last reset.
+    static ALLOCATED: ::std::cell::Cell<(isize, isize)> = ::std::cell::Cell::new((0, 0));
+}
+
+#[cfg(test)]
+#[global_allocator]
+static ALLOCATOR: PeakAllocator = PeakAllocator;
+
+#[cfg(test)]
+fn _track_allocation(delta: isize) {
+    let _ = ALLOCATED.try_with(|allocated| {
+        let (current, peak) = allocated.get();
+        let current = current + delta;
+        allocated.set((current, ::std::cmp::max(current, peak)));
+    });
+}
+
+#[cfg(test)]
+unsafe impl ::std::alloc::GlobalAlloc for PeakAllocator {
+    unsafe fn alloc(&self, layout: ::std::alloc::Layout) -> *mut u8 {
+        let ptr = ::std::alloc::System.alloc(layout);
+        if !ptr.is_null() {
+            _track_allocation(layout.size() as isize);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: ::std::alloc::Layout) {
+        ::std::alloc::System.dealloc(ptr, layout);
+        _track_allocation(-(layout.size() as isize));
+    }
+}
+
+#[cfg(test)]
+/// Measure the peak memory allocated by the current thread while running a function.
+fn _peak_allocation<F, R>(f: F) -> (R, usize)
+    where F: FnOnce() -> R,
+{
+    let start = ALLOCATED.with(|allocated| {
+        let (current, _) = allocated.get();
+        allocated.set((current, current));
+        current
+    });
+    let res = f();
+    let (_, peak) = ALLOCATED.with(|allocated| allocated.get());
+
+    (res, (peak - start) as usize)
+}
+
+#[test]
+fn streaming_result() {
+    use std::cell::Cell;
+    use std::mem;
+
+    const COUNT: u32 = 100000;
+
+    let produced = Rc::new(Cell::new(0));
+    let counter = produced.clone();
+    let iface = Interface::new()
+        .add_method("List",
+                    Method::streaming(move |_, _| {
+                            let counter = counter.clone();
+                            Ok(ArrayStream::new("u",
+                                                (0..COUNT).map(move |i| {
+                                                    counter.set(counter.get() + 1);
+                                                    Value::BasicValue(BasicValue::Uint32(i))
+                                                })))
+                        })
+                        .add_result(Argument::new("items", "au")));
+    let (ifaces, _children) = _with_interface("net.benboeckel.test.Interface", iface);
+
+    let mut msg = _method_call("/", "net.benboeckel.test.Interface", "List", &[]);
+    let (reply, peak) = _peak_allocation(|| ifaces._dispatch(None, &mut msg).unwrap());
+    assert_eq!(produced.get(), COUNT);
+
+    // Collecting the elements before marshaling them would need at least this much memory.
+    let collected = COUNT as usize * mem::size_of::<Value>();
+    assert!(peak < collected / 2,
+            "streaming used {} bytes; collecting would use {}",
+            peak,
+            collected);
+
+    let values = reply.values().unwrap().unwrap();
+    if let Value::Array(ref array) = values[0] {
+        assert_eq!(array.objects.len(), COUNT as usize);
+        assert_eq!(array.objects[COUNT as usize - 1],
+                   Value::BasicValue(BasicValue::Uint32(COUNT - 1)));
+    } else {
+        panic!("unexpected streamed result");
+    }
+}
+
+#[test]
+fn streaming_result_invalid_element() {
+    let iface = Interface::new()
+        .add_method("List",
+                    Method::streaming(|_, _| {
+                            let values = vec![Value::BasicValue(BasicValue::Int32(-1))];
+                            Ok(ArrayStream::new("u", values))
+                        })
+                        .add_result(Argument::new("items", "au")));
+    let reply = _dispatch_to(iface, "/", "net.benboeckel.test.Interface", "List", &[]);
+    assert_eq!(reply.error_name(), Some("org.freedesktop.DBus.Error.Failed".to_string()));
+}
+
+#[test]
+fn introspection_cache_invalidation() {
+    let (ifaces, _children) = _finalize(Interfaces::new());
+
+    let introspect = || _introspect(&ifaces);
+
+    assert!(!introspect().contains("net.benboeckel.test.Interface"));
+    assert!(ifaces.introspection.borrow().is_some());
+
+    ifaces.add_interface("net.benboeckel.test.Interface", Interface::new()).unwrap();
+    assert!(ifaces.introspection.borrow().is_none());
+    assert!(introspect().contains("net.benboeckel.test.Interface"));
+
+    ifaces.remove_interface("net.benboeckel.test.Interface").unwrap();
+    assert!(!introspect().contains("net.benboeckel.test.Interface"));
+}
+
+#[test]
+fn validate_interfaces() {
+    let iface = Interface::new()
+        .add_method("1Frobnicate", Method::new(|_| Ok(vec![])))
+        .add_signal::<&str>("Frobnicated",
+                            Signal::new().add_argument(Argument::new("amount", "u")));
+    let (ifaces, _children) = _with_interface("net.benboeckel.test.Interface", iface);
+
+    let errors = ifaces.validate().err().unwrap();
+    assert_eq!(errors,
+               vec![ValidationError::new("net.benboeckel.test.Interface",
+                                         Some("1Frobnicate"),
+                                         "invalid member name")]);
+}
+
+#[test]
+fn object_manager_introspection() {
+    let ifaces = Interfaces::new().object_manager(ManagedObjects::new());
+    let (ifaces, _children) = _finalize(ifaces);
+
+    let xml = ifaces.introspect_interface("org.freedesktop.DBus.ObjectManager").unwrap();
+    assert!(xml.contains("GetManagedObjects"));
+    assert!(xml.contains(concat!(r#"<arg name="objpath_interfaces_and_properties" "#,
+                                 r#"type="a{oa{sa{sv}}}" direction="out" />"#)));
+    assert!(xml.contains("InterfacesAdded"));
+    assert!(xml.contains(r#"<arg name="interfaces_and_properties" type="a{sa{sv}}""#));
+    assert!(xml.contains("InterfacesRemoved"));
+    assert!(xml.contains(r#"<arg name="interfaces" type="as""#));
+    assert_eq!(xml.matches(r#"<arg name="object_path" type="o""#).count(), 2);
+}
+
+#[test]
+fn property_changed_value() {
+    struct Count;
+
+    impl PropertyReadHandler for Count {
+        fn get(&self) -> PropertyGetResult {
+            Ok(Value::BasicValue(BasicValue::Uint32(5)))
+        }
+    }
+
+    let iface = Interface::new()
+        .add_property("Count", Property::new_ro(Signature("u".to_string()), Box::new(Count)));
+
+    let msg = iface.property_changed_signal("/net/benboeckel",
+                                            "net.benboeckel.test.Interface",
+                                            "Count")
+        .unwrap();
+    assert_eq!(msg.member(), Some("PropertiesChanged".to_string()));
+
+    let values = msg.values().unwrap().unwrap();
+    assert_eq!(values[0],
+               Value::BasicValue(BasicValue::String("net.benboeckel.test.Interface".to_string())));
+    if let Value::Dictionary(ref changed) = values[1] {
+        assert_eq!(changed.map.len(), 1);
+        assert_eq!(changed.map[&BasicValue::String("Count".to_string())],
+                   variant(Value::BasicValue(BasicValue::Uint32(5))));
+    } else {
+        panic!("unexpected changed properties argument");
+    }
+
+    assert!(iface.property_changed_signal("/net/benboeckel",
+                                          "net.benboeckel.test.Interface",
+                                          "Missing")
+        .is_err());
+}
+
+#[test]
+fn property_introspection_order() {
+    struct Value32;
+
+    impl PropertyReadHandler for Value32 {
+        fn get(&self) -> PropertyGetResult {
+            Ok(Value::BasicValue(BasicValue::Uint32(0)))
+        }
+    }
+
+    impl PropertyReadWriteHandler for Value32 {
+        fn get(&self) -> PropertyGetResult {
+            Ok(Value::BasicValue(BasicValue::Uint32(0)))
+        }
+
+        fn set(&self, _: &Value) -> PropertySetResult {
+            Ok(())
+        }
+    }
+
+    impl PropertyWriteHandler for Value32 {
+        fn set(&self, _: &Value) -> PropertySetResult {
+            Ok(())
+        }
+    }
+
+    let sig = || Signature("u".to_string());
+    let iface = Interface::new()
+        .add_property("Gamma", Property::new_wo(sig(), Box::new(Value32)))
+        .add_property("Alpha", Property::new_ro(sig(), Box::new(Value32)))
+        .add_property("Beta",
+                      Property::new_rw(sig(), Box::new(Value32))
+                          .annotate(Annotation::new("org.freedesktop.DBus.Property.EmitsChangedSignal",
+                                                    "invalidates")));
+    let (ifaces, _children) = _with_interface("net.benboeckel.test.Interface", iface);
+
+    let xml = ifaces.introspect_interface("net.benboeckel.test.Interface").unwrap();
+    assert_eq!(xml,
+               ifaces.introspect_interface("net.benboeckel.test.Interface").unwrap());
+
+    let access_of = |name: &str| {
+        let start = xml.find(name).unwrap();
+        let access = &xml[start..];
+        let access = &access[access.find("access=\"").unwrap() + 8..];
+        (start, access[..access.find('"').unwrap()].to_string())
+    };
+
+    let (alpha, alpha_access) = access_of("Alpha");
+    let (beta, beta_access) = access_of("Beta");
+    let (gamma, gamma_access) = access_of("Gamma");
+
+    assert!(alpha < beta && beta < gamma);
+    assert_eq!(alpha_access, "read");
+    assert_eq!(beta_access, "readwrite");
+    assert_eq!(gamma_access, "write");
+    assert!(xml.contains(concat!(r#"<annotation name="org.freedesktop.DBus.Property.EmitsChangedSignal" "#,
+                                 r#"value="invalidates" />"#)));
+}
+
+#[test]
+fn sanitized_errors() {
+    let iface = Interface::new()
+        .add_method("Fail",
+                    Method::new(|_| {
+                        Err(ErrorMessage::new("net.benboeckel.test.Error.Database",
+                                              "failed to open /var/lib/secret.db"))
+                    }));
+    let (ifaces, _children) = _with_interface("net.benboeckel.test.Interface", iface);
+
+    let err = ErrorMessage::new("net.benboeckel.test.Error.Database",
+                                "failed to open /var/lib/secret.db")
+        .sanitized();
+    assert_eq!(err.name(), "net.benboeckel.test.Error.Database");
+    assert_eq!(err.message(), "an internal error occurred");
+
+    let logged = Rc::new(RefCell::new(vec![]));
+    let log = logged.clone();
+    let errors = ErrorPolicy {
+        sanitize: true,
+        logger: Some(Rc::new(move |err: &ErrorMessage| {
+            log.borrow_mut().push(err.message().to_string())
+        })),
+    };
+
+    let call = || _method_call("/", "net.benboeckel.test.Interface", "Fail", &[]);
+    let message = |reply: Message| reply.values().unwrap().unwrap();
+
+    let reply = ifaces._dispatch(None, &mut call()).unwrap();
+    assert_eq!(message(reply),
+               vec![Value::BasicValue(BasicValue::String("failed to open /var/lib/secret.db"
+                   .to_string()))]);
+    assert!(logged.borrow().is_empty());
+
+    let reply = ifaces._dispatch(Some(&errors), &mut call()).unwrap();
+    assert_eq!(reply.error_name(),
+               Some("net.benboeckel.test.Error.Database".to_string()));
+    assert_eq!(message(reply),
+               vec![Value::BasicValue(BasicValue::String("an internal error occurred"
+                   .to_string()))]);
+    assert_eq!(*logged.borrow(), vec!["failed to open /var/lib/secret.db".to_string()]);
+}
+
+#[test]
+fn unknown_members() {
+    let (ifaces, _children) = _with_interface("net.benboeckel.test.Interface", Interface::new());
+
+    let mut msg = _method_call("/", "net.benboeckel.test.Missing", "Method", &[]);
+    let reply = ifaces._dispatch(None, &mut msg).unwrap();
+    assert_eq!(reply.error_name(),
+               Some("org.freedesktop.DBus.Error.UnknownInterface".to_string()));
+
+    let mut msg = _method_call("/", "net.benboeckel.test.Interface", "Missing", &[]);
+    let reply = ifaces._dispatch(None, &mut msg).unwrap();
+    assert_eq!(reply.error_name(),
+               Some("org.freedesktop.DBus.Error.UnknownMethod".to_string()));
+}
+
+#[test]
+fn debounced_property_changes() {
+    let debouncer = PropertyDebouncer::new(Duration::from_secs(60));
+
+    for count in 1..4 {
+        debouncer.property_changed("/net/benboeckel",
+                                   "net.benboeckel.test.Interface",
+                                   "Count",
+                                   Value::BasicValue(BasicValue::Uint32(count)));
+    }
+
+    let start = Instant::now();
+    assert!(debouncer._due(start).is_empty());
+    assert!(debouncer.is_pending());
+    assert!(debouncer.next_due().unwrap() > start + Duration::from_secs(59));
+
+    let msgs = debouncer._due(start + Duration::from_secs(61));
+    assert_eq!(msgs.len(), 1);
+    assert!(!debouncer.is_pending());
+    assert!(debouncer.next_due().is_none());
+
+    let values = msgs[0].values().unwrap().unwrap();
+    if let Value::Dictionary(ref changed) = values[1] {
+        assert_eq!(changed.map.len(), 1);
+        assert_eq!(changed.map[&BasicValue::String("Count".to_string())],
+                   variant(Value::BasicValue(BasicValue::Uint32(3))));
+    } else {
+        panic!("unexpected changed properties argument");
+    }
+}
+
+#[test]
+fn not_supported_result() {
+    let iface = Interface::new()
+        .add_method("Optional", Method::new(|_| not_supported("optional feature is disabled")));
+    let reply = _dispatch_to(iface, "/", "net.benboeckel.test.Interface", "Optional", &[]);
+    assert_eq!(reply.error_name(),
+               Some("org.freedesktop.DBus.Error.NotSupported".to_string()));
+}
+
+#[test]
+fn shared_state() {
+    struct Counter(u32);
+
+    let state = SharedState::new();
+    state.insert(Counter(0));
+
+    let iface = Interface::new()
+        .add_method("Increment",
+                    Method::with_context(|ctx, _| {
+                        ctx.state::<Counter>().unwrap().0 += 1;
+                        Ok(vec![])
+                    }))
+        .add_method("Get",
+                    Method::with_context(|ctx, _| {
+                            let count = ctx.state::<Counter>().unwrap().0;
+                            Ok(vec![Value::BasicValue(BasicValue::Uint32(count))])
+                        })
+                        .add_result(Argument::new("count", "u")));
+    let ifaces = Interfaces::new()
+        .add_interface("net.benboeckel.test.Interface", iface)
+        .unwrap()
+        .shared_state(state.clone());
+    let (ifaces, _children) = _finalize(ifaces);
+
+    let call = |method| {
+        let mut msg = _method_call("/", "net.benboeckel.test.Interface", method, &[]);
+        ifaces._dispatch(None, &mut msg).unwrap()
+    };
+
+    call("Increment");
+    call("Increment");
+
+    let reply = call("Get");
+    assert_eq!(reply.values().unwrap(),
+               Some(vec![Value::BasicValue(BasicValue::Uint32(2))]));
+    assert_eq!(state.get::<Counter>().unwrap().0, 2);
+    assert!(state.get::<String>().is_none());
+}
+
+#[test]
+fn signal_arguments() {
+    let iface = Interface::new()
+        .add_signal::<&str>("Reset", Signal::new())
+        .add_signal::<&str>("Changed", Signal::new().add_argument(Argument::new("count", "u")));
+
+    let path = "/net/benboeckel";
+    let name = "net.benboeckel.test.Interface";
+
+    let msg = iface.signal_message(path, name, "Reset", &[]).unwrap();
+    assert_eq!(msg.member(), Some("Reset".to_string()));
+
+    let stray = [Value::BasicValue(BasicValue::Uint32(1))];
+    assert!(iface.signal_message(path, name, "Reset", &stray).is_err());
+    assert!(iface.signal_message(path, name, "Changed", &stray).is_ok());
+    assert!(iface.signal_message(path, name, "Changed", &[]).is_err());
+    assert!(iface.signal_message(path, name, "Missing", &[]).is_err());
+}
+
+#[test]
+fn signal_body() {
+    let iface = Interface::new()
+        .add_signal::<&str>("Changed",
+                            Signal::new()
+                                .add_argument(Argument::new("name", "s"))
+                                .add_argument(Argument::new("count", "i")));
+
+    let args = [Value::BasicValue(BasicValue::String("abc".to_string())),
+                Value::BasicValue(BasicValue::Int32(5))];
+    let msg = iface.signal_message("/net/benboeckel",
+                                   "net.benboeckel.test.Interface",
+                                   "Changed",
+                                   &args)
+        .unwrap();
+
+    let mut expected = vec![];
+    if msg.message.big_endian {
+        expected.extend_from_slice(&[0, 0, 0, 3, b'a', b'b', b'c', 0, 0, 0, 0, 5]);
+    } else {
+        expected.extend_from_slice(&[3, 0, 0, 0, b'a', b'b', b'c', 0, 5, 0, 0, 0]);
+    }
+    assert_eq!(msg.message.body, expected);
+    assert_eq!(msg.values().unwrap().unwrap(), args.to_vec());
+
+    let swapped = [args[1].clone(), args[0].clone()];
+    let err = iface.signal_message("/net/benboeckel",
+                                   "net.benboeckel.test.Interface",
+                                   "Changed",
+                                   &swapped)
+        .unwrap_err();
+    assert_eq!(format!("{}", err),
+               "invalid arguments for signal Changed: expected 'si', got 'is'");
+}
+
+#[test]
+fn reserved_interfaces() {
+    let err = Interfaces::new()
+        .add_interface("org.freedesktop.DBus.Peer", Interface::new())
+        .err()
+        .unwrap();
+    assert_eq!(format!("{}", err),
+               "the org.freedesktop.DBus.Peer interface is reserved; it is added automatically \
+                when the interfaces are finalized");
+
+    let (ifaces, _children) = _finalize(Interfaces::new());
+    assert!(ifaces.add_interface("org.freedesktop.DBus.Properties", Interface::new()).is_err());
+}
+
+#[test]
+fn host_machine_id() {
+    let mid = machine_id();
+
+    assert_eq!(mid.len(), 32);
+    assert!(mid.chars().all(|c| c.is_digit(16)));
+}
+
+#[test]
+fn introspection_well_formed() {
+    let iface = Interface::new()
+        .add_method("Frobnicate",
+                    Method::new(|_| Ok(vec![]))
+                        .add_argument(Argument::new("amount", "u").describe("<how> \"much\""))
+                        .add_result(Argument::new("result", "a{sv}"))
+                        .annotate(Annotation::new("org.freedesktop.DBus.Deprecated", "true")))
+        .add_method("Reset", Method::new(|_| Ok(vec![])))
+        .add_signal::<&str>("Frobnicated",
+                            Signal::new().add_argument(Argument::new("amount", "u")));
+    let ifaces = Interfaces::new()
+        .add_interface("net.benboeckel.test.Interface", iface)
+        .unwrap()
+        .add_interface("net.benboeckel.test.Empty", Interface::new())
+        .unwrap();
+    let children = Rc::new(RefCell::new(vec!["child".to_string()]));
+
+    let ifaces = ifaces.finalize(&children).unwrap();
+
+    let xml = _introspect(&ifaces);
+
+    ::introspection::validate_introspection(&xml).unwrap();
+    let root = ::introspection::parse(&xml).unwrap();
+    assert_eq!(root.name, "node");
+    assert_eq!(root.children_named("node").map(|n| n.attribute("name")).collect::<Vec<_>>(),
+               vec![Some("child")]);
+
+    let iface = root.children_named("interface")
+        .find(|iface| iface.attribute("name") == Some("net.benboeckel.test.Interface"))
+        .unwrap();
+    let frobnicate = iface.children_named("method")
+        .find(|method| method.attribute("name") == Some("Frobnicate"))
+        .unwrap();
+    let args = frobnicate.children_named("arg").collect::<Vec<_>>();
+    assert_eq!(args.len(), 2);
+    assert_eq!(args[0].attribute("direction"), Some("in"));
+    assert_eq!(args[0].children[0].attribute("value"), Some("<how> \"much\""));
+    assert_eq!(args[1].attribute("type"), Some("a{sv}"));
+    assert_eq!(args[1].attribute("direction"), Some("out"));
+    assert_eq!(frobnicate.children_named("annotation").count(), 1);
+
+    let reset = iface.children_named("method")
+        .find(|method| method.attribute("name") == Some("Reset"))
+        .unwrap();
+    assert!(reset.children.is_empty());
+
+    let signal = iface.children_named("signal").next().unwrap();
+    assert_eq!(signal.attribute("name"), Some("Frobnicated"));
+
+    let xml = ifaces.introspect_interface("net.benboeckel.test.Empty").unwrap();
+    let root = ::introspection::parse(&xml).unwrap();
+    assert_eq!(root.children.len(), 1);
+    assert!(root.children[0].children.is_empty());
+}
+
+#[test]
+fn argument_signature_mismatch() {
+    let iface = Interface::new()
+        .add_method("Add",
+                    Method::new(|_| Ok(vec![]))
+                        .add_argument(Argument::new("name", "s"))
+                        .add_argument(Argument::new("amount", "u")))
+        .add_method("Reset", Method::new(|_| Ok(vec![])).strict_arguments());
+    assert_eq!(iface.methods["Add"].in_signature(), "su");
+    assert_eq!(iface.methods["Reset"].in_signature(), "");
+
+    let (ifaces, _children) = _with_interface("net.benboeckel.test.Interface", iface);
+
+    let error_name = |method, args: &[&Marshal]| {
+        let mut msg = _method_call("/", "net.benboeckel.test.Interface", method, args);
+        ifaces._dispatch(None, &mut msg).unwrap().error_name()
+    };
+    let invalid_args = Some("org.freedesktop.DBus.Error.InvalidArgs".to_string());
+
+    assert_eq!(error_name("Add", &[&"name", &5u32]), None);
+    assert_eq!(error_name("Add", &[&"name"]), invalid_args);
+    assert_eq!(error_name("Add", &[&"name", &5u32, &1u32]), None);
+    assert_eq!(error_name("Add", &[&"name", &"5"]), invalid_args);
+    assert_eq!(error_name("Reset", &[]), None);
+    assert_eq!(error_name("Reset", &[&1u32]), invalid_args);
+}
+
+#[test]
+fn method_rate_limit() {
+    let iface = Interface::new()
+        .add_method("Expensive", Method::new(|_| Ok(vec![])).rate_limit(2));
+    let (ifaces, _children) = _with_interface("net.benboeckel.test.Interface", iface);
+
+    let call = |sender: &str| {
+        let mut msg = _method_call_from(sender, "/", "net.benboeckel.test.Interface", "Expensive");
+        ifaces._dispatch(None, &mut msg).unwrap().error_name()
+    };
+    let limited = Some("org.freedesktop.DBus.Error.LimitsExceeded".to_string());
+
+    assert_eq!(call(":1.1"), None);
+    assert_eq!(call(":1.1"), None);
+    assert_eq!(call(":1.1"), limited);
+    assert_eq!(call(":1.2"), None);
+}
+
+#[test]
+fn introspection_member_names() {
+    struct Count;
+
+    impl PropertyReadHandler for Count {
+        fn get(&self) -> PropertyGetResult {
+            Ok(Value::BasicValue(BasicValue::Uint32(0)))
+        }
+    }
+
+    let iface = Interface::new()
+        .add_method("Reset", Method::new(|_| Ok(vec![])))
+        .add_property("Count", Property::new_ro(Signature("u".to_string()), Box::new(Count)))
+        .add_signal::<&str>("Changed", Signal::new());
+    let (ifaces, _children) = _with_interface("net.benboeckel.test.Interface", iface);
+
+    let xml = _introspect(&ifaces);
+
+    assert!(xml.contains(r#"<method name="Reset" />"#));
+    assert!(xml.contains(r#"<property name="Count" type="u" access="read" />"#));
+    assert!(xml.contains(r#"<signal name="Changed" />"#));
+    assert!(!xml.contains(r#"name="""#));
+}
+
+#[test]
+fn typed_property_signature() {
+    let name = || ReadWriteProperty::new(|| "name".to_string(), |_| ());
+
+    assert!(Property::new_rw_typed(Signature("s".to_string()), name()).is_ok());
+
+    let err = Property::new_rw_typed(Signature("i".to_string()), name())
+        .err()
+        .unwrap();
+    assert_eq!(format!("{}", err),
+               "invalid signature 'i': does not match the handler's type 's'");
+
+    let count = || ReadProperty::new(|| 0u32);
+    assert!(Property::new_ro_typed(Signature("u".to_string()), count()).is_ok());
+    assert!(Property::new_ro_typed(Signature("s".to_string()), count()).is_err());
+
+    let count = || WriteProperty::new(|_: u32| ());
+    assert!(Property::new_wo_typed(Signature("u".to_string()), count()).is_ok());
+    assert!(Property::new_wo_typed(Signature("s".to_string()), count()).is_err());
+}
+
+#[test]
+fn introspection_newlines() {
+    let iface = Interface::new()
+        .add_method("Add",
+                    Method::new(|_| Ok(vec![]))
+                        .add_argument(Argument::new("amount", "u").describe("The amount."))
+                        .annotate(Annotation::new("org.freedesktop.DBus.Deprecated", "true")));
+    let ifaces = Interfaces::new()
+        .add_interface("net.benboeckel.test.Interface", iface)
+        .unwrap();
+    let children = Rc::new(RefCell::new(vec!["child".to_string()]));
+
+    let ifaces = ifaces.finalize(&children).unwrap();
+
+    let xml = _introspect(&ifaces);
+
+    assert!(xml.contains('\n'));
+    assert!(!xml.contains("\\n"));
+    assert!(xml.lines().any(|line| line.trim_left().starts_with("<annotation")));
+
+    let xml = ifaces.introspect_interface("net.benboeckel.test.Interface").unwrap();
+    assert!(xml.contains('\n'));
+    assert!(!xml.contains("\\n"));
+}
+
+#[test]
+fn property_changes_per_interface() {
+    let msgs = PropertyChanges::new("/net/benboeckel")
+        .changed("net.benboeckel.test.First",
+                 "Count",
+                 Value::BasicValue(BasicValue::Uint32(1)))
+        .changed("net.benboeckel.test.Second",
+                 "Name",
+                 Value::BasicValue(BasicValue::String("name".to_string())))
+        .changed("net.benboeckel.test.First",
+                 "Total",
+                 Value::BasicValue(BasicValue::Uint32(2)))
+        .messages();
+    assert_eq!(msgs.len(), 2);
+
+    let check = |msg: &Message, iface: &str, expected: Vec<(&str, Value)>| {
+        assert_eq!(msg.path(), Some("/net/benboeckel".to_string()));
+        assert_eq!(msg.member(), Some("PropertiesChanged".to_string()));
+
+        let values = msg.values().unwrap().unwrap();
+        assert_eq!(values[0], Value::BasicValue(BasicValue::String(iface.to_string())));
+        if let Value::Dictionary(ref changed) = values[1] {
+            assert_eq!(changed.map.len(), expected.len());
+            for (name, value) in expected {
+                assert_eq!(changed.map[&BasicValue::String(name.to_string())], variant(value));
+            }
+        } else {
+            panic!("unexpected changed properties argument");
+        }
+    };
+
+    check(&msgs[0],
+          "net.benboeckel.test.First",
+          vec![("Count", Value::BasicValue(BasicValue::Uint32(1))),
+               ("Total", Value::BasicValue(BasicValue::Uint32(2)))]);
+    check(&msgs[1],
+          "net.benboeckel.test.Second",
+          vec![("Name", Value::BasicValue(BasicValue::String("name".to_string())))]);
+}
+
+#[test]
+fn introspection_escaping() {
+    let iface = Interface::new()
+        .add_method("Frobnicate",
+                    Method::new(|_| Ok(vec![]))
+                        .annotate(Annotation::new("net.benboeckel.test.Doc",
+                                                  r#"Frobs & "nicates" <things>"#)));
+    let (ifaces, _children) = _with_interface("net.benboeckel.test.Interface", iface);
+
+    let xml = ifaces.introspect_interface("net.benboeckel.test.Interface").unwrap();
+    assert!(xml.contains(concat!(r#"<annotation name="net.benboeckel.test.Doc" "#,
+                                 r#"value="Frobs &amp; &quot;nicates&quot; &lt;things&gt;" />"#)));
+    assert!(::introspection::parse(&xml).is_ok());
+}
+
+#[test]
+fn set_emits_properties_changed() {
+    use std::cell::Cell;
+
+    struct Count(Rc<Cell<u32>>);
+
+    impl PropertyReadWriteHandler for Count {
+        fn get(&self) -> PropertyGetResult {
+            Ok(Value::BasicValue(BasicValue::Uint32(self.0.get())))
+        }
+
+        fn set(&self, value: &Value) -> PropertySetResult {
+            match *value {
+                Value::BasicValue(BasicValue::Uint32(n)) => {
+                    self.0.set(n);
+                    Ok(())
+                },
+                _ => Err(Arguments::invalid_arguments()),
+            }
+        }
+    }
+
+    let count = Rc::new(Cell::new(0));
+    let sig = || Signature("u".to_string());
+    let emits = |value| Annotation::new("org.freedesktop.DBus.Property.EmitsChangedSignal", value);
+    let iface = Interface::new()
+        .add_property("Count", Property::new_rw(sig(), Box::new(Count(count.clone()))))
+        .add_property("Lazy",
+                      Property::new_rw(sig(), Box::new(Count(count.clone())))
+                          .annotate(emits("invalidates")))
+        .add_property("Quiet",
+                      Property::new_rw(sig(), Box::new(Count(count.clone())))
+                          .annotate(emits("false")));
+    let (ifaces, _children) = _with_interface("net.benboeckel.test.Interface", iface);
+
+    let set = |name: &str, value: u32| {
+        let value = Value::Variant(Variant::new(Value::BasicValue(BasicValue::Uint32(value)), "u"));
+        let mut msg = _method_call("/net/benboeckel",
+                                   "org.freedesktop.DBus.Properties",
+                                   "Set",
+                                   &[&"net.benboeckel.test.Interface", &name, &value]);
+        let (reply, signals) = ifaces._dispatch_with_signals(None, &mut msg).unwrap();
+        assert_eq!(reply.unwrap().error_name(), None);
+
+        signals.into_iter()
+            .map(|signal| {
+                assert_eq!(signal.path(), Some("/net/benboeckel".to_string()));
+                assert_eq!(signal.member(), Some("PropertiesChanged".to_string()));
+                signal.values().unwrap().unwrap()
+            })
+            .collect::<Vec<_>>()
+    };
+
+    let signals = set("Count", 7);
+    assert_eq!(count.get(), 7);
+    assert_eq!(signals.len(), 1);
+    assert_eq!(signals[0][0],
+               Value::BasicValue(BasicValue::String("net.benboeckel.test.Interface".to_string())));
+    if let Value::Dictionary(ref changed) = signals[0][1] {
+        assert_eq!(changed.map.len(), 1);
+        assert_eq!(changed.map[&BasicValue::String("Count".to_string())],
+                   variant(Value::BasicValue(BasicValue::Uint32(7))));
+    } else {
+        panic!("unexpected changed properties argument");
+    }
+
+    let signals = set("Lazy", 8);
+    assert_eq!(signals.len(), 1);
+    if let Value::Array(ref invalidated) = signals[0][2] {
+        assert_eq!(invalidated.objects,
+                   vec![Value::BasicValue(BasicValue::String("Lazy".to_string()))]);
+    } else {
+        panic!("unexpected invalidated properties argument");
+    }
+
+    assert!(set("Quiet", 9).is_empty());
+    assert_eq!(count.get(), 9);
+}
+
+#[test]
+fn cached_property() {
+    use std::cell::Cell;
+
+    let calls = Rc::new(Cell::new(0));
+    let counter = calls.clone();
+    let cached = CachedProperty::new(move || {
+        counter.set(counter.get() + 1);
+        Ok(Value::BasicValue(BasicValue::Uint32(counter.get())))
+    });
+    let iface = Interface::new()
+        .add_property("Expensive",
+                      Property::new_ro(Signature("u".to_string()), Box::new(cached.clone())));
+    let read = || {
+        iface.get_property("Expensive")
+            .and_then(|prop| prop._get())
+            .unwrap()
+            .ok()
+            .unwrap()
+    };
+
+    assert_eq!(read(), Value::BasicValue(BasicValue::Uint32(1)));
+    assert_eq!(read(), Value::BasicValue(BasicValue::Uint32(1)));
+    assert_eq!(calls.get(), 1);
+
+    let signal = cached.invalidate_signal("/net/benboeckel",
+                                          "net.benboeckel.test.Interface",
+                                          "Expensive");
+    assert_eq!(signal.member(), Some("PropertiesChanged".to_string()));
+    assert_eq!(calls.get(), 1);
+
+    assert_eq!(read(), Value::BasicValue(BasicValue::Uint32(2)));
+    assert_eq!(calls.get(), 2);
+}
+
+#[test]
+fn access_denied_by_sender() {
+    let iface = Interface::new()
+        .add_method("Restricted",
+                    Method::new(|m| {
+                        match m.sender() {
+                            Some(ref sender) if sender == ":1.1" => Ok(vec![]),
+                            _ => Err(ErrorMessage::access_denied("only :1.1 may call this")),
+                        }
+                    }));
+    let (ifaces, _children) = _with_interface("net.benboeckel.test.Interface", iface);
+
+    let call = |sender: &str| {
+        let mut msg = _method_call_from(sender, "/", "net.benboeckel.test.Interface", "Restricted");
+        ifaces._dispatch(None, &mut msg).unwrap().error_name()
+    };
+
+    assert_eq!(call(":1.1"), None);
+    assert_eq!(call(":1.2"),
+               Some("org.freedesktop.DBus.Error.AccessDenied".to_string()));
+}
+
+#[test]
+fn deferred_reply() {
+    let pending = Rc::new(RefCell::new(vec![]));
+
+    let queue = pending.clone();
+    let iface = Interface::new()
+        .add_method("Wait",
+                    Method::deferred(move |_, _, reply| queue.borrow_mut().push(reply))
+                        .add_result(Argument::new("result", "u")));
+    let (ifaces, _children) = _with_interface("net.benboeckel.test.Interface", iface);
+
+    let call = || {
+        let mut msg = _method_call("/", "net.benboeckel.test.Interface", "Wait", &[]);
+        msg.message.serial = 5;
+        assert!(ifaces._dispatch(None, &mut msg).is_none());
+    };
+
+    call();
+    call();
+    call();
+    assert_eq!(pending.borrow().len(), 3);
+
+    let reply = pending.borrow_mut().remove(0);
+    assert_eq!(reply.serial(), 5);
+    let msg = reply.into_message(Ok(vec![Value::BasicValue(BasicValue::Uint32(3))])).unwrap();
+    assert_eq!(msg.reply_serial(), Some(5));
+    assert_eq!(msg.values().unwrap().unwrap(),
+               vec![Value::BasicValue(BasicValue::Uint32(3))]);
+
+    let reply = pending.borrow_mut().remove(0);
+    assert!(reply.into_message(Ok(vec![])).is_err());
+
+    let reply = pending.borrow_mut().remove(0);
+    let msg = reply.into_message(Err(ErrorMessage::new("net.benboeckel.test.Error.Failed",
+                                                       "hardware went away")))
+        .unwrap();
+    assert_eq!(msg.error_name(), Some("net.benboeckel.test.Error.Failed".to_string()));
+}
+
+#[test]
+fn no_reply_expected() {
+    use message::FLAG_NO_REPLY_EXPECTED;
+
+    let calls = Rc::new(RefCell::new(0));
+
+    let count = calls.clone();
+    let iface = Interface::new()
+        .add_method("Poke",
+                    Method::new(move |_| {
+                        *count.borrow_mut() += 1;
+                        Ok(vec![])
+                    }));
+    let (ifaces, _children) = _with_interface("net.benboeckel.test.Interface", iface);
+
+    let call = |method: &str| {
+        let mut msg = _method_call("/", "net.benboeckel.test.Interface", method, &[]);
+        msg.message.flags |= FLAG_NO_REPLY_EXPECTED;
+        ifaces._dispatch(None, &mut msg)
+    };
+
+    assert!(call("Poke").is_none());
+    assert_eq!(*calls.borrow(), 1);
+    assert!(call("Missing").is_none());
+}
+
+#[test]
+fn object_manager_managed_objects() {
+    struct Fixed(u32);
+
+    impl PropertyReadHandler for Fixed {
+        fn get(&self) -> PropertyGetResult {
+            Ok(Value::BasicValue(BasicValue::Uint32(self.0)))
+        }
+    }
+
+    let child = |count| {
+        let iface = Interface::new()
+            .add_property("Count",
+                          Property::new_ro(Signature("u".to_string()), Box::new(Fixed(count))));
+        Interfaces::new()
+            .add_interface("net.benboeckel.test.Interface", iface)
+            .unwrap()
+            .finalize(&Rc::new(RefCell::new(vec![])))
+            .unwrap()
+    };
+    let first = child(1);
+    let second = child(2);
+
+    let objects = ManagedObjects::new();
+    objects.add("/net/benboeckel/first", &first);
+    objects.add("/net/benboeckel/second", &second);
+
+    let children = Rc::new(RefCell::new(vec!["first".to_string(), "second".to_string()]));
+    let ifaces = Interfaces::new()
+        .object_manager(objects)
+        .finalize(&children)
+        .unwrap();
+
+    let mut msg = _method_call("/net/benboeckel",
+                               "org.freedesktop.DBus.ObjectManager",
+                               "GetManagedObjects",
+                               &[]);
+    let reply = ifaces._dispatch(None, &mut msg).unwrap();
+    assert_eq!(reply.error_name(), None);
+
+    let values = reply.values().unwrap().unwrap();
+    let managed = match values[0] {
+        Value::Dictionary(ref managed) => managed,
+        _ => panic!("unexpected managed objects result"),
+    };
+    assert_eq!(managed.map.len(), 2);
+
+    let count = |path: &str| {
+        let path = BasicValue::ObjectPath(Path(path.to_string()));
+        let ifaces = match managed.map[&path] {
+            Value::Dictionary(ref ifaces) => ifaces,
+            _ => panic!("unexpected interfaces and properties"),
+        };
+        let props = match ifaces.map[&BasicValue::String("net.benboeckel.test.Interface"
+            .to_string())] {
+            Value::Dictionary(ref props) => props,
+            _ => panic!("unexpected properties"),
+        };
+        props.map[&BasicValue::String("Count".to_string())].clone()
+    };
+
+    assert_eq!(count("/net/benboeckel/first"),
+               variant(Value::BasicValue(BasicValue::Uint32(1))));
+    assert_eq!(count("/net/benboeckel/second"),
+               variant(Value::BasicValue(BasicValue::Uint32(2))));
+}
+
+#[test]
+fn reentrant_handler() {
+    let slot: Rc<RefCell<Option<Rc<Interfaces>>>> = Rc::new(RefCell::new(None));
+
+    let handler_slot = slot.clone();
+    let iface = Interface::new()
+        .add_method("Extend",
+                    Method::new(move |_| {
+                        let ifaces = handler_slot.borrow().clone().unwrap();
+                        ifaces.get_interfaces_and_properties();
+                        ifaces.add_interface("net.benboeckel.test.Extra", Interface::new())?;
+                        Ok(vec![])
+                    }));
+    let (ifaces, _children) = _with_interface("net.benboeckel.test.Interface", iface);
+    let ifaces = Rc::new(ifaces);
+    *slot.borrow_mut() = Some(ifaces.clone());
+
+    let mut msg = _method_call("/", "net.benboeckel.test.Interface", "Extend", &[]);
+    let reply = ifaces._dispatch(None, &mut msg).unwrap();
+    assert_eq!(reply.error_name(), None);
+    assert!(ifaces.names().contains(&"net.benboeckel.test.Extra".to_string()));
+
+    // Break the reference cycle.
+    *slot.borrow_mut() = None;
+}
+
+#[test]
+fn typed_property_handlers() {
+    use std::cell::Cell;
+
+    let count = Rc::new(Cell::new(1u32));
+    let getter = count.clone();
+    let setter = count.clone();
+    let handler = ReadWriteProperty::new(move || getter.get(), move |n| setter.set(n));
+
+    assert_eq!(PropertyReadWriteHandler::get(&handler).ok(),
+               Some(Value::BasicValue(BasicValue::Uint32(1))));
+    assert!(handler.set(&Value::BasicValue(BasicValue::Uint32(4))).is_ok());
+    assert_eq!(count.get(), 4);
+    assert!(handler.set(&Value::BasicValue(BasicValue::Uint32(5))).is_ok());
+    assert_eq!(count.get(), 5);
+    assert!(handler.set(&Value::BasicValue(BasicValue::String("five".to_string()))).is_err());
+    assert!(handler.set(&variant(Value::BasicValue(BasicValue::Uint32(6)))).is_err());
+    assert_eq!(count.get(), 5);
+
+    let property = Property::new_rw_typed(Signature("u".to_string()), handler);
+    assert!(property.is_ok());
+
+    let name = ReadProperty::new(|| "rust-bus".to_string());
+    assert_eq!(name.get().ok(),
+               Some(Value::BasicValue(BasicValue::String("rust-bus".to_string()))));
+}
+
+#[test]
+fn set_property_signature_mismatch() {
+    let iface = Interface::new()
+        .add_property("Count",
+                      Property::new_rw(Signature("u".to_string()),
+                                       Box::new(ReadWriteProperty::new(|| 0u32, |_| {
+                                           panic!("the handler should not be called")
+                                       }))));
+    let (ifaces, _children) = _with_interface("net.benboeckel.test.Interface", iface);
+
+    let value = variant(Value::BasicValue(BasicValue::String("five".to_string())));
+    let mut msg = _method_call("/",
+                               "org.freedesktop.DBus.Properties",
+                               "Set",
+                               &[&"net.benboeckel.test.Interface", &"Count", &value]);
+    let reply = ifaces._dispatch(None, &mut msg).unwrap();
+    assert_eq!(reply.error_name(),
+               Some("org.freedesktop.DBus.Error.InvalidArgs".to_string()));
+}
+
+#[test]
+fn checked_signatures() {
+    let add = |iface| Interfaces::new().add_interface("net.benboeckel.test.Interface", iface);
+    let with_arg = |sig: &str| {
+        Interface::new()
+            .add_method("Method",
+                        Method::new(|_| Ok(vec![])).add_argument(Argument::new("props", sig)))
+    };
+    let with_property = |sig: &str| {
+        Interface::new()
+            .add_property("Count",
+                          Property::new_ro(Signature(sig.to_string()),
+                                           Box::new(ReadProperty::new(|| 0u32))))
+    };
+    let with_signal = |sig: &str| {
+        Interface::new()
+            .add_signal::<&str>("Changed", Signal::new().add_argument(Argument::new("value", sig)))
+    };
+
+    assert!(add(with_arg("a{sv}")).is_ok());
+    assert!(add(with_arg("{sv}")).is_err());
+    assert!(add(with_property("u")).is_ok());
+    assert!(add(with_property("sv")).is_err());
+    assert!(add(with_property("a(s")).is_err());
+    assert!(add(with_signal("a")).is_err());
+
+    // Interfaces added after finalizing are checked as well.
+    let (ifaces, _children) = _finalize(Interfaces::new());
+    match *ifaces.add_interface("net.benboeckel.test.Interface", with_arg("{sv}"))
+        .unwrap_err()
+        .kind() {
+        ErrorKind::InvalidSignature(ref sig, _) => assert_eq!(sig, "{sv}"),
+        ref kind => panic!("unexpected error: {}", kind),
+    }
+    assert!(ifaces.names().iter().all(|name| name != "net.benboeckel.test.Interface"));
+}
+
+#[test]
+fn set_unwraps_variant() {
+    struct Record(Rc<RefCell<Vec<Value>>>);
+
+    impl PropertyWriteHandler for Record {
+        fn set(&self, value: &Value) -> PropertySetResult {
+            self.0.borrow_mut().push(value.clone());
+            Ok(())
+        }
+    }
+
+    let received = Rc::new(RefCell::new(vec![]));
+    let iface = Interface::new()
+        .add_property("Count",
+                      Property::new_wo(Signature("i".to_string()),
+                                       Box::new(Record(received.clone()))));
+    let (ifaces, _children) = _with_interface("net.benboeckel.test.Interface", iface);
+
+    let value = (-5i32).into_value().into_variant();
+    let mut msg = _method_call("/net/benboeckel",
+                               "org.freedesktop.DBus.Properties",
+                               "Set",
+                               &[&"net.benboeckel.test.Interface", &"Count", &value]);
+    let reply = ifaces._dispatch(None, &mut msg).unwrap();
+
+    assert_eq!(reply.error_name(), None);
+    assert_eq!(*received.borrow(), vec![Value::BasicValue(BasicValue::Int32(-5))]);
+}
+
+#[test]
+fn set_variant_property() {
+    struct Record(Rc<RefCell<Option<Value>>>);
+
+    impl PropertyWriteHandler for Record {
+        fn set(&self, value: &Value) -> PropertySetResult {
+            *self.0.borrow_mut() = Some(value.clone());
+            Ok(())
+        }
+    }
+
+    let received = Rc::new(RefCell::new(None));
+    let iface = Interface::new()
+        .add_property("Any",
+                      Property::new_wo(Signature("v".to_string()),
+                                       Box::new(Record(received.clone()))));
+    let (ifaces, _children) = _with_interface("net.benboeckel.test.Interface", iface);
+
+    // Only the variant of the `Set` call is unwrapped; the property's own variant remains.
+    let value = "five".into_value().into_variant();
+    let set = value.clone().into_variant();
+    let mut msg = _method_call("/",
+                               "org.freedesktop.DBus.Properties",
+                               "Set",
+                               &[&"net.benboeckel.test.Interface", &"Any", &set]);
+    let reply = ifaces._dispatch(None, &mut msg).unwrap();
+
+    assert_eq!(reply.error_name(), None);
+    assert_eq!(*received.borrow(), Some(value));
+}
+
+#[test]
+fn emits_changed_annotation() {
+    let value = || Box::new(ReadProperty::new(|| 0u32));
+    let iface = Interface::new()
+        .add_property("Version",
+                      Property::new_ro(Signature("u".to_string()), value())
+                          .emits_changed(EmitsChangedSignal::Const))
+        .add_property("Count",
+                      Property::new_ro(Signature("u".to_string()), value())
+                          .emits_changed(EmitsChangedSignal::Invalidates));
+    let (ifaces, _children) = _with_interface("net.benboeckel.test.Interface", iface);
+
+    let xml = ifaces.introspect_interface("net.benboeckel.test.Interface").unwrap();
+    let annotation = |value: &str| {
+        format!(concat!(r#"<annotation name="org.freedesktop.DBus.Property.EmitsChangedSignal" "#,
+                        r#"value="{}" />"#),
+                value)
+    };
+    let count = xml.find(r#"<property name="Count""#).unwrap();
+    let version = xml.find(r#"<property name="Version""#).unwrap();
+
+    assert!(count < version);
+    assert!(xml[count..version].contains(&annotation("invalidates")));
+    assert!(xml[version..].contains(&annotation("const")));
+}
+
+#[test]
+fn deprecated_members() {
+    let iface = Interface::new()
+        .add_method("Method", Method::new(|_| Ok(vec![])).deprecated())
+        .add_property("Property",
+                      Property::new_ro(Signature("u".to_string()),
+                                       Box::new(ReadProperty::new(|| 0u32)))
+                          .deprecated())
+        .add_signal::<&str>("Signal", Signal::new().deprecated());
+    let (ifaces, _children) = _with_interface("net.benboeckel.test.Interface", iface);
+
+    let xml = ifaces.introspect_interface("net.benboeckel.test.Interface").unwrap();
+    let annotation = r#"<annotation name="org.freedesktop.DBus.Deprecated" value="true" />"#;
+    let method = xml.find(r#"<method name="Method""#).unwrap();
+    let property = xml.find(r#"<property name="Property""#).unwrap();
+    let signal = xml.find(r#"<signal name="Signal""#).unwrap();
+
+    let mut starts = vec![method, property, signal];
+    starts.sort();
+    starts.push(xml.len());
+    for bounds in starts.windows(2) {
+        assert!(xml[bounds[0]..bounds[1]].contains(annotation));
+    }
+}
+
+#[test]
+fn error_with_args() {
+    let iface = Interface::new()
+        .add_method("Fail",
+                    Method::new(|_| {
+                        Err(ErrorMessage::with_args("net.benboeckel.test.Error.Failed",
+                                                    "it broke",
+                                                    vec![Value::BasicValue(BasicValue::Uint32(7))]))
+                    }));
+    let reply = _dispatch_to(iface, "/", "net.benboeckel.test.Interface", "Fail", &[]);
+
+    assert_eq!(reply.error_name(), Some("net.benboeckel.test.Error.Failed".to_string()));
+    assert_eq!(reply.values().unwrap().unwrap(),
+               vec![Value::BasicValue(BasicValue::String("it broke".to_string())),
+                    Value::BasicValue(BasicValue::Uint32(7))]);
+}
@@ -0,0 +1,612 @@
+// Distributed under the OSI-approved BSD 3-Clause License.
+// See accompanying LICENSE file for details.
+
+use error::*;
+
+use std::collections::btree_map::BTreeMap;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// An element in an introspection document.
+pub struct Element {
+    /// The name of the element.
+    pub name: String,
+    /// The attributes of the element, in document order.
+    pub attributes: Vec<(String, String)>,
+    /// The child elements of the element.
+    pub children: Vec<Element>,
+}
+
+impl Element {
+    /// Create a new element without attributes or children.
+    pub fn new<N>(name: N) -> Self
+        where N: ToString,
+    {
+        Element {
+            name: name.to_string(),
+            attributes: vec![],
+            children: vec![],
+        }
+    }
+
+    /// Add an attribute to the element.
+    pub fn with_attribute<K, V>(mut self, key: K, value: V) -> Self
+        where K: ToString,
+              V: ToString,
+    {
+        self.attributes.push((key.to_string(), value.to_string()));
+
+        self
+    }
+
+    /// Add a child element to the element.
+    pub fn with_child(mut self, child: Element) -> Self {
+        self.children.push(child);
+
+        self
+    }
+
+    /// Add child elements to the element.
+    pub fn with_children<I>(mut self, children: I) -> Self
+        where I: IntoIterator<Item = Element>,
+    {
+        self.children.extend(children);
+
+        self
+    }
+
+    /// Serialize the element as XML.
+    ///
+    /// Each element is written on its own line, indented by `unit` for each level of nesting.
+    /// Attribute values are escaped.
+    pub fn to_xml(&self, unit: &str) -> String {
+        let mut out = String::new();
+        self.write(&mut out, "", unit);
+
+        out
+    }
+
+    fn write(&self, out: &mut String, indent: &str, unit: &str) {
+        out.push_str(indent);
+        out.push('<');
+        out.push_str(&self.name);
+        for &(ref key, ref value) in &self.attributes {
+            out.push_str(&format!(r#" {}="{}""#, key, escape(value)));
+        }
+
+        if self.children.is_empty() {
+            out.push_str(" />\n");
+        } else {
+            out.push_str(">\n");
+
+            let child_indent = format!("{}{}", indent, unit);
+            for child in &self.children {
+                child.write(out, &child_indent, unit);
+            }
+
+            out.push_str(&format!("{}</{}>\n", indent, self.name));
+        }
+    }
+
+    /// The value of an attribute of the element.
+    pub fn attribute(&self, name: &str) -> Option<&str> {
+        self.attributes
+            .iter()
+            .find(|&&(ref key, _)| key == name)
+            .map(|&(_, ref value)| value.as_str())
+    }
+
+    /// The child elements with the given name.
+    pub fn children_named<'a>(&'a self, name: &'a str) -> Box<Iterator<Item = &'a Element> + 'a> {
+        Box::new(self.children.iter().filter(move |child| child.name == name))
+    }
+}
+
+struct Parser<'a> {
+    input: &'a str,
+    offset: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn error<T>(&self, desc: &str) -> Result<T> {
+        bail!(ErrorKind::InvalidXml(format!("{} at offset {}", desc, self.offset)))
+    }
+
+    fn rest(&self) -> &'a str {
+        &self.input[self.offset..]
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = self.rest();
+        self.offset += rest.len() - rest.trim_left().len();
+    }
+
+    fn skip_past(&mut self, end: &str) -> Result<()> {
+        match self.rest().find(end) {
+            Some(idx) => {
+                self.offset += idx + end.len();
+                Ok(())
+            },
+            None => self.error(&format!("missing '{}'", end)),
+        }
+    }
+
+    /// Skip over text, comments, processing instructions, and declarations.
+    fn skip_misc(&mut self) -> Result<()> {
+        loop {
+            match self.rest().find('<') {
+                Some(idx) => self.offset += idx,
+                None => {
+                    self.offset = self.input.len();
+                    return Ok(());
+                },
+            }
+
+            if self.rest().starts_with("<!--") {
+                self.skip_past("-->")?;
+            } else if self.rest().starts_with("<?") || self.rest().starts_with("<!") {
+                self.skip_past(">")?;
+            } else {
+                return Ok(());
+            }
+        }
+    }
+
+    fn name(&mut self) -> Result<String> {
+        let rest = self.rest();
+        let len = rest.find(|c: char| c.is_whitespace() || c == '/' || c == '>' || c == '=')
+            .unwrap_or_else(|| rest.len());
+        if len == 0 {
+            return self.error("expected a name");
+        }
+        self.offset += len;
+
+        Ok(rest[..len].to_string())
+    }
+
+    fn attribute_value(&mut self) -> Result<String> {
+        let quote = match self.rest().chars().next() {
+            Some(c @ '"') | Some(c @ '\'') => c,
+            _ => return self.error("expected a quoted attribute value"),
+        };
+        self.offset += 1;
+
+        match self.rest().find(quote) {
+            Some(idx) => {
+                let value = unescape(&self.rest()[..idx]);
+                self.offset += idx + 1;
+                Ok(value)
+            },
+            None => self.error("unterminated attribute value"),
+        }
+    }
+
+    fn element(&mut self) -> Result<Element> {
+        if !self.rest().starts_with('<') {
+            return self.error("expected an element");
+        }
+        self.offset += 1;
+
+        let name = self.name()?;
+        let mut attributes = vec![];
+
+        loop {
+            self.skip_whitespace();
+
+            if self.rest().starts_with("/>") {
+                self.offset += 2;
+
+                return Ok(Element {
+                    name: name,
+                    attributes: attributes,
+                    children: vec![],
+                });
+            } else if self.rest().starts_with('>') {
+                self.offset += 1;
+                break;
+            }
+
+            let key = self.name()?;
+            self.skip_whitespace();
+            if !self.rest().starts_with('=') {
+                return self.error("expected '='");
+            }
+            self.offset += 1;
+            self.skip_whitespace();
+            let value = self.attribute_value()?;
+
+            attributes.push((key, value));
+        }
+
+        let mut children = vec![];
+        loop {
+            self.skip_misc()?;
+
+            if self.rest().starts_with("</") {
+                self.offset += 2;
+                let end = self.name()?;
+                if end != name {
+                    return self.error(&format!("mismatched end tag '{}' for '{}'", end, name));
+                }
+                self.skip_whitespace();
+                if !self.rest().starts_with('>') {
+                    return self.error("expected '>'");
+                }
+                self.offset += 1;
+
+                return Ok(Element {
+                    name: name,
+                    attributes: attributes,
+                    children: children,
+                });
+            } else if self.rest().is_empty() {
+                return self.error(&format!("unterminated element '{}'", name));
+            }
+
+            children.push(self.element()?);
+        }
+    }
+}
+
+fn escape(value: &str) -> String {
+    value.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+fn unescape(value: &str) -> String {
+    value.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+/// Parse an introspection document into its root element.
+pub fn parse(xml: &str) -> Result<Element> {
+    let mut parser = Parser {
+        input: xml,
+        offset: 0,
+    };
+
+    parser.skip_misc()?;
+    let root = parser.element()?;
+    parser.skip_misc()?;
+
+    if !parser.rest().is_empty() {
+        return parser.error("trailing content");
+    }
+
+    Ok(root)
+}
+
+struct Validator {
+    violations: Vec<String>,
+}
+
+impl Validator {
+    fn violation(&mut self, path: &str, desc: &str) {
+        self.violations.push(format!("{}: {}", path, desc));
+    }
+
+    fn require_attribute(&mut self, path: &str, elem: &Element, name: &str) {
+        match elem.attribute(name) {
+            Some(value) if !value.is_empty() => (),
+            Some(_) => self.violation(path, &format!("empty '{}' attribute", name)),
+            None => self.violation(path, &format!("missing '{}' attribute", name)),
+        }
+    }
+
+    fn check_children(&mut self, path: &str, elem: &Element, allowed: &[&str]) {
+        for child in &elem.children {
+            let child_path = match child.attribute("name") {
+                Some(name) => format!("{}/{}[{}]", path, child.name, name),
+                None => format!("{}/{}", path, child.name),
+            };
+
+            if allowed.contains(&child.name.as_str()) {
+                self.element(&child_path, child);
+            } else {
+                self.violation(path, &format!("unexpected '{}' element", child.name));
+            }
+        }
+    }
+
+    fn element(&mut self, path: &str, elem: &Element) {
+        match elem.name.as_str() {
+            "node" => {
+                self.check_children(path, elem, &["interface", "node"]);
+            },
+            "interface" => {
+                self.require_attribute(path, elem, "name");
+                self.check_children(path, elem, &["method", "signal", "property", "annotation"]);
+            },
+            "method" | "signal" => {
+                self.require_attribute(path, elem, "name");
+                self.check_children(path, elem, &["arg", "annotation"]);
+
+                let allowed_directions: &[&str] = if elem.name == "method" {
+                    &["in", "out"]
+                } else {
+                    &["out"]
+                };
+                for arg in elem.children_named("arg") {
+                    if let Some(direction) = arg.attribute("direction") {
+                        if !allowed_directions.contains(&direction) {
+                            self.violation(path,
+                                           &format!("invalid argument direction '{}'",
+                                                    direction));
+                        }
+                    }
+                }
+            },
+            "arg" => {
+                self.require_attribute(path, elem, "type");
+                self.check_children(path, elem, &["annotation"]);
+            },
+            "property" => {
+                self.require_attribute(path, elem, "name");
+                self.require_attribute(path, elem, "type");
+                match elem.attribute("access") {
+                    Some("read") | Some("write") | Some("readwrite") => (),
+                    Some(access) => self.violation(path, &format!("invalid access '{}'", access)),
+                    None => self.violation(path, "missing 'access' attribute"),
+                }
+                self.check_children(path, elem, &["annotation"]);
+            },
+            "annotation" => {
+                self.require_attribute(path, elem, "name");
+                if elem.attribute("value").is_none() {
+                    self.violation(path, "missing 'value' attribute");
+                }
+                self.check_children(path, elem, &[]);
+            },
+            _ => self.violation(path, &format!("unexpected '{}' element", elem.name)),
+        }
+    }
+}
+
+/// Check that an introspection document follows the structure of the introspection format.
+///
+/// All violations are reported together in an `InvalidIntrospection` error.
+pub fn validate_introspection(xml: &str) -> Result<()> {
+    let root = parse(xml)?;
+    let mut validator = Validator {
+        violations: vec![],
+    };
+
+    if root.name == "node" {
+        validator.element("node", &root);
+    } else {
+        validator.violation(&root.name, "the root element must be 'node'");
+    }
+
+    if validator.violations.is_empty() {
+        Ok(())
+    } else {
+        bail!(ErrorKind::InvalidIntrospection(validator.violations))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+/// The kind of an interface member.
+pub enum MemberKind {
+    /// A method.
+    Method,
+    /// A property.
+    Property,
+    /// A signal.
+    Signal,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// A difference between two introspection documents.
+pub enum IntrospectionChange {
+    /// An interface was added.
+    InterfaceAdded(String),
+    /// An interface was removed.
+    InterfaceRemoved(String),
+    /// A member was added to an interface.
+    MemberAdded {
+        /// The interface of the member.
+        interface: String,
+        /// The kind of member.
+        kind: MemberKind,
+        /// The name of the member.
+        name: String,
+    },
+    /// A member was removed from an interface.
+    MemberRemoved {
+        /// The interface of the member.
+        interface: String,
+        /// The kind of member.
+        kind: MemberKind,
+        /// The name of the member.
+        name: String,
+    },
+    /// The signature of a member changed.
+    ///
+    /// Method signatures are described as `in -> out`, properties as `type (access)`, and signals
+    /// by their argument types.
+    SignatureChanged {
+        /// The interface of the member.
+        interface: String,
+        /// The kind of member.
+        kind: MemberKind,
+        /// The name of the member.
+        name: String,
+        /// The old signature.
+        old: String,
+        /// The new signature.
+        new: String,
+    },
+}
+
+type Members = BTreeMap<(MemberKind, String), String>;
+
+fn arg_types<'a, I>(args: I) -> String
+    where I: Iterator<Item = &'a Element>,
+{
+    args.filter_map(|arg| arg.attribute("type")).collect::<Vec<_>>().join("")
+}
+
+fn members(iface: &Element) -> Members {
+    iface.children
+        .iter()
+        .filter_map(|member| {
+            let kind_sig = match member.name.as_str() {
+                "method" => {
+                    let direction = |dir: &'static str| {
+                        move |arg: &&Element| arg.attribute("direction").unwrap_or("in") == dir
+                    };
+                    let in_args = arg_types(member.children_named("arg").filter(direction("in")));
+                    let out_args = arg_types(member.children_named("arg").filter(direction("out")));
+
+                    Some((MemberKind::Method, format!("{} -> {}", in_args, out_args)))
+                },
+                "property" => {
+                    Some((MemberKind::Property,
+                          format!("{} ({})",
+                                  member.attribute("type").unwrap_or(""),
+                                  member.attribute("access").unwrap_or(""))))
+                },
+                "signal" => Some((MemberKind::Signal, arg_types(member.children_named("arg")))),
+                _ => None,
+            };
+
+            kind_sig.map(|(kind, sig)| {
+                ((kind, member.attribute("name").unwrap_or("").to_string()), sig)
+            })
+        })
+        .collect()
+}
+
+fn interfaces(root: &Element) -> BTreeMap<String, Members> {
+    root.children_named("interface")
+        .map(|iface| (iface.attribute("name").unwrap_or("").to_string(), members(iface)))
+        .collect()
+}
+
+/// Compute the differences between two introspection documents.
+///
+/// Only the interfaces on the root node are compared.
+pub fn diff_introspection(old: &str, new: &str) -> Result<Vec<IntrospectionChange>> {
+    let old_ifaces = interfaces(&parse(old)?);
+    let new_ifaces = interfaces(&parse(new)?);
+    let mut changes = vec![];
+
+    for (name, old_members) in &old_ifaces {
+        let new_members = match new_ifaces.get(name) {
+            Some(members) => members,
+            None => {
+                changes.push(IntrospectionChange::InterfaceRemoved(name.clone()));
+                continue;
+            },
+        };
+
+        for (&(kind, ref member), old_sig) in old_members {
+            match new_members.get(&(kind, member.clone())) {
+                Some(new_sig) => {
+                    if old_sig != new_sig {
+                        changes.push(IntrospectionChange::SignatureChanged {
+                            interface: name.clone(),
+                            kind: kind,
+                            name: member.clone(),
+                            old: old_sig.clone(),
+                            new: new_sig.clone(),
+                        });
+                    }
+                },
+                None => {
+                    changes.push(IntrospectionChange::MemberRemoved {
+                        interface: name.clone(),
+                        kind: kind,
+                        name: member.clone(),
+                    })
+                },
+            }
+        }
+
+        for &(kind, ref member) in new_members.keys() {
+            if !old_members.contains_key(&(kind, member.clone())) {
+                changes.push(IntrospectionChange::MemberAdded {
+                    interface: name.clone(),
+                    kind: kind,
+                    name: member.clone(),
+                });
+            }
+        }
+    }
+
+    for name in new_ifaces.keys() {
+        if !old_ifaces.contains_key(name) {
+            changes.push(IntrospectionChange::InterfaceAdded(name.clone()));
+        }
+    }
+
+    Ok(changes)
+}
+
+#[test]
+fn diff_documents() {
+    let old = r#"<!DOCTYPE node PUBLIC "-//freedesktop//DTD D-BUS Object Introspection 1.0//EN"
+ "http://www.freedesktop.org/standards/dbus/1.0/introspect.dtd">
+<node>
+ <interface name="net.benboeckel.test.Interface">
+  <property name="Count" type="u" access="read" />
+  <method name="Reset" />
+ </interface>
+</node>
+"#;
+    let new = r#"<node>
+ <interface name="net.benboeckel.test.Interface">
+  <property name="Count" type="t" access="read" />
+  <method name="Reset" />
+  <method name="Add">
+   <arg name="amount" type="u" direction="in" />
+  </method>
+ </interface>
+</node>
+"#;
+
+    let changes = diff_introspection(old, new).unwrap();
+    assert_eq!(changes,
+               vec![IntrospectionChange::SignatureChanged {
+                        interface: "net.benboeckel.test.Interface".to_string(),
+                        kind: MemberKind::Property,
+                        name: "Count".to_string(),
+                        old: "u (read)".to_string(),
+                        new: "t (read)".to_string(),
+                    },
+                    IntrospectionChange::MemberAdded {
+                        interface: "net.benboeckel.test.Interface".to_string(),
+                        kind: MemberKind::Method,
+                        name: "Add".to_string(),
+                    }]);
+}
+
+#[test]
+fn validate_documents() {
+    let broken = concat!("<node>\n",
+                         " <interface name=\"net.benboeckel.test.Interface\">\n",
+                         "  <method name=\"\">\n",
+                         "   <arg name=\"amount\" type=\"u\" direction=\"in\" />\n",
+                         "  </method>\n",
+                         "  <property name=\"\" type=\"u\" access=\"read\" />\n",
+                         " </interface>\n",
+                         "</node>\n");
+    let err = validate_introspection(broken).unwrap_err();
+    assert_eq!(format!("{}", err),
+               concat!("invalid introspection document: ",
+                       "node/interface[net.benboeckel.test.Interface]/method[]: ",
+                       "empty 'name' attribute; ",
+                       "node/interface[net.benboeckel.test.Interface]/property[]: ",
+                       "empty 'name' attribute"));
+
+    let fixed = broken.replace(r#"method name="""#, r#"method name="Add""#)
+        .replace(r#"property name="""#, r#"property name="Count""#);
+    validate_introspection(&fixed).unwrap();
+
+    assert!(validate_introspection(r#"<node><signal name="Changed" /></node>"#).is_err());
+}
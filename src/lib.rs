@@ -21,6 +21,7 @@ mod crates {
     pub extern crate core;
     pub extern crate dbus_bytestream;
     pub extern crate dbus_serialize;
+    pub extern crate libc;
     pub extern crate machine_id;
 }
 
@@ -28,14 +29,28 @@ mod arguments;
 mod connection;
 mod error;
 mod interface;
+mod introspection;
+mod match_rule;
 mod message;
+mod names;
 mod object;
+mod pool;
 mod runner;
 mod server;
+mod signature;
 mod target;
+mod transport;
 mod value;
+mod wire;
 
+pub use arguments::Arguments;
+pub use arguments::FromSignalBody;
+pub use arguments::Reply;
+pub use connection::BusType;
+pub use connection::call;
+pub use connection::DEFAULT_CALL_TIMEOUT_SECS;
 pub use connection::Connection;
+pub use connection::ErrorLogger;
 pub use connection::ReleaseNameReply;
 pub use connection::RequestNameFlags;
 pub use connection::{ALLOW_REPLACEMENT, REPLACE_EXISTING, DO_NOT_QUEUE};
@@ -43,25 +58,52 @@ pub use connection::RequestNameReply;
 pub use error::Error;
 pub use interface::Annotation;
 pub use interface::Argument;
+pub use interface::CachedProperty;
+pub use interface::CallContext;
 pub use interface::ChildrenList;
+pub use interface::DeferredHandler;
+pub use interface::DeferredReply;
+pub use interface::EmitsChangedSignal;
 pub use interface::ErrorMessage;
 pub use interface::Interface;
 pub use interface::Interfaces;
+pub use interface::ManagedObjects;
 pub use interface::InterfacesBuilder;
 pub use interface::Method;
 pub use interface::MethodHandler;
 pub use interface::MethodResult;
+pub use interface::machine_id;
+pub use interface::not_supported;
+pub use interface::PropertiesHandler;
+pub use interface::PropertyChanges;
+pub use interface::PropertyDebouncer;
 pub use interface::Property;
 pub use interface::PropertyGetResult;
 pub use interface::PropertyReadHandler;
 pub use interface::PropertyReadWriteHandler;
 pub use interface::PropertySetResult;
 pub use interface::PropertyWriteHandler;
+pub use interface::ReadProperty;
+pub use interface::ReadWriteProperty;
+pub use interface::SharedState;
 pub use interface::Signal;
+pub use interface::StreamHandler;
+pub use interface::StreamResult;
+pub use interface::ValidationError;
+pub use interface::WriteProperty;
+pub use introspection::diff_introspection;
+pub use introspection::validate_introspection;
+pub use introspection::IntrospectionChange;
+pub use introspection::MemberKind;
+pub use match_rule::MatchRule;
 pub use message::Message;
 pub use message::MessageType;
 pub use object::Object;
+pub use pool::ValuePool;
 pub use runner::Runner;
 pub use server::Server;
+pub use server::SignalSubscriber;
+pub use server::SignalSubscription;
 pub use target::Target;
 pub use value::*;
+pub use wire::ByteOrder;
@@ -0,0 +1,150 @@
+// Distributed under the OSI-approved BSD 3-Clause License.
+// See accompanying LICENSE file for details.
+
+use message::MessageType;
+
+use std::fmt;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+/// A rule describing messages to be routed to a connection by the bus.
+///
+/// The rule syntax is documented in the [D-Bus
+/// specification](https://dbus.freedesktop.org/doc/dbus-specification.html#message-bus-routing-match-rules).
+pub struct MatchRule {
+    message_type: Option<&'static str>,
+    sender: Option<String>,
+    interface: Option<String>,
+    member: Option<String>,
+    path: Option<String>,
+    path_namespace: Option<String>,
+    arg0: Option<String>,
+}
+
+impl MatchRule {
+    /// Create a new rule which matches all messages.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Only match messages of the given type.
+    pub fn message_type(mut self, message_type: MessageType) -> Self {
+        self.message_type = match message_type {
+            MessageType::Error => Some("error"),
+            MessageType::Invalid => None,
+            MessageType::MethodCall => Some("method_call"),
+            MessageType::MethodReturn => Some("method_return"),
+            MessageType::Signal => Some("signal"),
+        };
+
+        self
+    }
+
+    /// Only match messages sent by the given bus name.
+    pub fn sender<S>(mut self, sender: S) -> Self
+        where S: ToString,
+    {
+        self.sender = Some(sender.to_string());
+
+        self
+    }
+
+    /// Only match messages for the given interface.
+    pub fn interface<I>(mut self, interface: I) -> Self
+        where I: ToString,
+    {
+        self.interface = Some(interface.to_string());
+
+        self
+    }
+
+    /// Only match messages for the given method or signal name.
+    pub fn member<M>(mut self, member: M) -> Self
+        where M: ToString,
+    {
+        self.member = Some(member.to_string());
+
+        self
+    }
+
+    /// Only match messages for the given object path.
+    pub fn path<P>(mut self, path: P) -> Self
+        where P: ToString,
+    {
+        self.path = Some(path.to_string());
+
+        self
+    }
+
+    /// Only match messages for the given object path or any path underneath it.
+    pub fn path_namespace<P>(mut self, path: P) -> Self
+        where P: ToString,
+    {
+        self.path_namespace = Some(path.to_string());
+
+        self
+    }
+
+    /// Only match messages with the given string as their first argument.
+    pub fn arg0<A>(mut self, arg0: A) -> Self
+        where A: ToString,
+    {
+        self.arg0 = Some(arg0.to_string());
+
+        self
+    }
+}
+
+/// Quote a value for use in a match rule.
+///
+/// Values are wrapped in single quotes, so commas within them are literal. Single quotes cannot
+/// be escaped within a quoted value, so the quoting is ended, the quote is escaped with a
+/// backslash, and the quoting is started again.
+fn quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', r"'\''"))
+}
+
+impl fmt::Display for MatchRule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let message_type = self.message_type.map(|t| ("type", t));
+        let keys = [("sender", &self.sender),
+                    ("interface", &self.interface),
+                    ("member", &self.member),
+                    ("path", &self.path),
+                    ("path_namespace", &self.path_namespace),
+                    ("arg0", &self.arg0)];
+
+        let rule = message_type.into_iter()
+            .chain(keys.iter()
+                .filter_map(|&(key, value)| value.as_ref().map(|value| (key, value.as_str()))))
+            .map(|(key, value)| format!("{}={}", key, quote(value)))
+            .collect::<Vec<_>>()
+            .join(",");
+
+        write!(f, "{}", rule)
+    }
+}
+
+#[test]
+fn signal_subscription() {
+    let rule = MatchRule::new()
+        .message_type(MessageType::Signal)
+        .sender("org.freedesktop.DBus")
+        .interface("org.freedesktop.DBus")
+        .member("NameOwnerChanged")
+        .path("/org/freedesktop/DBus")
+        .arg0("net.benboeckel.test.rustbus");
+
+    assert_eq!(rule.to_string(),
+               "type='signal',sender='org.freedesktop.DBus',interface='org.freedesktop.DBus',\
+                member='NameOwnerChanged',path='/org/freedesktop/DBus',\
+                arg0='net.benboeckel.test.rustbus'");
+}
+
+#[test]
+fn quoted_values() {
+    assert_eq!(MatchRule::new().to_string(), "");
+    assert_eq!(MatchRule::new().arg0("it's, here").to_string(),
+               r"arg0='it'\''s, here'");
+    assert_eq!(MatchRule::new().path_namespace("/net/benboeckel").to_string(),
+               "path_namespace='/net/benboeckel'");
+}
@@ -2,10 +2,63 @@
 // See accompanying LICENSE file for details.
 
 use crates::dbus_bytestream::message;
-use crates::dbus_serialize::types::Variant;
+use crates::dbus_serialize::types::{Array, Path, Signature, Struct, Variant};
+use crates::libc;
 
 use error::*;
-use value::{BasicValue, Marshal, Value};
+use pool::ValuePool;
+use signature;
+use value::{ArrayStream, BasicValue, Marshal, Value};
+use wire::{self, ByteOrder, Demarshaller};
+
+use std::io;
+use std::mem;
+use std::os::unix::io::RawFd;
+use std::rc::Rc;
+
+/// The signature of a message header.
+const HEADER_SIGNATURE: &'static str = "(yyyyuua(yv))";
+/// The flag indicating that the sender does not expect a reply.
+pub const FLAG_NO_REPLY_EXPECTED: u8 = 0x1;
+/// The header fields defined by the specification.
+const HEADER_FIELDS: ::std::ops::Range<u8> = 1..10;
+
+#[derive(Debug, Default)]
+/// The file descriptors attached to a message.
+struct Fds {
+    fds: Vec<RawFd>,
+    /// Whether the file descriptors are closed along with the message.
+    owned: bool,
+}
+
+impl Drop for Fds {
+    fn drop(&mut self) {
+        if self.owned {
+            for fd in self.fds.drain(..) {
+                unsafe {
+                    libc::close(fd);
+                }
+            }
+        }
+    }
+}
+
+/// An argument which has already been marshaled into the body of a message.
+struct Marshaled {
+    signature: String,
+    bytes: Vec<u8>,
+}
+
+impl Marshal for Marshaled {
+    fn dbus_encode(&self, buf: &mut Vec<u8>) -> usize {
+        buf.extend_from_slice(&self.bytes);
+        self.bytes.len()
+    }
+
+    fn get_type(&self) -> String {
+        self.signature.clone()
+    }
+}
 
 #[derive(Debug)]
 /// A message to communicate on the D-Bus.
@@ -13,6 +66,9 @@ pub struct Message {
     #[doc(hidden)]
     // This is used inside of the implementation, but should not be fully public.
     pub message: message::Message,
+
+    fds: Fds,
+    pool: Option<Rc<ValuePool>>,
 }
 
 /// The type of a message.
@@ -34,46 +90,163 @@ impl Message {
     pub fn new(message: message::Message) -> Self {
         Message {
             message: message,
+
+            fds: Fds::default(),
+            pool: None,
         }
     }
 
-    /// Create a call to a method.
-    pub fn new_method_call(dest: &str, path: &str, iface: &str, method: &str) -> Self {
+    /// Create a message which owns file descriptors received along with it.
+    ///
+    /// The file descriptors are closed when the message is dropped unless they have been taken
+    /// using `take_fds`.
+    pub fn new_with_fds(message: message::Message, fds: Vec<RawFd>) -> Self {
         Message {
-            message: message::create_method_call(dest, path, iface, method),
+            message: message,
+
+            fds: Fds {
+                fds: fds,
+                owned: true,
+            },
+            pool: None,
+        }
+    }
+
+    /// Copy the message.
+    ///
+    /// The file descriptors attached to the message are duplicated and owned by the copy.
+    pub fn try_clone(&self) -> Result<Self> {
+        let mut copy = Self::new_with_fds(self.message.clone(), vec![]);
+        copy.pool = self.pool.clone();
+
+        for &fd in &self.fds.fds {
+            let dup = unsafe { libc::fcntl(fd, libc::F_DUPFD_CLOEXEC, 0) };
+            if dup < 0 {
+                return Err(io::Error::last_os_error()).chain_err(|| {
+                    ErrorKind::Transport("failed to duplicate a file descriptor".to_string())
+                });
+            }
+
+            copy.fds.fds.push(dup);
         }
+
+        Ok(copy)
+    }
+
+    #[cfg(any(test, feature = "testing"))]
+    /// Create a message of the given type with arbitrary header fields.
+    ///
+    /// Header fields are given as pairs of the field code and its value as documented in the
+    /// [D-Bus specification](https://dbus.freedesktop.org/doc/dbus-specification.html#message-protocol-header-fields).
+    ///
+    /// This is intended for testing code which inspects messages without a bus.
+    pub fn with_headers(message_type: MessageType, headers: Vec<(u8, Value)>) -> Self {
+        let mut message = message::Message::new();
+        message.message_type = match message_type {
+            MessageType::Error => message::MESSAGE_TYPE_ERROR,
+            MessageType::Invalid => message::MESSAGE_TYPE_INVALID,
+            MessageType::MethodCall => message::MESSAGE_TYPE_METHOD_CALL,
+            MessageType::MethodReturn => message::MESSAGE_TYPE_METHOD_RETURN,
+            MessageType::Signal => message::MESSAGE_TYPE_SIGNAL,
+        };
+
+        Self::new(headers.into_iter().fold(message, |message, (code, value)| {
+            let signature = value.get_signature().to_string();
+            message.add_header(code, Variant::new(value, &signature))
+        }))
+    }
+
+    /// Create a call to a method.
+    pub fn new_method_call(dest: &str, path: &str, iface: &str, method: &str) -> Self {
+        Self::new(message::create_method_call(dest, path, iface, method))
     }
 
     /// Create a signal message.
     pub fn new_signal(path: &str, iface: &str, method: &str) -> Self {
-        Message {
-            message: message::create_signal(path, iface, method),
-        }
+        Self::new(message::create_signal(path, iface, method))
     }
 
     /// Create an error message.
     pub fn error_message(&self, name: &str) -> Self {
-        Message {
-            message: message::create_error(name, self.message.serial),
-        }
+        Self::new(message::create_error(name, self.message.serial))
     }
 
     /// Create a message which is a return value for the current message.
     ///
     /// This is used so that the return value is associated with the method call message.
     pub fn return_message(&self) -> Self {
-        Message {
-            message: message::create_method_return(self.message.serial),
-        }
+        Self::new(message::create_method_return(self.message.serial))
     }
 
     /// Add an argument to the message.
     pub fn add_argument(self, arg: &Marshal) -> Self {
         Message {
             message: self.message.add_arg(arg),
+            fds: self.fds,
+            pool: self.pool,
         }
     }
 
+    /// Add a streamed array argument to the message.
+    ///
+    /// The elements are marshaled in the byte order of the message as they are produced. Fails
+    /// if an element does not match the element signature of the stream.
+    pub fn add_stream(self, stream: ArrayStream) -> Result<Self> {
+        // Marshal at the same alignment the argument will have within the body.
+        let offset = self.message.body.len() % 8;
+        let mut bytes = vec![0; offset];
+        let signature = stream.signature();
+        stream.marshal(self._byte_order(), &mut bytes)?;
+        bytes.drain(..offset);
+
+        Ok(self.add_argument(&Marshaled {
+            signature: signature,
+            bytes: bytes,
+        }))
+    }
+
+    /// Use buffers from the given pool when unpacking the arguments of the message.
+    ///
+    /// This is used by `Arguments::new`; runners attach their pool (see
+    /// `Runner::set_value_pool`) to the messages they receive.
+    pub fn with_value_pool(mut self, pool: Rc<ValuePool>) -> Self {
+        self.pool = Some(pool);
+
+        self
+    }
+
+    /// The pool used to unpack the arguments of the message.
+    pub fn value_pool(&self) -> Option<Rc<ValuePool>> {
+        self.pool.clone()
+    }
+
+    /// Attach a file descriptor to the message.
+    ///
+    /// Arguments of type `h` refer to attached file descriptors by their index in the order they
+    /// were attached. The file descriptor is still owned by the caller and must stay open until
+    /// the message has been sent.
+    pub fn add_fd(mut self, fd: RawFd) -> Self {
+        self.fds.fds.push(fd);
+
+        self
+    }
+
+    /// The file descriptors attached to the message.
+    pub fn fds(&self) -> &[RawFd] {
+        &self.fds.fds
+    }
+
+    /// Take ownership of the file descriptors attached to the message.
+    ///
+    /// File descriptors received with a message are closed when it is dropped unless they are
+    /// taken; once taken, closing them is the responsibility of the caller. Later calls return an
+    /// empty list so that the same descriptors are not handed out (and closed) twice.
+    ///
+    /// Arguments of type `h` are extracted as `u32` indices into the returned list.
+    pub fn take_fds(&mut self) -> Vec<RawFd> {
+        mem::replace(&mut self.fds.fds, vec![])
+    }
+
     /// The type of the message.
     pub fn message_type(&self) -> MessageType {
         match self.message.message_type {
@@ -86,10 +259,11 @@ impl Message {
     }
 
     fn _extract_string(v: &Variant) -> Option<String> {
-        if let Value::BasicValue(BasicValue::String(ref s)) = *v.object {
-            Some(s.clone())
-        } else {
-            None
+        match *v.object {
+            Value::BasicValue(BasicValue::String(ref s)) |
+            Value::BasicValue(BasicValue::ObjectPath(Path(ref s))) |
+            Value::BasicValue(BasicValue::Signature(Signature(ref s))) => Some(s.clone()),
+            _ => None,
         }
     }
 
@@ -113,9 +287,410 @@ impl Message {
         Self::_get_header_string(&self.message, message::HEADER_FIELD_MEMBER)
     }
 
+    /// The name of the error for error messages.
+    pub fn error_name(&self) -> Option<String> {
+        Self::_get_header_string(&self.message, message::HEADER_FIELD_ERROR_NAME)
+    }
+
+    /// The unique name of the connection which sent the message.
+    ///
+    /// Messages received from peer-to-peer connections have no sender.
+    pub fn sender(&self) -> Option<String> {
+        Self::_get_header_string(&self.message, message::HEADER_FIELD_SENDER)
+    }
+
+    /// The bus name the message is destined for.
+    pub fn destination(&self) -> Option<String> {
+        Self::_get_header_string(&self.message, message::HEADER_FIELD_DESTINATION)
+    }
+
+    /// The serial of the message.
+    ///
+    /// Messages are assigned a serial when they are sent.
+    pub fn serial(&self) -> u32 {
+        self.message.serial
+    }
+
+    /// Whether the sender expects a reply to the message.
+    ///
+    /// Method calls with the `NO_REPLY_EXPECTED` flag must not be replied to, not even with an
+    /// error.
+    pub fn expects_reply(&self) -> bool {
+        self.message.flags & FLAG_NO_REPLY_EXPECTED == 0
+    }
+
+    /// The serial of the message this message is a reply to.
+    pub fn reply_serial(&self) -> Option<u32> {
+        self.message
+            .get_header(message::HEADER_FIELD_REPLY_SERIAL)
+            .and_then(|v| {
+                if let Value::BasicValue(BasicValue::Uint32(serial)) = *v.object {
+                    Some(serial)
+                } else {
+                    None
+                }
+            })
+    }
+
+    fn _byte_order(&self) -> ByteOrder {
+        if self.message.big_endian {
+            ByteOrder::BigEndian
+        } else {
+            ByteOrder::LittleEndian
+        }
+    }
+
+    /// Serialize the complete message (header and body) into the wire format.
+    ///
+    /// This is intended for capturing messages for debugging; see `from_debug_bytes`. Attached
+    /// file descriptors are not included.
+    pub fn to_debug_bytes(&self) -> Vec<u8> {
+        let order = self._byte_order();
+        let endian = match order {
+            ByteOrder::LittleEndian => b'l',
+            ByteOrder::BigEndian => b'B',
+        };
+        let fields = HEADER_FIELDS.filter_map(|code| {
+                self.message.get_header(code).map(|value| {
+                    Value::Struct(Struct {
+                        objects: vec![
+                            Value::BasicValue(BasicValue::Byte(code)),
+                            Value::Variant(value.clone()),
+                        ],
+                        signature: Signature("(yv)".to_string()),
+                    })
+                })
+            })
+            .collect();
+        let header = Value::Struct(Struct {
+            objects: vec![
+                Value::BasicValue(BasicValue::Byte(endian)),
+                Value::BasicValue(BasicValue::Byte(self.message.message_type)),
+                Value::BasicValue(BasicValue::Byte(self.message.flags)),
+                Value::BasicValue(BasicValue::Byte(1)),
+                Value::BasicValue(BasicValue::Uint32(self.message.body.len() as u32)),
+                Value::BasicValue(BasicValue::Uint32(self.message.serial)),
+                Value::Array(Array::new_with_sig(fields, "a(yv)".to_string())),
+            ],
+            signature: Signature(HEADER_SIGNATURE.to_string()),
+        });
+
+        let mut buf = vec![];
+        if let Err(err) = wire::marshal(&header, order, &mut buf) {
+            panic!("internal error: failed to marshal a message header: {}", err)
+        }
+        while buf.len() % 8 != 0 {
+            buf.push(0);
+        }
+        buf.extend_from_slice(&self.message.body);
+
+        buf
+    }
+
+    /// Reconstruct a message from the output of `to_debug_bytes`.
+    pub fn from_debug_bytes(bytes: &[u8]) -> Result<Self> {
+        let order = match bytes.first() {
+            Some(&b'l') => ByteOrder::LittleEndian,
+            Some(&b'B') => ByteOrder::BigEndian,
+            _ => bail!(ErrorKind::InvalidWireData("unknown byte order".to_string())),
+        };
+
+        let header_type = signature::parse_single(HEADER_SIGNATURE)?;
+        let mut demarshaller = Demarshaller::new(bytes, order);
+        let header = match demarshaller.read(&header_type)? {
+            Value::Struct(header) => header.objects,
+            _ => panic!("internal error: message header is not a structure?!"),
+        };
+
+        let byte = |value: &Value| {
+            match *value {
+                Value::BasicValue(BasicValue::Byte(b)) => b,
+                _ => panic!("internal error: expected a byte in the message header?!"),
+            }
+        };
+        let uint32 = |value: &Value| {
+            match *value {
+                Value::BasicValue(BasicValue::Uint32(n)) => n,
+                _ => panic!("internal error: expected a uint32 in the message header?!"),
+            }
+        };
+
+        let body_start = (demarshaller.offset() + 7) / 8 * 8;
+        let body_len = uint32(&header[4]) as usize;
+        if bytes.len() < body_start || bytes.len() - body_start != body_len {
+            bail!(ErrorKind::InvalidWireData(format!("expected a body of {} bytes", body_len)));
+        }
+
+        let mut message = message::Message::new();
+        message.big_endian = order == ByteOrder::BigEndian;
+        message.message_type = byte(&header[1]);
+        message.flags = byte(&header[2]);
+        message.serial = uint32(&header[5]);
+        message.body = bytes[body_start..].to_vec();
+
+        if let Value::Array(ref fields) = header[6] {
+            for field in &fields.objects {
+                if let Value::Struct(ref field) = *field {
+                    if let Value::Variant(ref value) = field.objects[1] {
+                        message = message.add_header(byte(&field.objects[0]), value.clone());
+                    }
+                }
+            }
+        }
+
+        Ok(Self::new(message))
+    }
+
     /// Unpack the argument values stored within the message.
+    ///
+    /// Containers within the message may not be nested more than `wire::MAX_DEPTH` levels deep.
     pub fn values(&self) -> Result<Option<Vec<Value>>> {
-        self.message.get_body()
-            .map_err(|err| ErrorKind::ExtractArguments(err).into())
+        self.values_with_max_depth(wire::MAX_DEPTH)
+    }
+
+    /// Unpack the argument values stored within the message with a limit on how deeply
+    /// containers may be nested.
+    ///
+    /// Messages from untrusted peers may contain deeply nested variants in an attempt to exhaust
+    /// the stack while unpacking them. The nesting is checked as the values are unpacked.
+    pub fn values_with_max_depth(&self, max_depth: usize) -> Result<Option<Vec<Value>>> {
+        match Self::_get_header_string(&self.message, message::HEADER_FIELD_SIGNATURE) {
+            Some(signature) => {
+                wire::demarshal_with_max_depth(&signature,
+                                               &self.message.body,
+                                               self._byte_order(),
+                                               max_depth)
+                    .map(Some)
+            },
+            None => Ok(None),
+        }
+    }
+
+    /// The signature of the arguments of the message.
+    ///
+    /// This is read from the header of the message, so the arguments are not unpacked. Messages
+    /// without arguments have an empty signature.
+    pub fn body_signature(&self) -> String {
+        Self::_get_header_string(&self.message, message::HEADER_FIELD_SIGNATURE)
+            .unwrap_or_else(String::new)
+    }
+
+    /// Unpack the argument values stored within the message using buffers from a pool.
+    ///
+    /// Once the values are no longer needed, their buffers may be returned using
+    /// `ValuePool::recycle`.
+    pub fn values_with_pool(&self, pool: &ValuePool) -> Result<Option<Vec<Value>>> {
+        match Self::_get_header_string(&self.message, message::HEADER_FIELD_SIGNATURE) {
+            Some(signature) => {
+                wire::demarshal_pooled(&signature,
+                                       &self.message.body,
+                                       self._byte_order(),
+                                       wire::MAX_DEPTH,
+                                       pool)
+                    .map(Some)
+            },
+            None => Ok(None),
+        }
+    }
+}
+
+#[test]
+fn message_with_headers() {
+    let msg = Message::with_headers(MessageType::MethodCall,
+                                    vec![(message::HEADER_FIELD_PATH,
+                                          Value::BasicValue(BasicValue::ObjectPath(Path("/net/benboeckel"
+                                              .to_string())))),
+                                         (message::HEADER_FIELD_SENDER,
+                                          Value::BasicValue(BasicValue::String(":1.23".to_string())))]);
+
+    if let MessageType::MethodCall = msg.message_type() {
+    } else {
+        panic!("unexpected message type");
     }
+    assert_eq!(Message::_get_header_string(&msg.message, message::HEADER_FIELD_SENDER),
+               Some(":1.23".to_string()));
+    assert_eq!(msg.path(), Some("/net/benboeckel".to_string()));
+}
+
+#[test]
+fn over_nested_body() {
+    let mut msg = Message::with_headers(MessageType::MethodCall,
+                                        vec![(message::HEADER_FIELD_SIGNATURE,
+                                              Value::BasicValue(BasicValue::Signature(Signature("v"
+                                                  .to_string()))))]);
+    for _ in 0..wire::MAX_DEPTH {
+        msg.message.body.extend_from_slice(b"\x01v\x00");
+    }
+    msg.message.body.extend_from_slice(b"\x01y\x00\x2a");
+
+    assert!(msg.values().is_err());
+    assert!(msg.values_with_max_depth(wire::MAX_DEPTH + 1).is_ok());
+}
+
+#[test]
+fn pooled_values() {
+    use crates::dbus_serialize::types::{Array, Struct};
+
+    let strings = vec![Value::BasicValue(BasicValue::String("a".to_string())),
+                       Value::BasicValue(BasicValue::String("b".to_string()))];
+    let msg = Message::new_method_call("net.benboeckel.test.rustbus",
+                                       "/net/benboeckel",
+                                       "net.benboeckel.test.Interface",
+                                       "Method")
+        .add_argument(&"name")
+        .add_argument(&5u32)
+        .add_argument(&Value::Struct(Struct {
+            objects: vec![Value::BasicValue(BasicValue::Int32(-1)),
+                          Value::BasicValue(BasicValue::ObjectPath(Path("/".to_string())))],
+            signature: Signature("(io)".to_string()),
+        }))
+        .add_argument(&Value::Array(Array::new_with_sig(strings, "as".to_string())))
+        .add_argument(&Value::Variant(Variant::new(Value::BasicValue(BasicValue::Byte(7)), "y")));
+
+    let pool = ValuePool::new();
+    let expected = msg.values().unwrap();
+    assert!(expected.is_some());
+
+    // Both fresh and reused buffers give the same values as unpacking without a pool.
+    for _ in 0..2 {
+        let values = msg.values_with_pool(&pool).unwrap();
+        assert_eq!(values, expected);
+        pool.recycle(values.unwrap());
+    }
+}
+
+#[test]
+fn take_fds() {
+    let mut msg = Message::new_signal("/net/benboeckel", "net.benboeckel.test.Interface", "Fds")
+        .add_fd(3)
+        .add_fd(4);
+
+    assert_eq!(msg.take_fds(), vec![3, 4]);
+    assert!(msg.fds().is_empty());
+    assert!(msg.take_fds().is_empty());
+}
+
+#[test]
+fn debug_bytes_round_trip() {
+    let mut msg = Message::new_method_call("net.benboeckel.test.rustbus",
+                                           "/net/benboeckel",
+                                           "net.benboeckel.test.Interface",
+                                           "Method")
+        .add_argument(&"argument")
+        .add_argument(&5u32);
+    msg.message.serial = 7;
+
+    let bytes = msg.to_debug_bytes();
+    let replay = Message::from_debug_bytes(&bytes).unwrap();
+
+    assert_eq!(replay.destination(), msg.destination());
+    assert_eq!(replay.path(), Some("/net/benboeckel".to_string()));
+    assert_eq!(replay.interface(), Some("net.benboeckel.test.Interface".to_string()));
+    assert_eq!(replay.member(), Some("Method".to_string()));
+    assert_eq!(replay.serial(), 7);
+    assert_eq!(replay.values().unwrap(),
+               Some(vec![Value::BasicValue(BasicValue::String("argument".to_string())),
+                         Value::BasicValue(BasicValue::Uint32(5))]));
+
+    assert!(Message::from_debug_bytes(&bytes[..bytes.len() - 1]).is_err());
+    assert!(Message::from_debug_bytes(b"x").is_err());
+}
+
+#[test]
+fn routing_accessors() {
+    let mut msg = Message::new_method_call("net.benboeckel.test.rustbus",
+                                           "/net/benboeckel",
+                                           "net.benboeckel.test.Interface",
+                                           "Method");
+    msg.message.serial = 12;
+
+    assert_eq!(msg.destination(), Some("net.benboeckel.test.rustbus".to_string()));
+    assert_eq!(msg.sender(), None);
+    assert_eq!(msg.serial(), 12);
+
+    let msg = Message::with_headers(MessageType::Signal,
+                                    vec![(message::HEADER_FIELD_SENDER,
+                                          Value::BasicValue(BasicValue::String(":1.23"
+                                              .to_string())))]);
+    assert_eq!(msg.sender(), Some(":1.23".to_string()));
+    assert_eq!(msg.destination(), None);
+}
+
+#[test]
+fn no_reply_expected() {
+    let mut msg = Message::new_method_call("net.benboeckel.test.rustbus",
+                                           "/net/benboeckel",
+                                           "net.benboeckel.test.Interface",
+                                           "Method");
+    assert!(msg.expects_reply());
+
+    msg.message.flags |= FLAG_NO_REPLY_EXPECTED;
+    assert!(!msg.expects_reply());
+}
+
+#[test]
+fn body_signature() {
+    let msg = Message::new_signal("/net/benboeckel", "net.benboeckel.test.Interface", "Signal");
+    assert_eq!(msg.body_signature(), "");
+    assert_eq!(msg.values().unwrap(), None);
+
+    let msg = msg.add_argument(&"name").add_argument(&5u32);
+    assert_eq!(msg.body_signature(), "su");
+}
+
+#[test]
+fn streamed_arguments() {
+    let mut msg = Message::new_signal("/net/benboeckel", "net.benboeckel.test.Interface", "Signal");
+    msg.message.big_endian = true;
+
+    let values = (1..3u64).map(|i| Value::BasicValue(BasicValue::Uint64(i)));
+    let msg = msg.add_argument(&42u8).add_stream(ArrayStream::new("t", values)).unwrap();
+    assert_eq!(msg.body_signature(), "yat");
+    assert_eq!(msg.message.body,
+               vec![42, 0, 0, 0, 0, 0, 0, 16, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 2]);
+    let items = vec![Value::BasicValue(BasicValue::Uint64(1)),
+                     Value::BasicValue(BasicValue::Uint64(2))];
+    assert_eq!(msg.values().unwrap().unwrap()[1],
+               Value::Array(Array::new_with_sig(items, "at".to_string())));
+
+    let values = vec![Value::BasicValue(BasicValue::Uint32(1))];
+    let err = Message::new_signal("/net/benboeckel", "net.benboeckel.test.Interface", "Signal")
+        .add_stream(ArrayStream::new("t", values))
+        .unwrap_err();
+    assert_eq!(err.to_string(), "unexpected value: expected type 't', found u");
+}
+
+#[test]
+fn received_fds() {
+    use std::fs::File;
+    use std::io::{Read, Write};
+    use std::os::unix::io::FromRawFd;
+
+    let mut fds = [0; 2];
+    assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+    let mut reader = unsafe { File::from_raw_fd(fds[0]) };
+    assert_eq!(unsafe { libc::fcntl(fds[0], libc::F_SETFL, libc::O_NONBLOCK) }, 0);
+
+    // The message owns the write end of the pipe and its copy owns a duplicate.
+    let msg = Message::new_with_fds(message::create_signal("/net/benboeckel",
+                                                           "net.benboeckel.test.Interface",
+                                                           "Fds"),
+                                    vec![fds[1]]);
+    let mut copy = msg.try_clone().unwrap();
+    assert_eq!(copy.fds().len(), 1);
+    drop(msg);
+
+    let mut buf = [0; 4];
+    assert_eq!(reader.read(&mut buf).unwrap_err().kind(), io::ErrorKind::WouldBlock);
+
+    // Taken file descriptors are not closed along with the message.
+    let mut writer = unsafe { File::from_raw_fd(copy.take_fds()[0]) };
+    drop(copy);
+    writer.write_all(b"data").unwrap();
+    drop(writer);
+
+    assert_eq!(reader.read(&mut buf).unwrap(), 4);
+    assert_eq!(&buf, b"data");
+    // Every write end has been closed.
+    assert_eq!(reader.read(&mut buf).unwrap(), 0);
 }
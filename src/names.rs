@@ -0,0 +1,45 @@
+// Distributed under the OSI-approved BSD 3-Clause License.
+// See accompanying LICENSE file for details.
+
+/// The maximum length of a name on the bus.
+const MAX_NAME_LENGTH: usize = 255;
+
+fn is_valid_element(element: &str) -> bool {
+    let mut chars = element.chars();
+
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => (),
+        _ => return false,
+    }
+
+    chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Whether a string is a valid interface name.
+///
+/// Interface names are at least two `.`-separated elements which consist of ASCII letters, digits,
+/// and underscores and do not start with a digit.
+pub fn is_valid_interface_name(name: &str) -> bool {
+    name.len() <= MAX_NAME_LENGTH && name.split('.').count() >= 2 &&
+    name.split('.').all(is_valid_element)
+}
+
+/// Whether a string is a valid member (method, property, or signal) name.
+pub fn is_valid_member_name(name: &str) -> bool {
+    name.len() <= MAX_NAME_LENGTH && is_valid_element(name)
+}
+
+#[test]
+fn name_validity() {
+    assert!(is_valid_interface_name("org.freedesktop.DBus"));
+    assert!(is_valid_interface_name("net._benboeckel.test2"));
+    assert!(!is_valid_interface_name("org"));
+    assert!(!is_valid_interface_name("org..freedesktop"));
+    assert!(!is_valid_interface_name("org.9freedesktop"));
+    assert!(!is_valid_interface_name("org.free-desktop"));
+
+    assert!(is_valid_member_name("GetAll"));
+    assert!(!is_valid_member_name(""));
+    assert!(!is_valid_member_name("Get.All"));
+    assert!(!is_valid_member_name("1Get"));
+}
@@ -2,7 +2,8 @@
 // See accompanying LICENSE file for details.
 
 use connection::Connection;
-use interface::Interfaces;
+use error::*;
+use interface::{Interface, Interfaces};
 use message::Message;
 
 /// An object which may receive messages.
@@ -30,8 +31,145 @@ impl Object {
         &self.path
     }
 
+    /// Whether the object implements the `org.freedesktop.DBus.ObjectManager` interface.
+    pub fn is_object_manager(&self) -> bool {
+        self.interfaces.is_object_manager()
+    }
+
+    /// Add an interface to the object.
+    ///
+    /// If the object is itself an object manager, it emits an `InterfacesAdded` signal for the new
+    /// interface. Use `add_managed_interface` for objects underneath an object manager.
+    pub fn add_interface<N>(&self, conn: &Connection, name: N, iface: Interface) -> Result<()>
+        where N: ToString,
+    {
+        let name = name.to_string();
+        self.interfaces.add_interface(&name, iface)?;
+
+        if self.is_object_manager() {
+            conn.send(self.interfaces.interfaces_added_signal(&self.path, &self.path, &[&name]))?;
+        }
+
+        Ok(())
+    }
+
+    /// Add an interface to an object managed by the object manager at `manager`.
+    ///
+    /// The manager emits an `InterfacesAdded` signal for the new interface.
+    pub fn add_managed_interface<N>(&self, conn: &Connection, manager: &str, name: N,
+                                    iface: Interface)
+                                    -> Result<()>
+        where N: ToString,
+    {
+        let name = name.to_string();
+        self.interfaces.add_interface(&name, iface)?;
+
+        conn.send(self.interfaces.interfaces_added_signal(manager, &self.path, &[&name]))?;
+
+        Ok(())
+    }
+
+    /// Remove an interface from the object.
+    ///
+    /// If the object is itself an object manager, it emits an `InterfacesRemoved` signal for the
+    /// interface. Use `remove_managed_interface` for objects underneath an object manager.
+    pub fn remove_interface<N>(&self, conn: &Connection, name: N) -> Result<Interface>
+        where N: AsRef<str>,
+    {
+        let is_object_manager = self.is_object_manager();
+        let iface = self.interfaces.remove_interface(&name)?;
+
+        if is_object_manager {
+            conn.send(Interfaces::interfaces_removed_signal(&self.path,
+                                                            &self.path,
+                                                            &[name.as_ref()]))?;
+        }
+
+        Ok(iface)
+    }
+
+    /// Remove an interface from an object managed by the object manager at `manager`.
+    ///
+    /// The manager emits an `InterfacesRemoved` signal for the interface.
+    pub fn remove_managed_interface<N>(&self, conn: &Connection, manager: &str, name: N)
+                                       -> Result<Interface>
+        where N: AsRef<str>,
+    {
+        let iface = self.interfaces.remove_interface(&name)?;
+
+        conn.send(Interfaces::interfaces_removed_signal(manager, &self.path, &[name.as_ref()]))?;
+
+        Ok(iface)
+    }
+
+    /// A signal announcing all of the interfaces of the object.
+    ///
+    /// The signal is emitted by the object manager at `manager`. Servers send it when the object
+    /// is added underneath an object manager.
+    pub fn interfaces_added_signal(&self, manager: &str) -> Message {
+        let names = self.interfaces.names();
+        let names = names.iter().map(String::as_str).collect::<Vec<_>>();
+
+        self.interfaces.interfaces_added_signal(manager, &self.path, &names)
+    }
+
+    /// A signal announcing the removal of all of the interfaces of the object.
+    ///
+    /// The signal is emitted by the object manager at `manager`. Servers send it when the object
+    /// is removed from underneath an object manager.
+    pub fn interfaces_removed_signal(&self, manager: &str) -> Message {
+        let names = self.interfaces.names();
+        let names = names.iter().map(String::as_str).collect::<Vec<_>>();
+
+        Interfaces::interfaces_removed_signal(manager, &self.path, &names)
+    }
+
     /// Give a message to the object to handle.
+    ///
+    /// Returns `None` if the message is not for this object's path or if it is not a method call.
     pub fn handle_message(&self, conn: &Connection, msg: &mut Message) -> Option<Result<(), ()>> {
+        if msg.path().map_or(true, |path| path != self.path) {
+            return None;
+        }
+
         self.interfaces.handle(conn, msg)
     }
 }
+
+#[test]
+fn dispatch_by_path() {
+    use interface::Method;
+
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    let conn = Connection::session_new().unwrap();
+    let calls = Rc::new(RefCell::new(0));
+
+    let counter = calls.clone();
+    let iface = Interface::new()
+        .add_method("Method",
+                    Method::new(move |_| {
+                        *counter.borrow_mut() += 1;
+                        Ok(vec![])
+                    }));
+    let children = Rc::new(RefCell::new(vec![]));
+    let ifaces = Interfaces::new()
+        .add_interface("net.benboeckel.test.Interface", iface)
+        .unwrap()
+        .finalize(&children)
+        .unwrap();
+    let object = Object::new("/net/benboeckel", ifaces);
+
+    let call = |path| {
+        Message::new_method_call("net.benboeckel.test.rustbus",
+                                 path,
+                                 "net.benboeckel.test.Interface",
+                                 "Method")
+    };
+
+    assert!(object.handle_message(&conn, &mut call("/net/benboeckel/child")).is_none());
+    assert_eq!(*calls.borrow(), 0);
+    assert_eq!(object.handle_message(&conn, &mut call("/net/benboeckel")), Some(Ok(())));
+    assert_eq!(*calls.borrow(), 1);
+}
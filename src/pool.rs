@@ -0,0 +1,153 @@
+// Distributed under the OSI-approved BSD 3-Clause License.
+// See accompanying LICENSE file for details.
+
+use value::{BasicValue, Path, Signature, Value};
+
+use std::cell::RefCell;
+
+/// The maximum number of buffers of each kind kept by a pool.
+const MAX_POOLED: usize = 256;
+
+/// The largest string capacity kept by a pool.
+///
+/// Larger strings are dropped instead so that a single large argument does not keep its memory
+/// alive for the lifetime of the pool.
+const MAX_STRING_CAPACITY: usize = 4096;
+
+#[derive(Debug, Default)]
+/// A pool of buffers reused when unpacking the arguments of messages.
+///
+/// Unpacking arguments allocates a vector for the arguments, each array and structure within
+/// them, and each string. Messages given a pool (see `Message::with_value_pool`) take these
+/// buffers from the pool and `Arguments` returns them once it is dropped so that busy servers do
+/// not go through the allocator for every call. Dictionaries and signatures are not pooled.
+pub struct ValuePool {
+    values: RefCell<Vec<Vec<Value>>>,
+    strings: RefCell<Vec<String>>,
+}
+
+impl ValuePool {
+    /// Create a new, empty pool.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Take an empty vector for values from the pool.
+    pub fn take_values(&self) -> Vec<Value> {
+        self.values.borrow_mut().pop().unwrap_or_else(Vec::new)
+    }
+
+    /// Take an empty string from the pool.
+    pub fn take_string(&self) -> String {
+        self.strings.borrow_mut().pop().unwrap_or_else(String::new)
+    }
+
+    /// Return the buffers used by values to the pool.
+    pub fn recycle(&self, mut values: Vec<Value>) {
+        for value in values.drain(..) {
+            self._recycle_value(value);
+        }
+
+        let mut pooled = self.values.borrow_mut();
+        if pooled.len() < MAX_POOLED {
+            pooled.push(values);
+        }
+    }
+
+    fn _recycle_string(&self, mut string: String) {
+        if string.capacity() > MAX_STRING_CAPACITY {
+            return;
+        }
+
+        let mut pooled = self.strings.borrow_mut();
+        if pooled.len() < MAX_POOLED {
+            string.clear();
+            pooled.push(string);
+        }
+    }
+
+    fn _recycle_basic(&self, value: BasicValue) {
+        match value {
+            BasicValue::String(s) |
+            BasicValue::ObjectPath(Path(s)) |
+            BasicValue::Signature(Signature(s)) => self._recycle_string(s),
+            _ => (),
+        }
+    }
+
+    fn _recycle_value(&self, value: Value) {
+        match value {
+            Value::BasicValue(b) => self._recycle_basic(b),
+            Value::Double(_) => (),
+            Value::Array(array) => self.recycle(array.objects),
+            Value::Dictionary(dict) => {
+                for (key, value) in dict.map {
+                    self._recycle_basic(key);
+                    self._recycle_value(value);
+                }
+            },
+            Value::Struct(st) => {
+                self.recycle(st.objects);
+                self._recycle_string(st.signature.0);
+            },
+            Value::Variant(variant) => self._recycle_value(*variant.object),
+        }
+    }
+}
+
+#[cfg(test)]
+impl ValuePool {
+    fn _counts(&self) -> (usize, usize) {
+        (self.values.borrow().len(), self.strings.borrow().len())
+    }
+}
+
+#[test]
+fn recycle_buffers() {
+    use value::{Array, Struct, Variant};
+
+    let pool = ValuePool::new();
+    assert_eq!(pool._counts(), (0, 0));
+
+    let string = |s: &str| Value::BasicValue(BasicValue::String(s.to_string()));
+    let values = vec![
+        string("plain"),
+        Value::Array(Array::new_with_sig(vec![string("a"), string("b")], "as".to_string())),
+        Value::Struct(Struct {
+            objects: vec![Value::BasicValue(BasicValue::Uint32(1))],
+            signature: Signature("(u)".to_string()),
+        }),
+        Value::Variant(Variant::new(string("variant"), "s")),
+    ];
+    pool.recycle(values);
+
+    // The top-level, array, and structure vectors along with every string.
+    assert_eq!(pool._counts(), (3, 5));
+
+    let vec = pool.take_values();
+    assert!(vec.is_empty());
+    assert!(vec.capacity() > 0);
+    let string = pool.take_string();
+    assert!(string.is_empty());
+    assert_eq!(pool._counts(), (2, 4));
+
+    // Empty pools hand out new buffers.
+    let pool = ValuePool::new();
+    assert_eq!(pool.take_values().capacity(), 0);
+    assert_eq!(pool.take_string().capacity(), 0);
+}
+
+#[test]
+fn drop_large_strings() {
+    let pool = ValuePool::new();
+
+    let small = String::with_capacity(MAX_STRING_CAPACITY);
+    let large = String::with_capacity(MAX_STRING_CAPACITY + 1);
+    pool.recycle(vec![
+        Value::BasicValue(BasicValue::String(small)),
+        Value::BasicValue(BasicValue::String(large)),
+    ]);
+
+    assert_eq!(pool._counts(), (1, 1));
+    assert!(pool.take_string().capacity() <= MAX_STRING_CAPACITY);
+}
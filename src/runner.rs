@@ -1,13 +1,95 @@
 // Distributed under the OSI-approved BSD 3-Clause License.
 // See accompanying LICENSE file for details.
 
-use connection::Connection;
+use connection::{self, BusType, Connection};
 use error::*;
-use message::MessageType;
+use interface::PropertyDebouncer;
+use message::{Message, MessageType};
+use pool::ValuePool;
 use server::Server;
+use value::Value;
 
+use std::cmp;
 use std::collections::btree_map::{BTreeMap, Entry};
 use std::rc::Rc;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+/// How often the event loop checks its stop flag while no messages are received.
+const STOP_CHECK_INTERVAL_MS: u64 = 100;
+
+/// A method call which is waiting for its reply.
+struct PendingReply {
+    method: String,
+    timeout: Duration,
+    sent: Instant,
+    reply: Option<Message>,
+}
+
+/// Replies to method calls which are being waited for.
+struct Replies {
+    pending: BTreeMap<u32, PendingReply>,
+}
+
+impl Replies {
+    fn new() -> Self {
+        Replies {
+            pending: BTreeMap::new(),
+        }
+    }
+
+    fn wait(&mut self, serial: u32, method: String, timeout: Duration) {
+        self.pending.insert(serial, PendingReply {
+            method: method,
+            timeout: timeout,
+            sent: Instant::now(),
+            reply: None,
+        });
+    }
+
+    fn deliver(&mut self, message: Message) {
+        let opt_pending = message.reply_serial().and_then(|serial| self.pending.get_mut(&serial));
+        if let Some(pending) = opt_pending {
+            pending.reply = Some(message);
+        }
+    }
+
+    /// Forget calls whose timeout has passed.
+    ///
+    /// Replies which arrive late or which are never taken would otherwise be kept forever.
+    fn expire(&mut self) {
+        let expired = self.pending
+            .iter()
+            .filter(|&(_, pending)| pending.sent.elapsed() >= pending.timeout)
+            .map(|(&serial, _)| serial)
+            .collect::<Vec<_>>();
+
+        for serial in expired {
+            self.pending.remove(&serial);
+        }
+    }
+
+    fn take(&mut self, serial: u32) -> Option<Result<Vec<Value>>> {
+        match self.pending.entry(serial) {
+            Entry::Occupied(o) => {
+                if o.get().reply.is_some() {
+                    let pending = o.remove();
+                    let reply = pending.reply.expect("the reply was just checked");
+
+                    Some(connection::_reply_values(&pending.method, reply))
+                } else if o.get().sent.elapsed() >= o.get().timeout {
+                    let pending = o.remove();
+
+                    Some(Err(ErrorKind::Timeout(pending.method, pending.timeout).into()))
+                } else {
+                    None
+                }
+            },
+            Entry::Vacant(_) => Some(Err(ErrorKind::NoPendingReply(serial).into())),
+        }
+    }
+}
 
 /// An object to handle messages and act on them.
 ///
@@ -18,6 +100,9 @@ pub struct Runner {
 
     listeners: Vec<Server>,
     servers: BTreeMap<String, Server>,
+    replies: Replies,
+    debouncers: Vec<Rc<PropertyDebouncer>>,
+    pool: Option<Rc<ValuePool>>,
 }
 
 impl Runner {
@@ -28,9 +113,19 @@ impl Runner {
 
             listeners: vec![],
             servers: BTreeMap::new(),
+            replies: Replies::new(),
+            debouncers: vec![],
+            pool: None,
         })
     }
 
+    /// Create a new runner on the bus indicated by the environment.
+    ///
+    /// See `BusType::from_env` for how the bus is chosen.
+    pub fn new_auto() -> Result<Self> {
+        Self::new(Connection::new(BusType::from_env())?)
+    }
+
     // FIXME: Rename to `new_listener`?
     /// Create a server which will listen for and handle signals.
     pub fn add_listener(&mut self, name: &str) -> Result<&mut Server> {
@@ -66,24 +161,371 @@ impl Runner {
         }
     }
 
+    /// Send a method call and wait for its reply in the event loop.
+    ///
+    /// Returns the serial of the method call. The reply may be retrieved using `take_reply` once
+    /// the event loop has received it. Unlike `Connection::call`, this does not wait.
+    pub fn call(&mut self, msg: Message, timeout: Duration) -> Result<u32> {
+        let method = msg.member().unwrap_or_else(String::new);
+        let serial = self.conn.send(msg)?;
+        self.replies.wait(serial, method, timeout);
+
+        Ok(serial)
+    }
+
+    /// Take the reply to a method call made using `call`.
+    ///
+    /// Returns `None` if the reply has not been received yet. As with `Connection::call`, the
+    /// values of the reply are returned and error replies are returned as errors. If the timeout
+    /// of the call has passed without a reply, a `Timeout` error is returned. Serials which are
+    /// not being waited for are reported with a `NoPendingReply` error. This includes calls
+    /// whose reply has already been taken and calls which the event loop has forgotten because
+    /// their timeout passed before the reply was taken.
+    pub fn take_reply(&mut self, serial: u32) -> Option<Result<Vec<Value>>> {
+        self.replies.take(serial)
+    }
+
+    /// Emit the coalesced signals of a debouncer from the event loop.
+    ///
+    /// The event loop wakes up when the window of a pending change passes, so signals are
+    /// emitted on time even if no messages are received. Failures to send them are logged.
+    pub fn add_debouncer(&mut self, debouncer: Rc<PropertyDebouncer>) -> &mut Self {
+        self.debouncers.push(debouncer);
+
+        self
+    }
+
+    /// Reuse buffers from a pool when unpacking the arguments of received messages.
+    ///
+    /// The pool is attached to each message handled by the runner, so handlers using `Arguments`
+    /// take their buffers from it without any changes.
+    pub fn set_value_pool(&mut self, pool: Rc<ValuePool>) -> &mut Self {
+        self.pool = Some(pool);
+
+        self
+    }
+
+    /// Act on timers which have passed.
+    fn _run_timers(&mut self) {
+        for debouncer in &self.debouncers {
+            if let Err(err) = debouncer.emit_due(&self.conn) {
+                println!("failed to emit debounced signals: {:?}", err);
+            }
+        }
+        self.replies.expire();
+    }
+
+    /// How long to wait for a message before a timer needs to run.
+    fn _timer_wait(&self, wait: Duration) -> Duration {
+        let now = Instant::now();
+
+        self.debouncers
+            .iter()
+            .filter_map(|debouncer| debouncer.next_due())
+            .map(|due| if due > now { due - now } else { Duration::from_secs(0) })
+            .fold(wait, cmp::min)
+    }
+
+    /// Handle a message received from the bus.
+    fn _handle(&mut self, message: Message) {
+        let mut message = match self.pool {
+            Some(ref pool) => message.with_value_pool(pool.clone()),
+            None => message,
+        };
+
+        match message.message_type() {
+            MessageType::MethodReturn | MessageType::Error => {
+                self.replies.deliver(message);
+                return;
+            },
+            MessageType::Signal => {
+                for listener in self.listeners.iter_mut() {
+                    listener.handle_message(&mut message);
+                }
+            },
+            _ => (),
+        }
+
+        self.servers.iter_mut().fold(Some(&mut message), |opt_m, (_, server)| {
+            opt_m.and_then(|m| server.handle_message(m))
+        });
+    }
+
+    /// Handle at most one message without waiting.
+    ///
+    /// Messages which have already been queued or which are ready to be read from the connection
+    /// are handled. Returns whether a message was handled.
+    pub fn run_once(&mut self) -> Result<bool> {
+        self._run_timers();
+
+        match self.conn.read_message_timeout(Some(Duration::from_secs(0)))? {
+            Some(message) => {
+                self._handle(message);
+
+                Ok(true)
+            },
+            None => Ok(false),
+        }
+    }
+
     // FIXME: Allow this to hook into other event loops.
     /// Run an event loop to handle messages.
-    pub fn run(&mut self) -> () {
-        let listeners = &mut self.listeners;
-        let servers = &mut self.servers;
+    ///
+    /// Failures to handle individual messages are logged, but errors reading from the connection
+    /// (e.g., because it has been closed) end the loop and are returned.
+    pub fn run(&mut self) -> Result<()> {
+        self.run_until(None, Arc::new(AtomicBool::new(false)))
+    }
 
+    /// Run an event loop to handle messages until the flag is set or the timeout has passed.
+    ///
+    /// The loop exits once the timeout has passed, even if no messages are received. When the
+    /// flag is set from another thread, the loop exits within `STOP_CHECK_INTERVAL_MS`
+    /// milliseconds or once the message being handled has been handled.
+    pub fn run_until(&mut self, timeout: Option<Duration>, stop: Arc<AtomicBool>) -> Result<()> {
         // TODO: add dummy objects to servers
+        let start = Instant::now();
+        let check_interval = Duration::from_millis(STOP_CHECK_INTERVAL_MS);
 
-        self.conn.iter().fold((), |_, mut message| {
-            if let MessageType::Signal = message.message_type() {
-                for listener in listeners.iter_mut() {
-                    listener.handle_message(&mut message);
-                }
+        while !stop.load(Ordering::SeqCst) {
+            let wait = match timeout {
+                Some(timeout) => {
+                    match timeout.checked_sub(start.elapsed()) {
+                        Some(remaining) => cmp::min(remaining, check_interval),
+                        None => break,
+                    }
+                },
+                None => check_interval,
+            };
+            let wait = self._timer_wait(wait);
+
+            if let Some(message) = self.conn.read_message_timeout(Some(wait))? {
+                self._handle(message);
             }
+            self._run_timers();
+        }
 
-            servers.iter_mut().fold(Some(&mut message), |opt_m, (_, server)| {
-                opt_m.and_then(|m| server.handle_message(m))
-            });
-        });
+        Ok(())
+    }
+}
+
+#[test]
+fn route_replies() {
+    use crates::dbus_bytestream::message;
+    use value::{BasicValue, Value};
+
+    let reply_to = |serial| {
+        Message::with_headers(MessageType::MethodReturn,
+                              vec![(message::HEADER_FIELD_REPLY_SERIAL,
+                                    Value::BasicValue(BasicValue::Uint32(serial)))])
+            .add_argument(&serial)
+    };
+    let timeout = Duration::from_secs(60);
+
+    let mut replies = Replies::new();
+    replies.wait(5, "Method".to_string(), timeout);
+
+    assert!(replies.take(5).is_none());
+
+    replies.deliver(reply_to(4));
+    replies.deliver(reply_to(5));
+
+    match *replies.take(4).unwrap().unwrap_err().kind() {
+        ErrorKind::NoPendingReply(4) => (),
+        ref kind => panic!("unexpected error: {}", kind),
+    }
+    assert_eq!(replies.take(5).unwrap().unwrap(),
+               vec![Value::BasicValue(BasicValue::Uint32(5))]);
+    assert!(replies.take(5).unwrap().is_err());
+
+    // Calls without a reply within their timeout report it.
+    replies.wait(6, "Method".to_string(), Duration::from_secs(0));
+    match *replies.take(6).unwrap().unwrap_err().kind() {
+        ErrorKind::Timeout(ref method, _) => assert_eq!(method, "Method"),
+        ref kind => panic!("unexpected error: {}", kind),
     }
+
+    // Calls whose timeout has passed are forgotten, even if their reply was received.
+    replies.wait(7, "Method".to_string(), timeout);
+    replies.wait(8, "Method".to_string(), Duration::from_secs(0));
+    replies.wait(9, "Method".to_string(), Duration::from_secs(0));
+    replies.deliver(reply_to(9));
+    replies.expire();
+    assert_eq!(replies.pending.keys().collect::<Vec<_>>(), vec![&7]);
+}
+
+#[test]
+fn stop_flag() {
+    use target::Target;
+
+    let stop = Arc::new(AtomicBool::new(false));
+    let mut runner = Runner::new(Connection::session_new().unwrap()).unwrap();
+    let unique_name = runner.conn.unique_name().unwrap();
+
+    let flag = stop.clone();
+    runner.add_listener(&unique_name)
+        .unwrap()
+        .subscribe(Target::new("net.benboeckel.test.Interface", "/net/benboeckel", "Stop"))
+        .connect(move |_, _, _| flag.store(true, Ordering::SeqCst))
+        .unwrap();
+    runner.conn
+        .send(Message::new_signal("/net/benboeckel", "net.benboeckel.test.Interface", "Stop"))
+        .unwrap();
+
+    runner.run_until(None, stop.clone()).unwrap();
+    assert!(stop.load(Ordering::SeqCst));
+
+    // An already-set flag returns without waiting for a message.
+    runner.run_until(None, stop).unwrap();
+}
+
+#[test]
+fn run_timeout() {
+    use connection::_peer_connection;
+
+    use std::thread;
+
+    let (conn, _stream) = _peer_connection("run-timeout", true);
+    let mut runner = Runner::new(conn).unwrap();
+    let stop = Arc::new(AtomicBool::new(false));
+
+    // An expired timeout returns without waiting for a message.
+    runner.run_until(Some(Duration::from_secs(0)), stop.clone()).unwrap();
+
+    // The timeout passes even though nothing is received.
+    let timeout = Duration::from_millis(50);
+    let start = Instant::now();
+    runner.run_until(Some(timeout), stop.clone()).unwrap();
+    assert!(start.elapsed() >= timeout);
+    assert!(start.elapsed() < Duration::from_secs(5));
+    assert!(!stop.load(Ordering::SeqCst));
+
+    // Setting the flag from another thread stops an idle loop.
+    let flag = stop.clone();
+    let stopper = thread::spawn(move || {
+        thread::sleep(Duration::from_millis(50));
+        flag.store(true, Ordering::SeqCst);
+    });
+    let start = Instant::now();
+    runner.run_until(None, stop).unwrap();
+    assert!(start.elapsed() < Duration::from_secs(5));
+    stopper.join().unwrap();
+}
+
+#[test]
+fn debounced_signals() {
+    use connection::_peer_connection;
+    use value::{BasicValue, Value};
+
+    use std::io::Read;
+
+    let (conn, mut stream) = _peer_connection("debounced-signals", true);
+    let mut runner = Runner::new(conn).unwrap();
+    let debouncer = Rc::new(PropertyDebouncer::new(Duration::from_millis(20)));
+    runner.add_debouncer(debouncer.clone());
+
+    debouncer.property_changed("/net/benboeckel",
+                               "net.benboeckel.test.Interface",
+                               "Count",
+                               Value::BasicValue(BasicValue::Uint32(1)));
+
+    // The signal is emitted once the window passes even though nothing is received.
+    runner.run_until(Some(Duration::from_millis(200)), Arc::new(AtomicBool::new(false)))
+        .unwrap();
+    assert!(!debouncer.is_pending());
+
+    let mut fixed = [0; 16];
+    stream.read_exact(&mut fixed).unwrap();
+    // A signal was sent to the peer.
+    assert_eq!(fixed[1], 4);
+}
+
+#[test]
+fn closed_connection() {
+    use connection::_peer_connection;
+
+    let (conn, stream) = _peer_connection("closed", true);
+    let mut runner = Runner::new(conn).unwrap();
+
+    // Hang up on the client.
+    drop(stream);
+
+    assert!(runner.run().is_err());
+}
+
+#[test]
+fn run_once() {
+    use connection::{_peer_connection, _peer_send};
+    use target::Target;
+
+    use std::cell::Cell;
+
+    let (conn, stream) = _peer_connection("run-once", true);
+    let mut runner = Runner::new(conn).unwrap();
+
+    let received = Rc::new(Cell::new(vec![]));
+    let serials = received.clone();
+    runner.add_listener("")
+        .unwrap()
+        .subscribe(Target::new("net.benboeckel.test.Interface", "/net/benboeckel", "Signal"))
+        .connect(move |_, _, m| {
+            let mut seen = serials.take();
+            seen.push(m.serial());
+            serials.set(seen);
+        })
+        .unwrap();
+
+    // Nothing has been received.
+    assert!(!runner.run_once().unwrap());
+
+    // Signals sent over peer connections are also queued for local handlers.
+    let sent = runner.conn
+        .send(Message::new_signal("/net/benboeckel", "net.benboeckel.test.Interface", "Signal"))
+        .unwrap();
+
+    assert!(runner.run_once().unwrap());
+    assert!(!runner.run_once().unwrap());
+
+    // Messages from the peer are read from the connection without waiting for more.
+    let signal = Message::new_signal("/net/benboeckel", "net.benboeckel.test.Interface", "Signal");
+    _peer_send(&stream, &signal, 1000);
+
+    assert!(runner.run_once().unwrap());
+    assert!(!runner.run_once().unwrap());
+    assert_eq!(received.take(), vec![sent, 1000]);
+}
+
+#[test]
+fn peer_signals() {
+    use connection::_peer_connection;
+    use target::Target;
+
+    use std::cell::Cell;
+    use std::io::Read;
+
+    let (conn, mut stream) = _peer_connection("peer-signals", true);
+    let mut runner = Runner::new(conn).unwrap();
+
+    let received = Rc::new(Cell::new(None));
+    let serial = received.clone();
+    runner.add_listener("")
+        .unwrap()
+        .subscribe(Target::new("net.benboeckel.test.Interface", "/net/benboeckel", "Signal"))
+        .connect(move |_, _, m| serial.set(Some(m.serial())))
+        .unwrap();
+
+    let sent = runner.conn
+        .send(Message::new_signal("/net/benboeckel", "net.benboeckel.test.Interface", "Signal"))
+        .unwrap();
+
+    // The signal reaches the local handler with the serial it was sent with.
+    assert!(runner.run_once().unwrap());
+    assert_eq!(received.get(), Some(sent));
+
+    // The peer receives it as well.
+    let mut fixed = [0; 16];
+    stream.read_exact(&mut fixed).unwrap();
+    assert_eq!(fixed[0], b'l');
+    let serial = fixed[8..12].iter().rev().fold(0, |n, b| (n << 8) | u32::from(*b));
+    assert_eq!(serial, sent);
 }
@@ -3,20 +3,49 @@
 
 use crates::core::ops::DerefMut;
 
-use connection::{Connection, ReleaseNameReply, DO_NOT_QUEUE};
+use arguments::{Arguments, FromSignalBody};
+use connection::{Connection, ReleaseNameReply, RequestNameFlags, RequestNameReply};
 use error::*;
-use interface::InterfacesBuilder;
+use interface::{Interface, InterfacesBuilder, SharedState};
+use match_rule::MatchRule;
 use message::{Message, MessageType};
 use object::Object;
 use target::Target;
 
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
 use std::collections::btree_map::{BTreeMap, Entry};
-use std::rc::Rc;
+use std::rc::{Rc, Weak};
 
-type SignalHandler = Rc<RefCell<FnMut(&Connection, &Target) -> ()>>;
+type SignalHandler = Rc<RefCell<FnMut(&Connection, &Target, &Message) -> ()>>;
 type SignalHandlers = Vec<SignalHandler>;
 type SignalHandlerMap = BTreeMap<Target, SignalHandlers>;
+type ReadyHandler = Box<FnMut() -> ()>;
+
+fn _match_rule(signal: &Target, namespace: bool) -> MatchRule {
+    let rule = MatchRule::new()
+        .message_type(MessageType::Signal)
+        .interface(&signal.interface);
+    let rule = if namespace {
+        rule.path_namespace(&signal.object)
+    } else {
+        rule.path(&signal.object)
+    };
+
+    if signal.method.is_empty() {
+        rule
+    } else {
+        rule.member(&signal.method)
+    }
+}
+
+/// Whether a path is underneath another path.
+fn _is_below(path: &str, ancestor: &str) -> bool {
+    if ancestor == "/" {
+        path != "/"
+    } else {
+        path.starts_with(ancestor) && path[ancestor.len()..].starts_with('/')
+    }
+}
 
 fn _add_handler(handlers: &mut SignalHandlerMap, signal: Target, handler: SignalHandler) {
     match handlers.entry(signal) {
@@ -27,16 +56,164 @@ fn _add_handler(handlers: &mut SignalHandlerMap, signal: Target, handler: Signal
     };
 }
 
+/// Options for connecting a handler to a signal.
+///
+/// Created by `Server::subscribe`. By default, the handler is called for the signal emitted by
+/// the object at the target's path by any sender.
+pub struct SignalSubscriber<'a> {
+    server: &'a mut Server,
+    target: Target,
+    sender: Option<String>,
+    namespace: bool,
+}
+
+impl<'a> SignalSubscriber<'a> {
+    /// Only handle the signal when emitted by the given sender.
+    ///
+    /// Signals carry the unique name of their sender, so the sender should be a unique name (see
+    /// `Connection::unique_name`). Signals from other senders are ignored even if they are
+    /// delivered due to other subscriptions.
+    pub fn sender<S>(mut self, sender: S) -> Self
+        where S: ToString,
+    {
+        self.sender = Some(sender.to_string());
+
+        self
+    }
+
+    /// Handle the signal when emitted by any object underneath the target's path instead.
+    pub fn namespace(mut self) -> Self {
+        self.namespace = true;
+
+        self
+    }
+
+    /// Connect a handler which receives the signal's message.
+    ///
+    /// This gives access to the sender and arguments of the signal (see `Message::sender` and
+    /// `Arguments`).
+    pub fn connect<F>(self, mut callback: F) -> Result<SignalSubscription>
+        where F: FnMut(&Connection, &Target, &Message) -> () + 'static
+    {
+        let handler: SignalHandler = match self.sender.clone() {
+            Some(sender) => {
+                Rc::new(RefCell::new(move |conn: &Connection, target: &Target, m: &Message| {
+                    if m.sender().map_or(false, |s| s == sender) {
+                        callback(conn, target, m)
+                    }
+                }))
+            },
+            None => Rc::new(RefCell::new(callback)),
+        };
+
+        self._connect(handler)
+    }
+
+    /// Connect a handler which receives the decoded arguments of the signal.
+    ///
+    /// Signals with arguments which fail to decode are logged and ignored.
+    pub fn connect_typed<T, F>(self, mut callback: F) -> Result<SignalSubscription>
+        where T: FromSignalBody + 'static,
+              F: FnMut(&Connection, T) -> () + 'static
+    {
+        self.connect(move |conn, target, m| {
+            match Arguments::new(m).and_then(|args| T::from_signal_body(&args)) {
+                Ok(body) => callback(conn, body),
+                Err(_) => {
+                    println!("ignoring signal with undecodable arguments: \
+                              interface: '{}' path: '{}' member: '{}'",
+                             target.interface,
+                             target.object,
+                             target.method)
+                },
+            }
+        })
+    }
+
+    fn _connect(self, handler: SignalHandler) -> Result<SignalSubscription> {
+        let rule = _match_rule(&self.target, self.namespace);
+        let rule = match self.sender {
+            Some(ref sender) => rule.sender(sender),
+            None => rule,
+        };
+        let signals = if self.namespace {
+            &self.server.namespace_signals
+        } else {
+            &self.server.signals
+        };
+
+        self.server.conn.add_match(&rule)?;
+        _add_handler(&mut signals.borrow_mut(), self.target.clone(), handler.clone());
+
+        Ok(SignalSubscription {
+            conn: self.server.conn.clone(),
+            signals: Rc::downgrade(signals),
+            target: self.target,
+            rule: rule,
+            handler: handler,
+        })
+    }
+}
+
+/// A handle to a signal handler registered with `Server::subscribe`.
+pub struct SignalSubscription {
+    conn: Rc<Connection>,
+    signals: Weak<RefCell<SignalHandlerMap>>,
+    target: Target,
+    rule: MatchRule,
+    handler: SignalHandler,
+}
+
+impl SignalSubscription {
+    /// The signal the handler is connected to.
+    pub fn target(&self) -> &Target {
+        &self.target
+    }
+
+    /// Disconnect the handler.
+    ///
+    /// Other handlers for the same signal are not affected. Handlers may not disconnect
+    /// subscriptions of the same server while being called.
+    pub fn disconnect(self) -> Result<()> {
+        let signals = match self.signals.upgrade() {
+            Some(signals) => signals,
+            // The server is gone, so there is nothing to disconnect from.
+            None => return Ok(()),
+        };
+
+        {
+            let mut signals = signals.borrow_mut();
+            let is_empty = match signals.get_mut(&self.target) {
+                Some(handlers) => {
+                    handlers.retain(|handler| !Rc::ptr_eq(handler, &self.handler));
+                    handlers.is_empty()
+                },
+                None => false,
+            };
+            if is_empty {
+                signals.remove(&self.target);
+            }
+        }
+
+        // Each handler added its own match rule.
+        self.conn.remove_match(&self.rule)
+    }
+}
+
 /// A representation of a collection of objects which implement an interface.
 pub struct Server {
     conn: Rc<Connection>,
     name: String,
     can_handle: bool,
+    owns_name: bool,
+    ready: Cell<bool>,
+    on_ready: RefCell<Option<ReadyHandler>>,
 
     // TODO: store children information
     objects: BTreeMap<String, Object>,
-    signals: SignalHandlerMap,
-    namespace_signals: SignalHandlerMap,
+    state: SharedState,
+    signals: Rc<RefCell<SignalHandlerMap>>,
+    namespace_signals: Rc<RefCell<SignalHandlerMap>>,
 }
 
 impl Server {
@@ -48,19 +225,34 @@ impl Server {
             conn: conn,
             name: name.to_string(),
             can_handle: false,
+            owns_name: false,
+            ready: Cell::new(true),
+            on_ready: RefCell::new(None),
 
             objects: BTreeMap::new(),
-            signals: SignalHandlerMap::new(),
-            namespace_signals: SignalHandlerMap::new(),
+            state: SharedState::new(),
+            signals: Rc::new(RefCell::new(SignalHandlerMap::new())),
+            namespace_signals: Rc::new(RefCell::new(SignalHandlerMap::new())),
         })
     }
 
     /// Create a new `Server` to handle method calls from the bus.
+    ///
+    /// If another connection owns the name, the server waits in the queue for the name. Use
+    /// `on_ready` to be notified once the name is acquired.
     pub fn new<N>(conn: Rc<Connection>, name: N) -> Result<Self>
         where N: ToString,
     {
         let name = name.to_string();
-        conn.request_name(&name, DO_NOT_QUEUE)?;
+        let ready = match conn.request_name(&name, RequestNameFlags::empty())? {
+            RequestNameReply::PrimaryOwner |
+            RequestNameReply::AlreadyOwner => true,
+            RequestNameReply::InQueue => false,
+            // Only possible when asking not to be queued.
+            RequestNameReply::Exists => {
+                bail!(ErrorKind::InvalidReply(format!("RequestName: {} exists", name)))
+            },
+        };
 
         // TODO: Add match for the server.
         // TODO: add root object
@@ -70,10 +262,37 @@ impl Server {
             conn: conn,
             name: name,
             can_handle: true,
+            owns_name: true,
+            ready: Cell::new(ready),
+            on_ready: RefCell::new(None),
 
             objects: BTreeMap::new(),
-            signals: SignalHandlerMap::new(),
-            namespace_signals: SignalHandlerMap::new(),
+            state: SharedState::new(),
+            signals: Rc::new(RefCell::new(SignalHandlerMap::new())),
+            namespace_signals: Rc::new(RefCell::new(SignalHandlerMap::new())),
+        })
+    }
+
+    /// Create a new `Server` to handle method calls without requesting a bus name.
+    ///
+    /// Objects are exported under the connection's unique name (or any name the connection
+    /// already owns). The server's name is the unique name of the connection; it is empty for
+    /// peer-to-peer connections.
+    pub fn new_unnamed(conn: Rc<Connection>) -> Result<Self> {
+        let name = conn.unique_name().unwrap_or_else(String::new);
+
+        Ok(Server {
+            conn: conn,
+            name: name,
+            can_handle: true,
+            owns_name: false,
+            ready: Cell::new(true),
+            on_ready: RefCell::new(None),
+
+            objects: BTreeMap::new(),
+            state: SharedState::new(),
+            signals: Rc::new(RefCell::new(SignalHandlerMap::new())),
+            namespace_signals: Rc::new(RefCell::new(SignalHandlerMap::new())),
         })
     }
 
@@ -82,6 +301,65 @@ impl Server {
         &self.name
     }
 
+    /// Whether the server requested its name from the bus.
+    ///
+    /// Names which are owned by the server are released when it is dropped. This includes names
+    /// which the server is still queued for.
+    pub fn owns_name(&self) -> bool {
+        self.owns_name
+    }
+
+    /// Whether the server is the primary owner of its name.
+    ///
+    /// Servers which do not request a name are always ready.
+    pub fn is_ready(&self) -> bool {
+        self.ready.get()
+    }
+
+    /// Call a function once the server is the primary owner of its name.
+    ///
+    /// If the server already owns its name, the function is called immediately. Otherwise, it is
+    /// called when the `NameAcquired` signal for the name is handled. This is meant for
+    /// readiness notifications of bus-activated services.
+    pub fn on_ready<F>(&mut self, mut callback: F) -> &mut Self
+        where F: FnMut() -> () + 'static,
+    {
+        if self.ready.get() {
+            callback();
+        } else {
+            *self.on_ready.borrow_mut() = Some(Box::new(callback));
+        }
+
+        self
+    }
+
+    fn _check_name_acquired(&self, m: &Message) {
+        if self.ready.get() ||
+           m.interface().map_or(true, |iface| iface != "org.freedesktop.DBus") ||
+           m.member().map_or(true, |member| member != "NameAcquired") {
+            return;
+        }
+
+        let acquired = Arguments::new(m)
+            .ok()
+            .map_or(false, |args| {
+                args.extract_string(0).ok().map_or(false, |name| *name == self.name)
+            });
+        if acquired {
+            self.ready.set(true);
+            if let Some(mut callback) = self.on_ready.borrow_mut().take() {
+                callback();
+            }
+        }
+    }
+
+    /// The state shared by the handlers of all objects on the server.
+    ///
+    /// Handlers may access it using `CallContext::state`.
+    pub fn state(&self) -> &SharedState {
+        &self.state
+    }
+
     /// Add an object to the server with the given interfaces.
     pub fn add_object<P>(&mut self, path: P, ifaces: InterfacesBuilder) -> Result<&mut Self>
         where P: ToString,
@@ -92,14 +370,18 @@ impl Server {
 
         // TODO: Validate the path is valid.
 
+        let manager = self._object_manager_for(&path.to_string()).map(str::to_string);
         match self.objects.entry(path.to_string()) {
             Entry::Vacant(v) => {
                 // TODO: store this
                 let children = Rc::new(RefCell::new(vec![]));
-                let finalized_ifaces = ifaces.finalize(&children)?;
+                let finalized_ifaces = ifaces.shared_state(self.state.clone())
+                    .finalize(&children)?;
                 let obj = Object::new(path, finalized_ifaces);
 
-                // TODO: emit InterfacesAdded signal
+                if let Some(manager) = manager {
+                    self.conn.send(obj.interfaces_added_signal(&manager))?;
+                }
 
                 v.insert(obj);
 
@@ -119,8 +401,10 @@ impl Server {
         }
 
         match self.objects.remove(path.as_ref()) {
-            Some(_) => {
-                // TODO: emit InterfacesRemoved signal
+            Some(obj) => {
+                if let Some(manager) = self._object_manager_for(path.as_ref()) {
+                    self.conn.send(obj.interfaces_removed_signal(manager))?;
+                }
 
                 Ok(self)
             },
@@ -128,43 +412,90 @@ impl Server {
         }
     }
 
-    /// Connect a handler to a specific object's signal.
+    /// The paths of all objects on the server.
+    pub fn paths(&self) -> Vec<String> {
+        self.objects.keys().cloned().collect()
+    }
+
+    /// The paths of all objects on the server underneath a path.
     ///
-    /// This will register a callback to listen to a specific object's signals.
-    pub fn connect<F>(&mut self, signal: Target, callback: F) -> Result<&mut Self>
-        where F: FnMut(&Connection, &Target) -> () + 'static
+    /// Objects at any depth are included, but the path itself is not.
+    pub fn child_paths<P>(&self, path: P) -> Vec<String>
+        where P: AsRef<str>,
     {
-        let dbus_match = format!("type='signal',interface='{}',path='{}',member='{}'",
-                                 signal.interface,
-                                 signal.object,
-                                 signal.method);
-        self.conn.add_match(&dbus_match)?;
+        let path = path.as_ref();
+        let prefix = if path.ends_with('/') {
+            path.to_string()
+        } else {
+            format!("{}/", path)
+        };
 
-        _add_handler(&mut self.signals, signal, Rc::new(RefCell::new(callback)));
-
-        Ok(self)
+        self.objects
+            .keys()
+            .filter(|child| child.starts_with(&prefix) && child.len() > prefix.len())
+            .cloned()
+            .collect()
     }
 
-    /// Connect a handler to a set of objects' signals.
+    /// The path of the object manager responsible for the object at the given path.
     ///
-    /// Any object underneath the requested object path's hierarchy emitting the requested signal
-    /// will trigger the callback.
-    pub fn connect_namespace<F>(&mut self, signal: Target, callback: F) -> Result<&mut Self>
-        where F: FnMut(&Connection, &Target) -> () + 'static
-    {
-        let dbus_match = format!("type='signal',interface='{}',path_namespace='{}',member='{}'",
-                                 signal.interface,
-                                 signal.object,
-                                 signal.method);
-        self.conn.add_match(&dbus_match)?;
+    /// This is the closest object above the path which implements the
+    /// `org.freedesktop.DBus.ObjectManager` interface.
+    fn _object_manager_for(&self, path: &str) -> Option<&str> {
+        self.objects
+            .iter()
+            .filter(|&(manager, obj)| _is_below(path, manager) && obj.is_object_manager())
+            .map(|(manager, _)| manager.as_str())
+            .max_by_key(|manager| manager.len())
+    }
 
-        _add_handler(&mut self.namespace_signals,
-                     signal,
-                     Rc::new(RefCell::new(callback)));
+    /// Add an interface to an object on the server.
+    pub fn add_interface<P, N>(&mut self, path: P, name: N, iface: Interface) -> Result<&mut Self>
+        where P: AsRef<str>,
+              N: ToString,
+    {
+        match self.objects.get(path.as_ref()) {
+            Some(obj) => {
+                match self._object_manager_for(path.as_ref()) {
+                    Some(manager) => obj.add_managed_interface(&self.conn, manager, name, iface)?,
+                    None => obj.add_interface(&self.conn, name, iface)?,
+                }
+            },
+            None => bail!(ErrorKind::NoSuchPath(path.as_ref().to_string())),
+        }
 
         Ok(self)
     }
 
+    /// Remove an interface from an object on the server.
+    pub fn remove_interface<P, N>(&mut self, path: P, name: N) -> Result<Interface>
+        where P: AsRef<str>,
+              N: AsRef<str>,
+    {
+        match self.objects.get(path.as_ref()) {
+            Some(obj) => {
+                match self._object_manager_for(path.as_ref()) {
+                    Some(manager) => obj.remove_managed_interface(&self.conn, manager, name),
+                    None => obj.remove_interface(&self.conn, name),
+                }
+            },
+            None => bail!(ErrorKind::NoSuchPath(path.as_ref().to_string())),
+        }
+    }
+
+    /// Connect a handler to a signal.
+    ///
+    /// Targets with an empty method name (see `Target::interface_on_path`) match any signal of
+    /// the interface. See `SignalSubscriber` for the available options.
+    pub fn subscribe(&mut self, signal: Target) -> SignalSubscriber {
+        SignalSubscriber {
+            server: self,
+            target: signal,
+            sender: None,
+            namespace: false,
+        }
+    }
+
     /// Handle a message with the appropriate handler.
     ///
     /// Returns `None` if the message was consumed, otherwise it returns the original message for
@@ -172,47 +503,71 @@ impl Server {
     pub fn handle_message<'b>(&self, m: &'b mut Message) -> Option<&'b mut Message> {
         match m.message_type() {
             MessageType::MethodCall => self._call_method(m),
-            MessageType::Signal => Some(self._match_signal(m)),
+            MessageType::Signal => {
+                self._check_name_acquired(m);
+                Some(self._match_signal(m))
+            },
             _ => Some(m),
         }
     }
 
+    fn _object_for(&self, m: &Message) -> Option<&Object> {
+        m.path().and_then(|path| self.objects.get(&path))
+    }
+
+    /// Call a method on the object at the message's path.
+    ///
+    /// Messages for paths which are not on this server are passed along. Messages for objects on
+    /// this server are always consumed; unknown interfaces and methods are replied to with an
+    /// error.
     fn _call_method<'b>(&self, m: &'b mut Message) -> Option<&'b mut Message> {
-        let conn = self.conn.clone();
-        self.objects.iter().fold(Some(m), |opt_m, (_, object)| {
-            opt_m.and_then(|mut m| {
-                match object.handle_message(&conn, &mut m) {
-                    None => Some(m),
-                    Some(Ok(())) => None,
-                    Some(Err(())) => {
-                        println!("failed to send a reply for {:?}", m);
-                        None
-                    },
-                }
-            })
-        })
+        let object = match self._object_for(m) {
+            Some(object) => object,
+            None => return Some(m),
+        };
+
+        match object.handle_message(&self.conn, m) {
+            None => Some(m),
+            Some(Ok(())) => None,
+            Some(Err(())) => {
+                println!("failed to send a reply for {:?}", m);
+                None
+            },
+        }
     }
 
     fn _match_signal<'b>(&self, m: &'b mut Message) -> &'b mut Message {
         let conn = self.conn.clone();
 
         Target::extract(m).map(|signal| {
-            for handlers in self.signals.get(&signal) {
+            let wildcard = Target::interface_on_path(&signal.interface, &signal.object);
+            let signals = self.signals.borrow();
+            let matched_handlers = signals
+                .get(&signal)
+                .into_iter()
+                .chain(if signal.method.is_empty() {
+                    None
+                } else {
+                    signals.get(&wildcard)
+                });
+
+            for handlers in matched_handlers {
                 for handler in handlers.iter() {
                     let mut cb = handler.borrow_mut();
 
-                    cb.deref_mut()(&conn, &signal);
+                    cb.deref_mut()(&conn, &signal, m);
                 }
             }
 
+            let namespace_signals = self.namespace_signals.borrow();
             let matched_handlers =
-                self.namespace_signals.iter().filter(|&(expect, _)| expect.namespace_eq(&signal));
+                namespace_signals.iter().filter(|&(expect, _)| expect.namespace_eq(&signal));
 
             for (_, handlers) in matched_handlers {
                 for handler in handlers.iter() {
                     let mut cb = handler.borrow_mut();
 
-                    cb.deref_mut()(&conn, &signal);
+                    cb.deref_mut()(&conn, &signal, m);
                 }
             }
         });
@@ -223,7 +578,7 @@ impl Server {
 
 impl Drop for Server {
     fn drop(&mut self) {
-        if !self.can_handle {
+        if !self.owns_name {
             return;
         }
 
@@ -244,3 +599,547 @@ impl Drop for Server {
         }
     }
 }
+
+#[test]
+fn route_by_path() {
+    let conn = Rc::new(Connection::session_new().unwrap());
+    let mut server = Server::new(conn, "net.benboeckel.test.rustbus.routing").unwrap();
+    server.add_object("/net/benboeckel/object", ::interface::Interfaces::new()).unwrap();
+
+    let call = |path| {
+        Message::new_method_call("net.benboeckel.test.rustbus.routing",
+                                 path,
+                                 "net.benboeckel.test.Interface",
+                                 "Method")
+    };
+
+    assert!(server._object_for(&call("/net/benboeckel/object")).is_some());
+    assert!(server._object_for(&call("/net/benboeckel/other")).is_none());
+
+    let mut other = call("/net/benboeckel/other");
+    assert!(server.handle_message(&mut other).is_some());
+}
+
+#[test]
+fn dispatch_to_paths() {
+    use interface::{Interface, Interfaces, Method};
+
+    let conn = Rc::new(Connection::session_new().unwrap());
+    let mut server = Server::new_unnamed(conn).unwrap();
+    let calls = Rc::new(RefCell::new(vec![]));
+
+    for name in &["first", "second"] {
+        let calls = calls.clone();
+        let name = name.to_string();
+        let iface = Interface::new()
+            .add_method("Method",
+                        Method::new(move |_| {
+                            calls.borrow_mut().push(name.clone());
+                            Ok(vec![])
+                        }));
+        server.add_object(format!("/net/benboeckel/{}", name),
+                        Interfaces::new()
+                            .add_interface("net.benboeckel.test.Interface", iface)
+                            .unwrap())
+            .unwrap();
+    }
+
+    let call = |path| {
+        Message::new_method_call("net.benboeckel.test.rustbus",
+                                 path,
+                                 "net.benboeckel.test.Interface",
+                                 "Method")
+    };
+
+    assert!(server.handle_message(&mut call("/net/benboeckel/second")).is_none());
+    assert!(server.handle_message(&mut call("/net/benboeckel/first")).is_none());
+    assert!(server.handle_message(&mut call("/net/benboeckel/third")).is_some());
+    assert_eq!(*calls.borrow(), vec!["second".to_string(), "first".to_string()]);
+}
+
+#[test]
+fn unknown_members_on_object() {
+    use interface::{Interface, Interfaces};
+
+    let conn = Rc::new(Connection::session_new().unwrap());
+    let unique_name = conn.unique_name().unwrap();
+
+    let mut server = Server::new_unnamed(conn.clone()).unwrap();
+    server.add_object("/net/benboeckel/object",
+                    Interfaces::new()
+                        .add_interface("net.benboeckel.test.Interface", Interface::new())
+                        .unwrap())
+        .unwrap();
+
+    let client = Connection::session_new().unwrap();
+    let error_name = |iface, method| {
+        let serial = client.send(Message::new_method_call(&unique_name,
+                                                          "/net/benboeckel/object",
+                                                          iface,
+                                                          method))
+            .unwrap();
+
+        let mut call = conn.iter()
+            .find(|m| {
+                match m.message_type() {
+                    MessageType::MethodCall => true,
+                    _ => false,
+                }
+            })
+            .unwrap();
+        // Calls to objects on the server are always consumed.
+        assert!(server.handle_message(&mut call).is_none());
+
+        client.iter()
+            .find(|m| m.reply_serial() == Some(serial))
+            .unwrap()
+            .error_name()
+    };
+
+    assert_eq!(error_name("net.benboeckel.test.Missing", "Method"),
+               Some("org.freedesktop.DBus.Error.UnknownInterface".to_string()));
+    assert_eq!(error_name("net.benboeckel.test.Interface", "Missing"),
+               Some("org.freedesktop.DBus.Error.UnknownMethod".to_string()));
+}
+
+#[test]
+fn method_fds() {
+    use crates::libc;
+    use interface::{Argument, Interface, Interfaces, Method};
+    use value::UnixFd;
+
+    use std::fs::File;
+    use std::io::{Read, Write};
+    use std::os::unix::io::FromRawFd;
+
+    let conn = Rc::new(Connection::session_new().unwrap());
+    let unique_name = conn.unique_name().unwrap();
+    let read = Rc::new(RefCell::new(String::new()));
+
+    let mut server = Server::new_unnamed(conn.clone()).unwrap();
+    let contents = read.clone();
+    let iface = Interface::new()
+        .add_method("Read",
+                    Method::new(move |m| {
+                        let index = Arguments::new(m)?.extract_u32(0)? as usize;
+                        let mut file = unsafe { File::from_raw_fd(m.take_fds()[index]) };
+                        file.read_to_string(&mut contents.borrow_mut()).unwrap();
+                        Ok(vec![])
+                    })
+                    .add_argument(Argument::new("fd", "h")));
+    server.add_object("/net/benboeckel/object",
+                    Interfaces::new()
+                        .add_interface("net.benboeckel.test.Interface", iface)
+                        .unwrap())
+        .unwrap();
+
+    let mut fds = [0; 2];
+    assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+    let reader = unsafe { File::from_raw_fd(fds[0]) };
+    let mut writer = unsafe { File::from_raw_fd(fds[1]) };
+    writer.write_all(b"through a pipe").unwrap();
+    drop(writer);
+
+    let client = Connection::session_new().unwrap();
+    client.send(Message::new_method_call(&unique_name,
+                                         "/net/benboeckel/object",
+                                         "net.benboeckel.test.Interface",
+                                         "Read")
+            .add_argument(&UnixFd(0))
+            .add_fd(fds[0]))
+        .unwrap();
+    // The bus has its own copy of the read end of the pipe.
+    drop(reader);
+
+    let mut call = conn.iter()
+        .find(|m| {
+            match m.message_type() {
+                MessageType::MethodCall => true,
+                _ => false,
+            }
+        })
+        .unwrap();
+    assert_eq!(call.fds().len(), 1);
+    assert!(server.handle_message(&mut call).is_none());
+    assert_eq!(*read.borrow(), "through a pipe");
+}
+
+#[test]
+fn unnamed_server() {
+    use interface::{Interface, Interfaces, Method};
+
+    let conn = Rc::new(Connection::session_new().unwrap());
+    let unique_name = conn.unique_name().unwrap();
+
+    let mut server = Server::new_unnamed(conn.clone()).unwrap();
+    assert_eq!(server.name(), unique_name);
+    assert!(!server.owns_name());
+
+    let iface = Interface::new()
+        .add_method("Method", Method::new(|_| Ok(vec![])));
+    server.add_object("/net/benboeckel/object",
+                    Interfaces::new()
+                        .add_interface("net.benboeckel.test.Interface", iface)
+                        .unwrap())
+        .unwrap();
+
+    let client = Connection::session_new().unwrap();
+    client.send(Message::new_method_call(&unique_name,
+                                         "/net/benboeckel/object",
+                                         "net.benboeckel.test.Interface",
+                                         "Method"))
+        .unwrap();
+
+    let mut call = conn.iter()
+        .find(|m| {
+            match m.message_type() {
+                MessageType::MethodCall => true,
+                _ => false,
+            }
+        })
+        .unwrap();
+    assert!(server.handle_message(&mut call).is_none());
+}
+
+#[test]
+fn ready_callback() {
+    use connection::DO_NOT_QUEUE;
+
+    let name = "net.benboeckel.test.rustbus.ready";
+    let holder = Connection::session_new().unwrap();
+    assert_eq!(holder.request_name(name, DO_NOT_QUEUE).unwrap(),
+               RequestNameReply::PrimaryOwner);
+
+    let conn = Rc::new(Connection::session_new().unwrap());
+    let count = Rc::new(Cell::new(0));
+
+    let mut server = Server::new(conn.clone(), name).unwrap();
+    assert!(server.owns_name());
+    assert!(!server.is_ready());
+
+    let counter = count.clone();
+    server.on_ready(move || counter.set(counter.get() + 1));
+    assert_eq!(count.get(), 0);
+
+    let mut other =
+        Message::new_signal("/org/freedesktop/DBus", "org.freedesktop.DBus", "NameAcquired")
+            .add_argument(&"net.benboeckel.test.rustbus.other");
+    server.handle_message(&mut other);
+    assert_eq!(count.get(), 0);
+    assert!(!server.is_ready());
+
+    assert_eq!(holder.release_name(name).unwrap(), ReleaseNameReply::Released);
+
+    let mut acquired = conn.iter()
+        .find(|m| {
+            m.member() == Some("NameAcquired".to_string()) &&
+            Arguments::new(m)
+                .ok()
+                .map_or(false, |args| args.extract_string(0).ok().map_or(false, |n| n == name))
+        })
+        .unwrap();
+    server.handle_message(&mut acquired);
+    assert_eq!(count.get(), 1);
+    assert!(server.is_ready());
+
+    server.handle_message(&mut acquired);
+    assert_eq!(count.get(), 1);
+
+    let counter = count.clone();
+    server.on_ready(move || counter.set(counter.get() + 1));
+    assert_eq!(count.get(), 2);
+}
+
+#[test]
+fn disconnect_subscription() {
+    let conn = Rc::new(Connection::session_new().unwrap());
+    let mut server = Server::new_listener(conn, "net.benboeckel.test.rustbus.subscription")
+        .unwrap();
+    let target = || Target::new("net.benboeckel.test.Interface", "/net/benboeckel", "Signal");
+
+    let first = Rc::new(Cell::new(0));
+    let second = Rc::new(Cell::new(0));
+
+    let counter = first.clone();
+    let subscription = server.subscribe(target())
+        .connect(move |_, _, _| counter.set(counter.get() + 1))
+        .unwrap();
+    let counter = second.clone();
+    server.subscribe(target()).connect(move |_, _, _| counter.set(counter.get() + 1)).unwrap();
+
+    let mut signal = Message::new_signal("/net/benboeckel",
+                                         "net.benboeckel.test.Interface",
+                                         "Signal");
+    server.handle_message(&mut signal);
+    assert_eq!((first.get(), second.get()), (1, 1));
+
+    subscription.disconnect().unwrap();
+
+    server.handle_message(&mut signal);
+    assert_eq!((first.get(), second.get()), (1, 2));
+
+    // Namespace subscriptions may be disconnected as well.
+    let nested = Rc::new(Cell::new(0));
+    let counter = nested.clone();
+    let subscription = server
+        .subscribe(Target::new("net.benboeckel.test.Interface", "/net", "Signal"))
+        .namespace()
+        .connect(move |_, _, _| counter.set(counter.get() + 1))
+        .unwrap();
+
+    server.handle_message(&mut signal);
+    assert_eq!((second.get(), nested.get()), (3, 1));
+
+    subscription.disconnect().unwrap();
+
+    server.handle_message(&mut signal);
+    assert_eq!((second.get(), nested.get()), (4, 1));
+}
+
+#[test]
+fn wildcard_signals() {
+    let conn = Rc::new(Connection::session_new().unwrap());
+    let mut server = Server::new_listener(conn, "net.benboeckel.test.rustbus.wildcard").unwrap();
+
+    let seen = Rc::new(RefCell::new(vec![]));
+    let members = seen.clone();
+    server.subscribe(Target::interface_on_path("net.benboeckel.test.Interface", "/net/benboeckel"))
+        .connect(move |_, signal, _| members.borrow_mut().push(signal.method.clone()))
+        .unwrap();
+
+    let signals = [
+        ("/net/benboeckel", "net.benboeckel.test.Interface", "First"),
+        ("/net/benboeckel", "net.benboeckel.test.Interface", "Second"),
+        ("/net/benboeckel", "net.benboeckel.test.Other", "First"),
+        ("/net", "net.benboeckel.test.Interface", "First"),
+    ];
+    for &(path, interface, member) in &signals {
+        let mut signal = Message::new_signal(path, interface, member);
+        server.handle_message(&mut signal);
+    }
+
+    assert_eq!(*seen.borrow(), vec!["First".to_string(), "Second".to_string()]);
+}
+
+#[test]
+fn signal_sender() {
+    let conn = Rc::new(Connection::session_new().unwrap());
+    let emitter = Connection::session_new().unwrap();
+    let emitter_name = emitter.unique_name().unwrap();
+
+    let mut server = Server::new_listener(conn.clone(), "net.benboeckel.test.rustbus.sender")
+        .unwrap();
+    let target = || Target::new("net.benboeckel.test.Interface", "/net/benboeckel", "Signal");
+
+    let seen = Rc::new(RefCell::new(vec![]));
+    let filtered = Rc::new(Cell::new(0));
+    let matched = Rc::new(Cell::new(0));
+
+    let senders = seen.clone();
+    server.subscribe(target())
+        .connect(move |_, _, m| senders.borrow_mut().push(m.sender()))
+        .unwrap();
+    let counter = filtered.clone();
+    server.subscribe(target())
+        .sender(":0.0")
+        .connect(move |_, _, _| counter.set(counter.get() + 1))
+        .unwrap();
+    let counter = matched.clone();
+    server.subscribe(target())
+        .sender(&emitter_name)
+        .connect(move |_, _, _| counter.set(counter.get() + 1))
+        .unwrap();
+
+    emitter.send(Message::new_signal("/net/benboeckel", "net.benboeckel.test.Interface", "Signal"))
+        .unwrap();
+
+    let mut signal = conn.iter()
+        .find(|m| m.member() == Some("Signal".to_string()))
+        .unwrap();
+    server.handle_message(&mut signal);
+
+    assert_eq!(*seen.borrow(), vec![Some(emitter_name)]);
+    assert_eq!(filtered.get(), 0);
+    assert_eq!(matched.get(), 1);
+}
+
+#[test]
+fn typed_signals() {
+    struct Renamed {
+        old: String,
+        new: String,
+    }
+
+    impl FromSignalBody for Renamed {
+        fn from_signal_body(args: &Arguments) -> Result<Self, ::interface::ErrorMessage> {
+            Ok(Renamed {
+                old: args.extract_string(0)?.clone(),
+                new: args.extract_string(1)?.clone(),
+            })
+        }
+    }
+
+    let conn = Rc::new(Connection::session_new().unwrap());
+    let emitter = Connection::session_new().unwrap();
+
+    let mut server = Server::new_listener(conn.clone(), "net.benboeckel.test.rustbus.typed")
+        .unwrap();
+    let renames = Rc::new(RefCell::new(vec![]));
+
+    let seen = renames.clone();
+    server.subscribe(Target::new("net.benboeckel.test.Interface", "/net/benboeckel", "Renamed"))
+        .connect_typed(move |_, renamed: Renamed| {
+            seen.borrow_mut().push((renamed.old, renamed.new))
+        })
+        .unwrap();
+
+    // Signals which fail to decode do not reach the handler.
+    emitter.send(Message::new_signal("/net/benboeckel", "net.benboeckel.test.Interface", "Renamed")
+            .add_argument(&"before"))
+        .unwrap();
+    emitter.send(Message::new_signal("/net/benboeckel", "net.benboeckel.test.Interface", "Renamed")
+            .add_argument(&"before")
+            .add_argument(&"after"))
+        .unwrap();
+
+    for mut signal in conn.iter().filter(|m| m.member() == Some("Renamed".to_string())).take(2) {
+        server.handle_message(&mut signal);
+    }
+
+    assert_eq!(*renames.borrow(), vec![("before".to_string(), "after".to_string())]);
+}
+
+#[test]
+fn object_lifetime_signals() {
+    use crates::dbus_serialize::types::Path;
+    use interface::{Interface, Interfaces, ManagedObjects};
+    use value::{BasicValue, Value};
+
+    let conn = Rc::new(Connection::session_new().unwrap());
+    let unique_name = conn.unique_name().unwrap();
+    let client = Connection::session_new().unwrap();
+    client.add_match(&format!("type='signal',sender='{}',\
+                               interface='org.freedesktop.DBus.ObjectManager'",
+                              unique_name))
+        .unwrap();
+
+    let mut server = Server::new_unnamed(conn).unwrap();
+    // Objects without an object manager above them are not announced.
+    server.add_object("/unmanaged", Interfaces::new()).unwrap();
+    server.remove_object("/unmanaged").unwrap();
+    server.add_object("/net/benboeckel", Interfaces::new().object_manager(ManagedObjects::new()))
+        .unwrap();
+    server.add_object("/net/benboeckel/object",
+                    Interfaces::new()
+                        .add_interface("net.benboeckel.test.Interface", Interface::new())
+                        .unwrap())
+        .unwrap();
+    server.remove_object("/net/benboeckel/object").unwrap();
+
+    let mut signals = client.iter()
+        .filter(|m| {
+            match m.message_type() {
+                MessageType::Signal => m.interface() == Some("org.freedesktop.DBus.ObjectManager"
+                    .to_string()),
+                _ => false,
+            }
+        });
+    let path = Value::BasicValue(BasicValue::ObjectPath(Path("/net/benboeckel/object"
+        .to_string())));
+
+    let added = signals.next().unwrap();
+    assert_eq!(added.path(), Some("/net/benboeckel".to_string()));
+    assert_eq!(added.member(), Some("InterfacesAdded".to_string()));
+    let args = added.values().unwrap().unwrap();
+    assert_eq!(args[0], path);
+    match args[1] {
+        Value::Dictionary(ref ifaces) => {
+            let name = BasicValue::String("net.benboeckel.test.Interface".to_string());
+            assert!(ifaces.map.contains_key(&name));
+        },
+        _ => panic!("unexpected interfaces and properties"),
+    }
+
+    let removed = signals.next().unwrap();
+    assert_eq!(removed.path(), Some("/net/benboeckel".to_string()));
+    assert_eq!(removed.member(), Some("InterfacesRemoved".to_string()));
+    assert_eq!(removed.values().unwrap().unwrap()[0], path);
+}
+
+#[test]
+fn nested_child_paths() {
+    use interface::Interfaces;
+
+    let conn = Rc::new(Connection::session_new().unwrap());
+    let mut server = Server::new_unnamed(conn).unwrap();
+
+    let paths = vec!["/net",
+                     "/net/benboeckel",
+                     "/net/benboeckel/a/b",
+                     "/net/benboeckelish",
+                     "/org"];
+    for path in &paths {
+        server.add_object(path, Interfaces::new()).unwrap();
+    }
+
+    assert_eq!(server.paths(), paths);
+    assert_eq!(server.child_paths("/net"),
+               vec!["/net/benboeckel", "/net/benboeckel/a/b", "/net/benboeckelish"]);
+    assert_eq!(server.child_paths("/net/benboeckel"), vec!["/net/benboeckel/a/b"]);
+    assert_eq!(server.child_paths("/net/benboeckel/a"), vec!["/net/benboeckel/a/b"]);
+    assert_eq!(server.child_paths("/"), server.paths());
+    assert!(server.child_paths("/org").is_empty());
+}
+
+#[test]
+fn live_interface_signals() {
+    use crates::dbus_serialize::types::Path;
+    use interface::{Interface, Interfaces, ManagedObjects};
+    use value::{BasicValue, Value};
+
+    let conn = Rc::new(Connection::session_new().unwrap());
+    let unique_name = conn.unique_name().unwrap();
+    let client = Connection::session_new().unwrap();
+    client.add_match(&format!("type='signal',sender='{}',\
+                               interface='org.freedesktop.DBus.ObjectManager'",
+                              unique_name))
+        .unwrap();
+
+    let mut server = Server::new_unnamed(conn).unwrap();
+    // The object manager is added last so that the objects are not announced when added.
+    server.add_object("/net/benboeckel/object", Interfaces::new()).unwrap();
+    server.add_object("/unmanaged", Interfaces::new()).unwrap();
+    server.add_object("/net/benboeckel", Interfaces::new().object_manager(ManagedObjects::new()))
+        .unwrap();
+
+    // Objects without an object manager do not announce their interfaces.
+    server.add_interface("/unmanaged", "net.benboeckel.test.Interface", Interface::new())
+        .unwrap();
+    server.add_interface("/net/benboeckel/object",
+                       "net.benboeckel.test.Interface",
+                       Interface::new())
+        .unwrap();
+    server.remove_interface("/net/benboeckel/object", "net.benboeckel.test.Interface").unwrap();
+
+    let mut signals = client.iter()
+        .filter(|m| {
+            match m.message_type() {
+                MessageType::Signal => {
+                    m.interface() == Some("org.freedesktop.DBus.ObjectManager".to_string())
+                },
+                _ => false,
+            }
+        });
+    let path = Value::BasicValue(BasicValue::ObjectPath(Path("/net/benboeckel/object"
+        .to_string())));
+
+    let added = signals.next().unwrap();
+    assert_eq!(added.path(), Some("/net/benboeckel".to_string()));
+    assert_eq!(added.member(), Some("InterfacesAdded".to_string()));
+    assert_eq!(added.values().unwrap().unwrap()[0], path);
+
+    let removed = signals.next().unwrap();
+    assert_eq!(removed.path(), Some("/net/benboeckel".to_string()));
+    assert_eq!(removed.member(), Some("InterfacesRemoved".to_string()));
+    assert_eq!(removed.values().unwrap().unwrap()[0], path);
+}
@@ -0,0 +1,192 @@
+// Distributed under the OSI-approved BSD 3-Clause License.
+// See accompanying LICENSE file for details.
+
+use error::*;
+
+use std::fmt;
+
+/// The maximum length of a signature.
+const MAX_SIGNATURE_LENGTH: usize = 255;
+/// The maximum nesting depth of arrays within a signature.
+const MAX_ARRAY_DEPTH: usize = 32;
+/// The maximum nesting depth of structures within a signature.
+const MAX_STRUCT_DEPTH: usize = 32;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// A single complete type within a signature.
+pub enum Type {
+    /// A basic type with the given type code.
+    Basic(char),
+    /// A variant.
+    Variant,
+    /// An array of the given type.
+    Array(Box<Type>),
+    /// A dictionary entry with the given key and value types.
+    DictEntry(Box<Type>, Box<Type>),
+    /// A structure with the given field types.
+    Struct(Vec<Type>),
+}
+
+impl Type {
+    /// The alignment of the type in the wire format.
+    pub fn alignment(&self) -> usize {
+        match *self {
+            Type::Basic('y') | Type::Basic('g') | Type::Variant => 1,
+            Type::Basic('n') | Type::Basic('q') => 2,
+            Type::Basic('x') | Type::Basic('t') | Type::Basic('d') => 8,
+            Type::Basic(_) | Type::Array(_) => 4,
+            Type::DictEntry(_, _) | Type::Struct(_) => 8,
+        }
+    }
+
+    /// Whether the type is a basic type.
+    pub fn is_basic(&self) -> bool {
+        match *self {
+            Type::Basic(_) => true,
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Type::Basic(code) => write!(f, "{}", code),
+            Type::Variant => write!(f, "v"),
+            Type::Array(ref elem) => write!(f, "a{}", elem),
+            Type::DictEntry(ref key, ref value) => write!(f, "{{{}{}}}", key, value),
+            Type::Struct(ref fields) => {
+                write!(f, "(")?;
+                for field in fields {
+                    write!(f, "{}", field)?;
+                }
+                write!(f, ")")
+            },
+        }
+    }
+}
+
+struct Parser<'a> {
+    signature: &'a str,
+    chars: ::std::iter::Peekable<::std::str::Chars<'a>>,
+    array_depth: usize,
+    struct_depth: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn new(signature: &'a str) -> Self {
+        Parser {
+            signature: signature,
+            chars: signature.chars().peekable(),
+            array_depth: 0,
+            struct_depth: 0,
+        }
+    }
+
+    fn error<T>(&self, reason: &str) -> Result<T> {
+        bail!(ErrorKind::InvalidSignature(self.signature.to_string(), reason.to_string()))
+    }
+
+    fn parse_type(&mut self, in_array: bool) -> Result<Type> {
+        match self.chars.next() {
+            Some(code @ 'y') |
+            Some(code @ 'b') |
+            Some(code @ 'n') |
+            Some(code @ 'q') |
+            Some(code @ 'i') |
+            Some(code @ 'u') |
+            Some(code @ 'x') |
+            Some(code @ 't') |
+            Some(code @ 'd') |
+            Some(code @ 's') |
+            Some(code @ 'o') |
+            Some(code @ 'g') |
+            Some(code @ 'h') => Ok(Type::Basic(code)),
+            Some('v') => Ok(Type::Variant),
+            Some('a') => {
+                self.array_depth += 1;
+                if self.array_depth > MAX_ARRAY_DEPTH {
+                    return self.error("arrays are nested too deeply");
+                }
+                let elem = self.parse_type(true)?;
+                self.array_depth -= 1;
+
+                Ok(Type::Array(Box::new(elem)))
+            },
+            Some('(') => {
+                self.struct_depth += 1;
+                if self.struct_depth > MAX_STRUCT_DEPTH {
+                    return self.error("structures are nested too deeply");
+                }
+                let mut fields = vec![];
+                loop {
+                    match self.chars.peek() {
+                        Some(&')') => {
+                            self.chars.next();
+                            break;
+                        },
+                        Some(_) => fields.push(self.parse_type(false)?),
+                        None => return self.error("unterminated structure"),
+                    }
+                }
+                self.struct_depth -= 1;
+
+                if fields.is_empty() {
+                    return self.error("empty structure");
+                }
+
+                Ok(Type::Struct(fields))
+            },
+            Some('{') => {
+                if !in_array {
+                    return self.error("dictionary entry outside of an array");
+                }
+                self.struct_depth += 1;
+                if self.struct_depth > MAX_STRUCT_DEPTH {
+                    return self.error("structures are nested too deeply");
+                }
+                let key = self.parse_type(false)?;
+                if !key.is_basic() {
+                    return self.error("dictionary key is not a basic type");
+                }
+                let value = self.parse_type(false)?;
+                if self.chars.next() != Some('}') {
+                    return self.error("dictionary entry must contain exactly two types");
+                }
+                self.struct_depth -= 1;
+
+                Ok(Type::DictEntry(Box::new(key), Box::new(value)))
+            },
+            Some(')') => self.error("unexpected end of structure"),
+            Some('}') => self.error("unexpected end of dictionary entry"),
+            Some(_) => self.error("unknown type code"),
+            None => self.error("missing type"),
+        }
+    }
+}
+
+/// Parse a signature into its complete types.
+pub fn parse(signature: &str) -> Result<Vec<Type>> {
+    let mut parser = Parser::new(signature);
+    if signature.len() > MAX_SIGNATURE_LENGTH {
+        return parser.error("signature is too long");
+    }
+
+    let mut types = vec![];
+    while parser.chars.peek().is_some() {
+        types.push(parser.parse_type(false)?);
+    }
+
+    Ok(types)
+}
+
+/// Parse a signature which must contain exactly one complete type.
+pub fn parse_single(signature: &str) -> Result<Type> {
+    let mut types = parse(signature)?;
+    if types.len() != 1 {
+        bail!(ErrorKind::InvalidSignature(signature.to_string(),
+                                          "expected a single complete type".to_string()));
+    }
+
+    Ok(types.remove(0))
+}
@@ -3,7 +3,7 @@
 
 use message::Message;
 
-#[derive(PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord)]
 /// A representation of a signal which may be emitted.
 pub struct Target {
     /// The interface the signal belongs to.
@@ -34,6 +34,16 @@ impl Target {
         }
     }
 
+    /// Create a `Target` for any signal of an interface on an object.
+    ///
+    /// The method name of the target is empty which matches any signal name.
+    pub fn interface_on_path<I, O>(interface: I, object: O) -> Self
+        where I: ToString,
+              O: ToString,
+    {
+        Self::new(interface, object, "")
+    }
+
     /// Extract the signal from a `Message`.
     ///
     /// Returns `None` if parsing fails.
@@ -45,9 +55,20 @@ impl Target {
     ///
     /// This is used to test if a signal belongs to the
     pub fn namespace_eq(&self, t: &Self) -> bool {
-        self.interface == t.interface && self.method == t.method &&
+        self.interface == t.interface && self._method_matches(t) &&
         t.object.starts_with(&format!("{}/", self.object))
     }
+
+    /// Test if a signal matches this target.
+    ///
+    /// An empty method name in this target matches any signal name.
+    pub fn matches(&self, t: &Self) -> bool {
+        self.interface == t.interface && self.object == t.object && self._method_matches(t)
+    }
+
+    fn _method_matches(&self, t: &Self) -> bool {
+        self.method.is_empty() || self.method == t.method
+    }
 }
 
 impl SignalHeaders {
@@ -65,3 +86,36 @@ impl SignalHeaders {
         })
     }
 }
+
+#[test]
+fn interface_on_path_matches() {
+    let target = Target::interface_on_path("net.benboeckel.test.Interface", "/net/benboeckel");
+
+    assert!(target.matches(&Target::new("net.benboeckel.test.Interface",
+                                         "/net/benboeckel",
+                                         "Started")));
+    assert!(target.matches(&Target::new("net.benboeckel.test.Interface",
+                                         "/net/benboeckel",
+                                         "Stopped")));
+    assert!(!target.matches(&Target::new("net.benboeckel.test.Other",
+                                          "/net/benboeckel",
+                                          "Started")));
+    assert!(!target.matches(&Target::new("net.benboeckel.test.Interface",
+                                          "/net/benboeckel/child",
+                                          "Started")));
+}
+
+#[test]
+fn extract_from_message() {
+    use message::MessageType;
+
+    let signal = Message::new_signal("/net/benboeckel",
+                                     "net.benboeckel.test.Interface",
+                                     "Started");
+    let target = Target::extract(&signal).unwrap();
+
+    assert!(target == Target::new("net.benboeckel.test.Interface", "/net/benboeckel", "Started"));
+
+    // Messages without an interface, path, or member do not have a target.
+    assert!(Target::extract(&Message::with_headers(MessageType::Signal, vec![])).is_none());
+}
@@ -0,0 +1,832 @@
+// Distributed under the OSI-approved BSD 3-Clause License.
+// See accompanying LICENSE file for details.
+
+use crates::dbus_bytestream::message;
+use crates::dbus_serialize::types::{Path as ObjectPath, Signature, Variant};
+use crates::libc;
+
+use error::*;
+use message::Message;
+use value::{BasicValue, Value};
+
+use std::cell::{Cell, RefCell};
+use std::cmp;
+use std::collections::VecDeque;
+use std::env;
+use std::io::{self, Read, Write};
+use std::mem;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::ptr;
+use std::time::{Duration, Instant};
+
+/// The address of the system bus if `DBUS_SYSTEM_BUS_ADDRESS` is not set.
+const DEFAULT_SYSTEM_BUS_ADDRESS: &'static str = "unix:path=/var/run/dbus/system_bus_socket";
+/// The major version of the protocol.
+const PROTOCOL_VERSION: u8 = 1;
+/// The length of the fixed portion of a message header.
+const FIXED_HEADER_LEN: usize = 16;
+/// The largest message allowed by the specification.
+const MAX_MESSAGE_LEN: usize = 1 << 27;
+/// The maximum number of file descriptors which may be attached to a single message.
+const MAX_FDS: u32 = 16;
+/// The header field holding the number of file descriptors attached to a message.
+const HEADER_FIELD_UNIX_FDS: u8 = 9;
+/// The most file descriptors which may be received at once.
+///
+/// This is the limit Linux places on a single control message.
+const MAX_RECEIVED_FDS: usize = 253;
+
+/// A connection to a bus or a peer over a Unix socket.
+///
+/// This performs the [authentication
+/// handshake](https://dbus.freedesktop.org/doc/dbus-specification.html#auth-protocol) itself so
+/// that the capabilities negotiated while connecting (e.g., passing file descriptors) are known.
+pub struct Transport {
+    stream: UnixStream,
+    unix_fds: bool,
+    unique_name: Option<String>,
+
+    serial: Cell<u32>,
+    queue: RefCell<VecDeque<Message>>,
+    /// File descriptors which have been received ahead of the rest of their message.
+    fds: RefCell<VecDeque<RawFd>>,
+}
+
+impl Transport {
+    fn _connect(path: &Path) -> Result<Self> {
+        let stream = UnixStream::connect(path).chain_err(|| {
+                ErrorKind::Transport(format!("failed to connect to {}", path.display()))
+            })?;
+        let unix_fds = _authenticate(&stream)?;
+
+        Ok(Self::_new(stream, unix_fds))
+    }
+
+    fn _new(stream: UnixStream, unix_fds: bool) -> Self {
+        Transport {
+            stream: stream,
+            unix_fds: unix_fds,
+            unique_name: None,
+
+            serial: Cell::new(1),
+            queue: RefCell::new(VecDeque::new()),
+            fds: RefCell::new(VecDeque::new()),
+        }
+    }
+
+    fn _connect_bus(address: &str) -> Result<Self> {
+        let path = match _address_path(address) {
+            Some(path) => path,
+            None => bail!(ErrorKind::Transport(format!("unsupported address: {}", address))),
+        };
+
+        let mut transport = Self::_connect(&path)?;
+        transport.unique_name = Some(transport._hello()?);
+
+        Ok(transport)
+    }
+
+    /// Connect to the session bus.
+    pub fn connect_session() -> Result<Self> {
+        match env::var("DBUS_SESSION_BUS_ADDRESS") {
+            Ok(address) => Self::_connect_bus(&address),
+            Err(_) => {
+                bail!(ErrorKind::Transport("DBUS_SESSION_BUS_ADDRESS is not set".to_string()))
+            },
+        }
+    }
+
+    /// Connect to the system bus.
+    pub fn connect_system() -> Result<Self> {
+        let address = env::var("DBUS_SYSTEM_BUS_ADDRESS")
+            .unwrap_or_else(|_| DEFAULT_SYSTEM_BUS_ADDRESS.to_string());
+
+        Self::_connect_bus(&address)
+    }
+
+    /// Connect to a peer listening on the given Unix socket.
+    ///
+    /// There is no bus to greet, so no unique name is assigned.
+    pub fn connect_uds<P>(path: P) -> Result<Self>
+        where P: AsRef<Path>,
+    {
+        Self::_connect(path.as_ref())
+    }
+
+    /// Whether the server agreed to pass file descriptors while authenticating.
+    pub fn supports_fd_passing(&self) -> bool {
+        self.unix_fds
+    }
+
+    /// The maximum number of file descriptors which may be sent with a message.
+    pub fn max_fds(&self) -> u32 {
+        if self.unix_fds {
+            MAX_FDS
+        } else {
+            0
+        }
+    }
+
+    /// The unique name assigned by the bus in reply to the `Hello` call.
+    pub fn unique_name(&self) -> Option<&str> {
+        self.unique_name.as_ref().map(String::as_str)
+    }
+
+    fn _hello(&self) -> Result<String> {
+        let msg = message::create_method_call("org.freedesktop.DBus",
+                                              "/org/freedesktop/DBus",
+                                              "org.freedesktop.DBus",
+                                              "Hello");
+        if let Some(mut results) = self.call_sync(msg)? {
+            if let Some(Value::BasicValue(BasicValue::String(name))) = results.pop() {
+                return Ok(name);
+            }
+        }
+
+        bail!(ErrorKind::InvalidReply("Hello: invalid response".to_string()));
+    }
+
+    fn _next_serial(&self) -> u32 {
+        let serial = self.serial.get();
+        // Serials may not be zero.
+        self.serial.set(serial.checked_add(1).unwrap_or(1));
+
+        serial
+    }
+
+    /// Send a message along with file descriptors.
+    ///
+    /// Returns the serial assigned to the message. The file descriptors are still owned by the
+    /// caller; the receiver is given its own copies of them.
+    pub fn send(&self, msg: &message::Message, fds: &[RawFd]) -> Result<u32> {
+        if fds.len() > self.max_fds() as usize {
+            bail!(ErrorKind::TooManyFds(fds.len(), self.max_fds()));
+        }
+
+        let serial = self._next_serial();
+        let bytes = _encode(msg, serial, fds.len() as u32)?;
+
+        // The file descriptors are sent along with the start of the message.
+        let sent = if fds.is_empty() {
+            0
+        } else {
+            self._send_fds(&bytes, fds)?
+        };
+        (&self.stream).write_all(&bytes[sent..])
+            .chain_err(|| ErrorKind::Transport("failed to send a message".to_string()))?;
+
+        Ok(serial)
+    }
+
+    /// Send the start of a message with file descriptors attached.
+    ///
+    /// Returns the number of bytes which were sent.
+    fn _send_fds(&self, bytes: &[u8], fds: &[RawFd]) -> Result<usize> {
+        let fds_len = (fds.len() * mem::size_of::<RawFd>()) as u32;
+        let space = unsafe { libc::CMSG_SPACE(fds_len) } as usize;
+        // Control messages must be aligned for their headers.
+        let mut control = vec![0u64; (space + 7) / 8];
+        let mut iov = libc::iovec {
+            iov_base: bytes.as_ptr() as *mut libc::c_void,
+            iov_len: bytes.len(),
+        };
+
+        let mut header: libc::msghdr = unsafe { mem::zeroed() };
+        header.msg_iov = &mut iov;
+        header.msg_iovlen = 1;
+        header.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+        header.msg_controllen = space as _;
+
+        unsafe {
+            let cmsg = libc::CMSG_FIRSTHDR(&header);
+            (*cmsg).cmsg_level = libc::SOL_SOCKET;
+            (*cmsg).cmsg_type = libc::SCM_RIGHTS;
+            (*cmsg).cmsg_len = libc::CMSG_LEN(fds_len) as _;
+            ptr::copy_nonoverlapping(fds.as_ptr(), libc::CMSG_DATA(cmsg) as *mut RawFd, fds.len());
+        }
+
+        loop {
+            let sent = unsafe { libc::sendmsg(self.stream.as_raw_fd(), &header, 0) };
+            if sent >= 0 {
+                return Ok(sent as usize);
+            }
+
+            let err = io::Error::last_os_error();
+            if err.kind() != io::ErrorKind::Interrupted {
+                return Err(err).chain_err(|| {
+                    ErrorKind::Transport("failed to send file descriptors".to_string())
+                });
+            }
+        }
+    }
+
+    /// Send a method call and wait for its reply.
+    ///
+    /// Other messages received while waiting are queued for `read_msg`. Error replies are
+    /// returned as errors.
+    pub fn call_sync(&self, msg: message::Message) -> Result<Option<Vec<Value>>> {
+        let serial = self.send(&msg, &[])?;
+
+        loop {
+            let reply = self._read()?;
+            if reply.reply_serial() != Some(serial) {
+                self.queue.borrow_mut().push_back(reply);
+                continue;
+            }
+
+            if reply.message.message_type == message::MESSAGE_TYPE_ERROR {
+                let name = match reply.message
+                    .get_header(message::HEADER_FIELD_ERROR_NAME)
+                    .map(|value| &*value.object) {
+                    Some(&Value::BasicValue(BasicValue::String(ref name))) => name.clone(),
+                    _ => "unknown error".to_string(),
+                };
+
+                bail!(ErrorKind::InvalidReply(format!("error reply: {}", name)));
+            }
+
+            return reply.values();
+        }
+    }
+
+    /// Wait for the next message.
+    ///
+    /// File descriptors received with the message are owned by it.
+    pub fn read_msg(&self) -> Result<Message> {
+        if let Some(msg) = self.queue.borrow_mut().pop_front() {
+            return Ok(msg);
+        }
+
+        self._read()
+    }
+
+    /// Wait for the next message for at most the given amount of time.
+    ///
+    /// Returns `None` if no message arrived in time. Without a timeout, this waits until a message
+    /// is received. Once the start of a message has arrived, the rest of it is read without a
+    /// timeout.
+    pub fn read_msg_timeout(&self, timeout: Option<Duration>) -> Result<Option<Message>> {
+        if let Some(msg) = self.queue.borrow_mut().pop_front() {
+            return Ok(Some(msg));
+        }
+
+        if self._poll(timeout)? {
+            self._read().map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Wait until the connection is readable.
+    ///
+    /// Returns whether the connection became readable before the timeout passed.
+    fn _poll(&self, timeout: Option<Duration>) -> Result<bool> {
+        let start = Instant::now();
+
+        loop {
+            let millis = match timeout {
+                Some(timeout) => {
+                    // Always check at least once, even if the timeout has passed.
+                    let remaining = timeout.checked_sub(start.elapsed())
+                        .unwrap_or_else(|| Duration::from_secs(0));
+                    // Round up so that the timeout does not pass early.
+                    let millis = remaining.as_secs() * 1000 +
+                                 (u64::from(remaining.subsec_nanos()) + 999_999) / 1_000_000;
+                    cmp::min(millis, libc::c_int::max_value() as u64) as libc::c_int
+                },
+                None => -1,
+            };
+
+            let mut pollfd = libc::pollfd {
+                fd: self.stream.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            };
+            let ready = unsafe { libc::poll(&mut pollfd, 1, millis) };
+            if ready > 0 {
+                // Hangups and errors are reported when reading.
+                return Ok(true);
+            } else if ready == 0 {
+                return Ok(false);
+            }
+
+            let err = io::Error::last_os_error();
+            if err.kind() != io::ErrorKind::Interrupted {
+                return Err(err)
+                    .chain_err(|| ErrorKind::Transport("failed to wait for a message".to_string()));
+            }
+        }
+    }
+
+    fn _read_exact(&self, buf: &mut [u8]) -> Result<()> {
+        let mut offset = 0;
+
+        while offset < buf.len() {
+            let len = self._recv(&mut buf[offset..])?;
+            if len == 0 {
+                bail!(ErrorKind::Transport("connection closed".to_string()));
+            }
+
+            offset += len;
+        }
+
+        Ok(())
+    }
+
+    /// Read bytes from the connection along with any file descriptors sent with them.
+    fn _recv(&self, buf: &mut [u8]) -> Result<usize> {
+        let fds_len = (MAX_RECEIVED_FDS * mem::size_of::<RawFd>()) as u32;
+        let space = unsafe { libc::CMSG_SPACE(fds_len) } as usize;
+        // Control messages must be aligned for their headers.
+        let mut control = vec![0u64; (space + 7) / 8];
+        let mut iov = libc::iovec {
+            iov_base: buf.as_mut_ptr() as *mut libc::c_void,
+            iov_len: buf.len(),
+        };
+
+        let mut header: libc::msghdr = unsafe { mem::zeroed() };
+        header.msg_iov = &mut iov;
+        header.msg_iovlen = 1;
+        header.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+        header.msg_controllen = space as _;
+
+        let len = loop {
+            let len = unsafe {
+                libc::recvmsg(self.stream.as_raw_fd(), &mut header, libc::MSG_CMSG_CLOEXEC)
+            };
+            if len >= 0 {
+                break len as usize;
+            }
+
+            let err = io::Error::last_os_error();
+            if err.kind() != io::ErrorKind::Interrupted {
+                return Err(err)
+                    .chain_err(|| ErrorKind::Transport("failed to read a message".to_string()));
+            }
+        };
+
+        let mut fds = self.fds.borrow_mut();
+        unsafe {
+            let mut cmsg = libc::CMSG_FIRSTHDR(&header);
+            while !cmsg.is_null() {
+                if (*cmsg).cmsg_level == libc::SOL_SOCKET && (*cmsg).cmsg_type == libc::SCM_RIGHTS {
+                    let data = libc::CMSG_DATA(cmsg) as *const RawFd;
+                    let count = ((*cmsg).cmsg_len as usize - libc::CMSG_LEN(0) as usize) /
+                                mem::size_of::<RawFd>();
+                    fds.extend((0..count).map(|i| ptr::read_unaligned(data.offset(i as isize))));
+                }
+
+                cmsg = libc::CMSG_NXTHDR(&header, cmsg);
+            }
+        }
+
+        if header.msg_flags & libc::MSG_CTRUNC != 0 {
+            bail!(ErrorKind::Transport("too many file descriptors received".to_string()));
+        }
+
+        Ok(len)
+    }
+
+    fn _read(&self) -> Result<Message> {
+        let mut fixed = [0; FIXED_HEADER_LEN];
+        self._read_exact(&mut fixed)?;
+
+        let big_endian = _big_endian(fixed[0])?;
+        let body_len = _read_u32(&fixed[4..8], big_endian) as usize;
+        let fields_len = _read_u32(&fixed[12..16], big_endian) as usize;
+        let len = _align(FIXED_HEADER_LEN + fields_len, 8) + body_len;
+        if len > MAX_MESSAGE_LEN {
+            bail!(ErrorKind::Transport(format!("message of {} bytes is too large", len)));
+        }
+
+        let mut bytes = fixed.to_vec();
+        bytes.resize(len, 0);
+        self._read_exact(&mut bytes[FIXED_HEADER_LEN..])?;
+
+        let msg = _decode(&bytes)?;
+        let count = match msg.get_header(HEADER_FIELD_UNIX_FDS).map(|value| &*value.object) {
+            Some(&Value::BasicValue(BasicValue::Uint32(count))) => count as usize,
+            _ => 0,
+        };
+
+        let mut fds = self.fds.borrow_mut();
+        if fds.len() < count {
+            bail!(ErrorKind::Transport(format!("expected {} file descriptors, received {}",
+                                               count,
+                                               fds.len())));
+        }
+
+        Ok(Message::new_with_fds(msg, fds.drain(..count).collect()))
+    }
+}
+
+impl Drop for Transport {
+    fn drop(&mut self) {
+        for fd in self.fds.borrow_mut().drain(..) {
+            unsafe {
+                libc::close(fd);
+            }
+        }
+    }
+}
+
+/// Authenticate with the server using the credentials of the process.
+///
+/// Returns whether the server agreed to pass file descriptors.
+fn _authenticate(stream: &UnixStream) -> Result<bool> {
+    // The server reads the credentials of the process from the socket itself; the user ID is
+    // sent so that it may check them.
+    let uid = unsafe { libc::geteuid() }.to_string();
+    let hex_uid = uid.bytes().map(|b| format!("{:02x}", b)).collect::<String>();
+
+    // A nul byte is required before the handshake starts.
+    _write_line(stream, &format!("\0AUTH EXTERNAL {}", hex_uid))?;
+    let reply = _read_line(stream)?;
+    if !reply.starts_with("OK ") {
+        bail!(ErrorKind::Transport(format!("authentication failed: {}", reply)));
+    }
+
+    _write_line(stream, "NEGOTIATE_UNIX_FD")?;
+    let unix_fds = _read_line(stream)? == "AGREE_UNIX_FD";
+    _write_line(stream, "BEGIN")?;
+
+    Ok(unix_fds)
+}
+
+fn _write_line(mut stream: &UnixStream, line: &str) -> Result<()> {
+    stream.write_all(format!("{}\r\n", line).as_bytes())
+        .chain_err(|| ErrorKind::Transport("failed to authenticate".to_string()))
+}
+
+fn _read_line(mut stream: &UnixStream) -> Result<String> {
+    let mut line = Vec::new();
+    let mut byte = [0];
+
+    // Read a byte at a time so that messages sent right after the handshake are not consumed.
+    while !line.ends_with(b"\r\n") {
+        let len = stream.read(&mut byte)
+            .chain_err(|| ErrorKind::Transport("failed to authenticate".to_string()))?;
+        if len == 0 {
+            bail!(ErrorKind::Transport("connection closed while authenticating".to_string()));
+        }
+
+        line.push(byte[0]);
+    }
+
+    let len = line.len() - 2;
+    line.truncate(len);
+    String::from_utf8(line)
+        .chain_err(|| ErrorKind::Transport("invalid authentication reply".to_string()))
+}
+
+/// Find the path of the Unix socket in a server address.
+///
+/// Addresses may list multiple servers separated by `;`; the first Unix socket with a path is
+/// used. Abstract sockets and other transports are not supported.
+fn _address_path(address: &str) -> Option<PathBuf> {
+    address.split(';')
+        .filter_map(|server| {
+            let mut parts = server.splitn(2, ':');
+            match (parts.next(), parts.next()) {
+                (Some("unix"), Some(params)) => {
+                    params.split(',')
+                        .filter_map(|param| {
+                            let mut parts = param.splitn(2, '=');
+                            match (parts.next(), parts.next()) {
+                                (Some("path"), Some(path)) => _unescape(path),
+                                _ => None,
+                            }
+                        })
+                        .next()
+                },
+                _ => None,
+            }
+        })
+        .next()
+        .map(PathBuf::from)
+}
+
+/// Decode the `%`-escaped bytes of an address value.
+fn _unescape(value: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(value.len());
+    let mut iter = value.bytes();
+
+    while let Some(byte) = iter.next() {
+        if byte == b'%' {
+            let hex = iter.by_ref().take(2).map(char::from).collect::<String>();
+            bytes.push(u8::from_str_radix(&hex, 16).ok()?);
+        } else {
+            bytes.push(byte);
+        }
+    }
+
+    String::from_utf8(bytes).ok()
+}
+
+fn _align(offset: usize, align: usize) -> usize {
+    (offset + align - 1) / align * align
+}
+
+fn _big_endian(flag: u8) -> Result<bool> {
+    match flag {
+        b'l' => Ok(false),
+        b'B' => Ok(true),
+        _ => bail!(ErrorKind::Transport("unknown byte order".to_string())),
+    }
+}
+
+fn _read_u32(bytes: &[u8], big_endian: bool) -> u32 {
+    let bytes = &bytes[..4];
+    let fold = |n: u32, b: &u8| (n << 8) | u32::from(*b);
+
+    if big_endian {
+        bytes.iter().fold(0, fold)
+    } else {
+        bytes.iter().rev().fold(0, fold)
+    }
+}
+
+fn _u32_bytes(n: u32, big_endian: bool) -> [u8; 4] {
+    let bytes = [(n >> 24) as u8, (n >> 16) as u8, (n >> 8) as u8, n as u8];
+
+    if big_endian {
+        bytes
+    } else {
+        [bytes[3], bytes[2], bytes[1], bytes[0]]
+    }
+}
+
+/// A buffer for writing message headers.
+struct HeaderWriter {
+    buf: Vec<u8>,
+    big_endian: bool,
+}
+
+impl HeaderWriter {
+    fn pad(&mut self, align: usize) {
+        let len = _align(self.buf.len(), align);
+        self.buf.resize(len, 0);
+    }
+
+    fn u8(&mut self, n: u8) {
+        self.buf.push(n);
+    }
+
+    fn u32(&mut self, n: u32) {
+        self.pad(4);
+        self.buf.extend_from_slice(&_u32_bytes(n, self.big_endian));
+    }
+
+    fn string(&mut self, s: &str) {
+        self.u32(s.len() as u32);
+        self.buf.extend_from_slice(s.as_bytes());
+        self.buf.push(0);
+    }
+
+    fn signature(&mut self, s: &str) {
+        self.u8(s.len() as u8);
+        self.buf.extend_from_slice(s.as_bytes());
+        self.buf.push(0);
+    }
+
+    fn field(&mut self, code: u8, value: &Variant) -> Result<()> {
+        self.pad(8);
+        self.u8(code);
+
+        match *value.object {
+            Value::BasicValue(BasicValue::String(ref s)) => {
+                self.signature("s");
+                self.string(s);
+            },
+            Value::BasicValue(BasicValue::ObjectPath(ObjectPath(ref s))) => {
+                self.signature("o");
+                self.string(s);
+            },
+            Value::BasicValue(BasicValue::Signature(Signature(ref s))) => {
+                self.signature("g");
+                self.signature(s);
+            },
+            Value::BasicValue(BasicValue::Uint32(n)) => {
+                self.signature("u");
+                self.u32(n);
+            },
+            _ => {
+                bail!(ErrorKind::Transport(format!("unsupported value for header field {}",
+                                                   code)))
+            },
+        }
+
+        Ok(())
+    }
+}
+
+/// Write a message in the wire format.
+///
+/// The serial and the number of attached file descriptors are given separately since they are
+/// only known once the message is being sent.
+pub fn _encode(msg: &message::Message, serial: u32, fds: u32) -> Result<Vec<u8>> {
+    let mut writer = HeaderWriter {
+        buf: Vec::with_capacity(FIXED_HEADER_LEN + msg.body.len()),
+        big_endian: msg.big_endian,
+    };
+
+    writer.u8(if msg.big_endian { b'B' } else { b'l' });
+    writer.u8(msg.message_type);
+    writer.u8(msg.flags);
+    writer.u8(PROTOCOL_VERSION);
+    writer.u32(msg.body.len() as u32);
+    writer.u32(serial);
+    // The length of the header fields is filled in once they have been written.
+    writer.u32(0);
+
+    // Header fields defined by the specification use codes 1 through 9.
+    for code in (1..10).filter(|&code| code != HEADER_FIELD_UNIX_FDS) {
+        if let Some(value) = msg.get_header(code) {
+            writer.field(code, value)?;
+        }
+    }
+    if fds > 0 {
+        writer.field(HEADER_FIELD_UNIX_FDS,
+                     &Variant::new(Value::BasicValue(BasicValue::Uint32(fds)), "u"))?;
+    }
+
+    let fields_len = writer.buf.len() - FIXED_HEADER_LEN;
+    writer.buf[12..16].copy_from_slice(&_u32_bytes(fields_len as u32, msg.big_endian));
+    writer.pad(8);
+    writer.buf.extend_from_slice(&msg.body);
+
+    Ok(writer.buf)
+}
+
+/// A cursor for reading message headers.
+struct HeaderReader<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+    big_endian: bool,
+}
+
+impl<'a> HeaderReader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        if self.bytes.len() - self.offset < len {
+            bail!(ErrorKind::Transport("truncated message header".to_string()));
+        }
+
+        let bytes = &self.bytes[self.offset..self.offset + len];
+        self.offset += len;
+
+        Ok(bytes)
+    }
+
+    fn align(&mut self, align: usize) -> Result<()> {
+        let len = _align(self.offset, align) - self.offset;
+        self.take(len).map(|_| ())
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        self.align(4)?;
+        let bytes = self.take(4)?;
+
+        Ok(_read_u32(bytes, self.big_endian))
+    }
+
+    fn utf8(&mut self, len: usize) -> Result<String> {
+        let bytes = self.take(len + 1)?;
+
+        String::from_utf8(bytes[..len].to_vec())
+            .chain_err(|| ErrorKind::Transport("invalid string in message header".to_string()))
+    }
+
+    fn string(&mut self) -> Result<String> {
+        let len = self.u32()? as usize;
+        self.utf8(len)
+    }
+
+    fn signature(&mut self) -> Result<String> {
+        let len = self.u8()? as usize;
+        self.utf8(len)
+    }
+}
+
+/// Read a message from the wire format.
+fn _decode(bytes: &[u8]) -> Result<message::Message> {
+    let big_endian = match bytes.first() {
+        Some(&flag) => _big_endian(flag)?,
+        None => bail!(ErrorKind::Transport("empty message".to_string())),
+    };
+    let mut reader = HeaderReader {
+        bytes: bytes,
+        offset: 1,
+        big_endian: big_endian,
+    };
+
+    let mut msg = message::Message::new();
+    msg.big_endian = big_endian;
+    msg.message_type = reader.u8()?;
+    msg.flags = reader.u8()?;
+    let _version = reader.u8()?;
+    let body_len = reader.u32()? as usize;
+    msg.serial = reader.u32()?;
+    let fields_end = FIXED_HEADER_LEN + reader.u32()? as usize;
+
+    while reader.offset < fields_end {
+        reader.align(8)?;
+        let code = reader.u8()?;
+        let signature = reader.signature()?;
+        let value = match signature.as_str() {
+            "s" => BasicValue::String(reader.string()?),
+            "o" => BasicValue::ObjectPath(ObjectPath(reader.string()?)),
+            "g" => BasicValue::Signature(Signature(reader.signature()?)),
+            "u" => BasicValue::Uint32(reader.u32()?),
+            _ => {
+                bail!(ErrorKind::Transport(format!("unsupported type for header field {}: {}",
+                                                   code,
+                                                   signature)))
+            },
+        };
+
+        msg = msg.add_header(code, Variant::new(Value::BasicValue(value), &signature));
+    }
+
+    let body_start = _align(fields_end, 8);
+    if bytes.len() < body_start || bytes.len() - body_start != body_len {
+        bail!(ErrorKind::Transport(format!("expected a body of {} bytes", body_len)));
+    }
+    msg.body = bytes[body_start..].to_vec();
+
+    Ok(msg)
+}
+
+#[test]
+fn message_framing() {
+    let msg = message::create_method_call("net.benboeckel.test.rustbus",
+                                          "/net/benboeckel",
+                                          "net.benboeckel.test.Interface",
+                                          "Method")
+        .add_arg(&"argument");
+
+    let bytes = _encode(&msg, 7, 0).unwrap();
+    let decoded = Message::new(_decode(&bytes).unwrap());
+
+    assert_eq!(decoded.message.serial, 7);
+    assert_eq!(decoded.path(), Some("/net/benboeckel".to_string()));
+    assert_eq!(decoded.interface(), Some("net.benboeckel.test.Interface".to_string()));
+    assert_eq!(decoded.member(), Some("Method".to_string()));
+    assert_eq!(decoded.values().unwrap(),
+               Some(vec![Value::BasicValue(BasicValue::String("argument".to_string()))]));
+
+    assert!(_decode(&bytes[..bytes.len() - 1]).is_err());
+    assert!(_decode(b"x").is_err());
+}
+
+#[test]
+fn bus_addresses() {
+    assert_eq!(_address_path("unix:path=/run/user/1000/bus"),
+               Some(PathBuf::from("/run/user/1000/bus")));
+    assert_eq!(_address_path("unix:abstract=/tmp/dbus-abc;unix:guid=0123,path=/tmp/a%20bus"),
+               Some(PathBuf::from("/tmp/a bus")));
+    assert_eq!(_address_path("tcp:host=localhost,port=4000"), None);
+    assert_eq!(_address_path("unix:path=/tmp/%zz"), None);
+}
+
+#[test]
+fn fd_passing() {
+    use std::fs::File;
+    use std::os::unix::io::FromRawFd;
+
+    let (sender, receiver) = UnixStream::pair().unwrap();
+    let sender = Transport::_new(sender, true);
+    let receiver = Transport::_new(receiver, true);
+
+    let mut fds = [0; 2];
+    assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+    let reader = unsafe { File::from_raw_fd(fds[0]) };
+    let mut writer = unsafe { File::from_raw_fd(fds[1]) };
+    writer.write_all(b"data").unwrap();
+    drop(writer);
+
+    let msg = message::create_signal("/net/benboeckel", "net.benboeckel.test.Interface", "Fds");
+    sender.send(&msg, &[fds[0], fds[0]]).unwrap();
+    drop(reader);
+
+    let mut received = receiver.read_msg().unwrap();
+    let fds = received.take_fds();
+    assert_eq!(fds.len(), 2);
+    assert!(fds[0] != fds[1]);
+
+    let mut contents = String::new();
+    let mut reader = unsafe { File::from_raw_fd(fds[0]) };
+    reader.read_to_string(&mut contents).unwrap();
+    assert_eq!(contents, "data");
+    drop(unsafe { File::from_raw_fd(fds[1]) });
+
+    // Without negotiating, file descriptors may not be sent.
+    let (sender, _) = UnixStream::pair().unwrap();
+    let sender = Transport::_new(sender, false);
+    assert!(sender.send(&msg, &[0]).is_err());
+}
@@ -3,3 +3,502 @@
 
 pub use crates::dbus_bytestream::marshal::Marshal;
 pub use crates::dbus_serialize::types::*;
+
+use error::*;
+use signature;
+use wire::{self, ByteOrder};
+
+/// Extension methods for `Value`.
+pub trait ValueExt: Sized {
+    /// Marshal the value into the D-Bus wire format.
+    ///
+    /// Alignment padding is computed as if the value starts at an 8-byte boundary (as message
+    /// bodies do).
+    fn to_wire_bytes(&self, byte_order: ByteOrder) -> Result<Vec<u8>>;
+
+    /// Demarshal a value with the given signature from the D-Bus wire format.
+    ///
+    /// The signature must be a single complete type and must describe all of the given bytes.
+    fn from_wire_bytes(signature: &str, bytes: &[u8], byte_order: ByteOrder) -> Result<Self>;
+
+    /// Convert the value into a Rust type.
+    ///
+    /// The error names the kind of value which was found instead.
+    fn extract<T>(&self) -> Result<T>
+        where T: FromValue + HasSignature;
+
+    /// The signature of the value.
+    ///
+    /// Containers are inspected recursively, so the signature describes the values within them
+    /// rather than what the container claims to hold. Empty arrays and dictionaries have no
+    /// elements to inspect, so the signature they were created with is used instead.
+    fn signature(&self) -> String;
+
+    /// Wrap the value in a variant.
+    fn into_variant(self) -> Value;
+
+    /// The value within a variant.
+    ///
+    /// Returns `None` if the value is not a variant.
+    fn unwrap_variant(&self) -> Option<&Value>;
+}
+
+/// The name of the kind of a value.
+fn variant_name(value: &Value) -> &'static str {
+    match *value {
+        Value::BasicValue(BasicValue::Byte(_)) => "BasicValue::Byte",
+        Value::BasicValue(BasicValue::Boolean(_)) => "BasicValue::Boolean",
+        Value::BasicValue(BasicValue::Int16(_)) => "BasicValue::Int16",
+        Value::BasicValue(BasicValue::Uint16(_)) => "BasicValue::Uint16",
+        Value::BasicValue(BasicValue::Int32(_)) => "BasicValue::Int32",
+        Value::BasicValue(BasicValue::Uint32(_)) => "BasicValue::Uint32",
+        Value::BasicValue(BasicValue::Int64(_)) => "BasicValue::Int64",
+        Value::BasicValue(BasicValue::Uint64(_)) => "BasicValue::Uint64",
+        Value::BasicValue(BasicValue::String(_)) => "BasicValue::String",
+        Value::BasicValue(BasicValue::ObjectPath(_)) => "BasicValue::ObjectPath",
+        Value::BasicValue(BasicValue::Signature(_)) => "BasicValue::Signature",
+        Value::Double(_) => "Double",
+        Value::Array(_) => "Array",
+        Value::Variant(_) => "Variant",
+        Value::Struct(_) => "Struct",
+        Value::Dictionary(_) => "Dictionary",
+    }
+}
+
+impl ValueExt for Value {
+    fn to_wire_bytes(&self, byte_order: ByteOrder) -> Result<Vec<u8>> {
+        let mut buf = vec![];
+        wire::marshal(self, byte_order, &mut buf)?;
+
+        Ok(buf)
+    }
+
+    fn from_wire_bytes(sig: &str, bytes: &[u8], byte_order: ByteOrder) -> Result<Self> {
+        signature::parse_single(sig)?;
+
+        wire::demarshal(sig, bytes, byte_order).map(|mut values| values.remove(0))
+    }
+
+    fn extract<T>(&self) -> Result<T>
+        where T: FromValue + HasSignature,
+    {
+        match T::from_value(self) {
+            Some(value) => Ok(value),
+            None => {
+                let actual = variant_name(self).to_string();
+                bail!(ErrorKind::UnexpectedValue(T::signature().0, actual))
+            },
+        }
+    }
+
+    fn signature(&self) -> String {
+        match *self {
+            Value::Array(ref array) => {
+                match array.objects.first() {
+                    Some(object) => format!("a{}", object.signature()),
+                    None => self.get_signature().to_string(),
+                }
+            },
+            Value::Dictionary(ref dict) => {
+                match dict.map.iter().next() {
+                    Some((key, value)) => {
+                        format!("a{{{}{}}}", key.get_signature(), value.signature())
+                    },
+                    None => self.get_signature().to_string(),
+                }
+            },
+            Value::Struct(ref st) => {
+                let fields = st.objects
+                    .iter()
+                    .map(ValueExt::signature)
+                    .collect::<Vec<_>>()
+                    .join("");
+
+                format!("({})", fields)
+            },
+            _ => self.get_signature().to_string(),
+        }
+    }
+
+    fn into_variant(self) -> Value {
+        let signature = ValueExt::signature(&self);
+        Value::Variant(Variant::new(self, &signature))
+    }
+
+    fn unwrap_variant(&self) -> Option<&Value> {
+        match *self {
+            Value::Variant(ref v) => Some(&v.object),
+            _ => None,
+        }
+    }
+}
+
+/// Extension methods for `Signature`.
+pub trait SignatureExt {
+    /// Whether two signatures describe the same types.
+    ///
+    /// Signatures are compared structurally after parsing them. Invalid signatures are never
+    /// equivalent to anything.
+    fn equivalent(&self, other: &Signature) -> bool;
+
+    /// Parse and validate a signature.
+    fn parse(signature: &str) -> Result<Signature>;
+
+    /// Parse and validate a signature which must be exactly one complete type.
+    ///
+    /// This is required for properties and variants.
+    fn single(signature: &str) -> Result<Signature>;
+}
+
+impl SignatureExt for Signature {
+    fn parse(sig: &str) -> Result<Signature> {
+        signature::parse(sig)?;
+
+        Ok(Signature(sig.to_string()))
+    }
+
+    fn single(sig: &str) -> Result<Signature> {
+        signature::parse_single(sig)?;
+
+        Ok(Signature(sig.to_string()))
+    }
+
+    fn equivalent(&self, other: &Signature) -> bool {
+        match (signature::parse(&self.0), signature::parse(&other.0)) {
+            (Ok(lhs), Ok(rhs)) => lhs == rhs,
+            _ => false,
+        }
+    }
+}
+
+/// A trait for Rust types which correspond to a D-Bus type.
+pub trait HasSignature {
+    /// The signature of the corresponding D-Bus type.
+    fn signature() -> Signature;
+
+    /// Whether values of the type may be used where the given signature is declared.
+    fn matches_signature(sig: &Signature) -> bool {
+        Self::signature().equivalent(sig)
+    }
+}
+
+macro_rules! has_signature {
+    ($ty:ty, $sig:expr) => {
+        impl HasSignature for $ty {
+            fn signature() -> Signature {
+                Signature($sig.to_string())
+            }
+        }
+    };
+}
+
+has_signature!(bool, "b");
+has_signature!(u8, "y");
+has_signature!(i16, "n");
+has_signature!(u16, "q");
+has_signature!(i32, "i");
+has_signature!(u32, "u");
+has_signature!(i64, "x");
+has_signature!(u64, "t");
+has_signature!(f64, "d");
+has_signature!(String, "s");
+has_signature!(Path, "o");
+has_signature!(Signature, "g");
+
+/// A trait for Rust types which may be converted from a `Value`.
+pub trait FromValue: Sized {
+    /// Convert the value, returning `None` if it has a different type.
+    fn from_value(value: &Value) -> Option<Self>;
+}
+
+macro_rules! from_basic_value {
+    ($ty:ty, $variant:ident) => {
+        impl FromValue for $ty {
+            fn from_value(value: &Value) -> Option<Self> {
+                if let Value::BasicValue(BasicValue::$variant(ref v)) = *value {
+                    Some(v.clone())
+                } else {
+                    None
+                }
+            }
+        }
+    };
+}
+
+from_basic_value!(bool, Boolean);
+from_basic_value!(u8, Byte);
+from_basic_value!(i16, Int16);
+from_basic_value!(u16, Uint16);
+from_basic_value!(i32, Int32);
+from_basic_value!(u32, Uint32);
+from_basic_value!(i64, Int64);
+from_basic_value!(u64, Uint64);
+from_basic_value!(String, String);
+from_basic_value!(Path, ObjectPath);
+from_basic_value!(Signature, Signature);
+
+impl FromValue for f64 {
+    fn from_value(value: &Value) -> Option<Self> {
+        if let Value::Double(d) = *value {
+            Some(d)
+        } else {
+            None
+        }
+    }
+}
+
+/// A trait for Rust types which may be converted into a `Value`.
+pub trait IntoValue {
+    /// Convert into a value.
+    fn into_value(self) -> Value;
+}
+
+macro_rules! into_basic_value {
+    ($ty:ty, $variant:ident) => {
+        impl IntoValue for $ty {
+            fn into_value(self) -> Value {
+                Value::BasicValue(BasicValue::$variant(self))
+            }
+        }
+    };
+}
+
+into_basic_value!(bool, Boolean);
+into_basic_value!(u8, Byte);
+into_basic_value!(i16, Int16);
+into_basic_value!(u16, Uint16);
+into_basic_value!(i32, Int32);
+into_basic_value!(u32, Uint32);
+into_basic_value!(i64, Int64);
+into_basic_value!(u64, Uint64);
+into_basic_value!(String, String);
+into_basic_value!(Path, ObjectPath);
+into_basic_value!(Signature, Signature);
+
+impl IntoValue for f64 {
+    fn into_value(self) -> Value {
+        Value::Double(self)
+    }
+}
+
+impl<'a> IntoValue for &'a str {
+    fn into_value(self) -> Value {
+        Value::BasicValue(BasicValue::String(self.to_string()))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// An argument referring to a file descriptor attached to a message.
+///
+/// The index is into the file descriptors attached to the message (see `Message::add_fd`) and is
+/// marshaled using the `h` type. Received `h` arguments are unpacked as `u32` indices.
+pub struct UnixFd(pub u32);
+
+impl Marshal for UnixFd {
+    fn dbus_encode(&self, buf: &mut Vec<u8>) -> usize {
+        self.0.dbus_encode(buf)
+    }
+
+    fn get_type(&self) -> String {
+        "h".to_string()
+    }
+}
+
+/// An array whose elements are produced lazily while it is marshaled.
+///
+/// The elements are not collected into memory at any point; they are marshaled as they are
+/// produced. Streams are added to messages using `Message::add_stream`.
+pub struct ArrayStream {
+    signature: String,
+    values: Box<Iterator<Item = Value>>,
+}
+
+impl ArrayStream {
+    /// Create a new array stream with the given element signature.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the signature is not a single complete type.
+    pub fn new<S, I>(signature: S, values: I) -> Self
+        where S: ToString,
+              I: IntoIterator<Item = Value>,
+              I::IntoIter: 'static,
+    {
+        let signature = signature.to_string();
+        if let Err(err) = signature::parse_single(&signature) {
+            panic!("invalid array stream element signature: {}", err);
+        }
+
+        ArrayStream {
+            signature: signature,
+            values: Box::new(values.into_iter()),
+        }
+    }
+
+    /// The signature of the array.
+    pub fn signature(&self) -> String {
+        format!("a{}", self.signature)
+    }
+
+    /// Marshal the array into a buffer using the given byte order.
+    ///
+    /// Fails if an element does not match the element signature of the stream.
+    pub fn marshal(self, order: ByteOrder, buf: &mut Vec<u8>) -> Result<()> {
+        let elem = signature::parse_single(&self.signature)?;
+        let signature = self.signature;
+        let values = self.values;
+
+        wire::marshal_array(buf, order, elem.alignment(), |buf| {
+            for value in values {
+                if value.get_signature() != signature {
+                    let actual = value.get_signature().to_string();
+                    bail!(ErrorKind::UnexpectedValue(signature, actual));
+                }
+                wire::marshal(&value, order, buf)?;
+            }
+
+            Ok(())
+        })
+    }
+}
+
+#[test]
+fn wire_bytes_round_trip() {
+    let value = Value::Struct(Struct {
+        objects: vec![
+            Value::BasicValue(BasicValue::String("answer".to_string())),
+            Value::BasicValue(BasicValue::Int32(-42)),
+        ],
+        signature: Signature("(si)".to_string()),
+    });
+
+    let little = value.to_wire_bytes(ByteOrder::LittleEndian).unwrap();
+    assert_eq!(little,
+               vec![6, 0, 0, 0, b'a', b'n', b's', b'w', b'e', b'r', 0, 0, 0xd6, 0xff, 0xff, 0xff]);
+    assert_eq!(Value::from_wire_bytes("(si)", &little, ByteOrder::LittleEndian).unwrap(),
+               value);
+
+    let big = value.to_wire_bytes(ByteOrder::BigEndian).unwrap();
+    assert_eq!(big,
+               vec![0, 0, 0, 6, b'a', b'n', b's', b'w', b'e', b'r', 0, 0, 0xff, 0xff, 0xff, 0xd6]);
+    assert_eq!(Value::from_wire_bytes("(si)", &big, ByteOrder::BigEndian).unwrap(),
+               value);
+
+    assert!(Value::from_wire_bytes("(si)", &big[..12], ByteOrder::BigEndian).is_err());
+}
+
+#[test]
+fn signature_equivalence() {
+    let sig = Signature("a{sv}".to_string());
+    let reserialized = signature::parse(&sig.0)
+        .unwrap()
+        .iter()
+        .map(|t| t.to_string())
+        .collect::<Vec<_>>()
+        .join("");
+
+    assert!(sig.equivalent(&Signature(reserialized)));
+    assert!(!Signature("s".to_string()).equivalent(&Signature("i".to_string())));
+    assert!(!Signature("a".to_string()).equivalent(&Signature("a".to_string())));
+}
+
+#[test]
+fn rust_type_signatures() {
+    assert!(i32::matches_signature(&Signature("i".to_string())));
+    assert!(!i32::matches_signature(&Signature("s".to_string())));
+    assert!(!String::matches_signature(&Signature("i".to_string())));
+}
+
+#[test]
+fn rust_value_conversions() {
+    assert_eq!(5u32.into_value(), Value::BasicValue(BasicValue::Uint32(5)));
+    assert_eq!(u32::from_value(&5u32.into_value()), Some(5));
+    assert_eq!(String::from_value(&"name".to_string().into_value()),
+               Some("name".to_string()));
+    assert_eq!(f64::from_value(&0.5f64.into_value()), Some(0.5));
+    assert_eq!(i32::from_value(&5u32.into_value()), None);
+}
+
+#[test]
+fn primitive_values() {
+    assert_eq!(true.into_value(), Value::BasicValue(BasicValue::Boolean(true)));
+    assert_eq!(1u8.into_value(), Value::BasicValue(BasicValue::Byte(1)));
+    assert_eq!((-2i16).into_value(), Value::BasicValue(BasicValue::Int16(-2)));
+    assert_eq!(3u16.into_value(), Value::BasicValue(BasicValue::Uint16(3)));
+    assert_eq!((-4i32).into_value(), Value::BasicValue(BasicValue::Int32(-4)));
+    assert_eq!(5u32.into_value(), Value::BasicValue(BasicValue::Uint32(5)));
+    assert_eq!((-6i64).into_value(), Value::BasicValue(BasicValue::Int64(-6)));
+    assert_eq!(7u64.into_value(), Value::BasicValue(BasicValue::Uint64(7)));
+    assert_eq!(0.5f64.into_value(), Value::Double(0.5));
+    assert_eq!("name".into_value(),
+               Value::BasicValue(BasicValue::String("name".to_string())));
+    assert_eq!("name".to_string().into_value(),
+               Value::BasicValue(BasicValue::String("name".to_string())));
+}
+
+#[test]
+fn extract_rust_values() {
+    let value = Value::BasicValue(BasicValue::Uint32(5));
+    assert_eq!(value.extract::<u32>().unwrap(), 5);
+
+    let err = value.extract::<String>().unwrap_err();
+    assert_eq!(format!("{}", err),
+               "unexpected value: expected type 's', found BasicValue::Uint32");
+
+    let err = Value::Double(0.5).extract::<u32>().unwrap_err();
+    assert_eq!(format!("{}", err), "unexpected value: expected type 'u', found Double");
+}
+
+#[test]
+fn signature_validation() {
+    assert_eq!(Signature::parse("a{sv}(si)").unwrap(), Signature("a{sv}(si)".to_string()));
+    assert!(Signature::parse("").is_ok());
+    assert!(Signature::parse("{sv}").is_err());
+    assert!(Signature::parse("a{vs}").is_err());
+    assert!(Signature::parse("(si").is_err());
+    assert!(Signature::parse("si)").is_err());
+    assert!(Signature::parse("z").is_err());
+
+    assert!(Signature::single("a{sv}").is_ok());
+    assert!(Signature::single("sv").is_err());
+    assert!(Signature::single("").is_err());
+}
+
+#[test]
+fn value_signatures() {
+    use std::collections::HashMap;
+
+    let st = Value::Struct(Struct {
+        objects: vec![
+            "answer".into_value(),
+            (-42i32).into_value(),
+        ],
+        signature: Signature("(si)".to_string()),
+    });
+    assert_eq!(st.signature(), "(si)");
+
+    let mut map = HashMap::new();
+    map.insert(BasicValue::String("answer".to_string()),
+               Value::Variant(Variant::new(st.clone(), "(si)")));
+    let dict = Value::Dictionary(Dictionary::new_with_sig(map, "a{sv}".to_string()));
+    assert_eq!(dict.signature(), "a{sv}");
+
+    let array = Value::Array(Array::new_with_sig(vec![st], "a(si)".to_string()));
+    assert_eq!(array.signature(), "a(si)");
+
+    // Empty containers use the signature they were created with.
+    let empty = Value::Array(Array::new_with_sig(vec![], "aa{sv}".to_string()));
+    assert_eq!(empty.signature(), "aa{sv}");
+    let empty = Value::Dictionary(Dictionary::new_with_sig(HashMap::new(), "a{ov}".to_string()));
+    assert_eq!(empty.signature(), "a{ov}");
+}
+
+#[test]
+fn variant_helpers() {
+    let value = 5u32.into_value();
+    let wrapped = value.clone().into_variant();
+
+    assert_eq!(wrapped, Value::Variant(Variant::new(value.clone(), "u")));
+    assert_eq!(wrapped.unwrap_variant(), Some(&value));
+    assert_eq!(value.unwrap_variant(), None);
+}
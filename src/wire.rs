@@ -0,0 +1,448 @@
+// Distributed under the OSI-approved BSD 3-Clause License.
+// See accompanying LICENSE file for details.
+
+use error::*;
+use pool::ValuePool;
+use signature::{self, Type};
+use value::{Array, BasicValue, Dictionary, Path, Signature, Struct, Value, Variant};
+
+use std::collections::HashMap;
+use std::str;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// The byte order of data in the D-Bus wire format.
+pub enum ByteOrder {
+    /// Little endian byte order (`l` in message headers).
+    LittleEndian,
+    /// Big endian byte order (`B` in message headers).
+    BigEndian,
+}
+
+fn pad(buf: &mut Vec<u8>, alignment: usize) {
+    while buf.len() % alignment != 0 {
+        buf.push(0);
+    }
+}
+
+fn write_uint(buf: &mut Vec<u8>, value: u64, size: usize, order: ByteOrder) {
+    pad(buf, size);
+    for i in 0..size {
+        let shift = match order {
+            ByteOrder::LittleEndian => i,
+            ByteOrder::BigEndian => size - i - 1,
+        };
+        buf.push((value >> (8 * shift)) as u8);
+    }
+}
+
+fn write_string(buf: &mut Vec<u8>, value: &str, order: ByteOrder) {
+    write_uint(buf, value.len() as u64, 4, order);
+    buf.extend_from_slice(value.as_bytes());
+    buf.push(0);
+}
+
+fn write_signature(buf: &mut Vec<u8>, value: &str) {
+    buf.push(value.len() as u8);
+    buf.extend_from_slice(value.as_bytes());
+    buf.push(0);
+}
+
+fn marshal_basic(value: &BasicValue, order: ByteOrder, buf: &mut Vec<u8>) {
+    match *value {
+        BasicValue::Byte(b) => buf.push(b),
+        BasicValue::Boolean(b) => write_uint(buf, b as u64, 4, order),
+        BasicValue::Int16(n) => write_uint(buf, n as u16 as u64, 2, order),
+        BasicValue::Uint16(n) => write_uint(buf, n as u64, 2, order),
+        BasicValue::Int32(n) => write_uint(buf, n as u32 as u64, 4, order),
+        BasicValue::Uint32(n) => write_uint(buf, n as u64, 4, order),
+        BasicValue::Int64(n) => write_uint(buf, n as u64, 8, order),
+        BasicValue::Uint64(n) => write_uint(buf, n, 8, order),
+        BasicValue::String(ref s) |
+        BasicValue::ObjectPath(Path(ref s)) => write_string(buf, s, order),
+        BasicValue::Signature(Signature(ref s)) => write_signature(buf, s),
+    }
+}
+
+/// Marshal a value into the wire format.
+///
+/// Alignment is relative to the start of the buffer.
+pub fn marshal(value: &Value, order: ByteOrder, buf: &mut Vec<u8>) -> Result<()> {
+    match *value {
+        Value::BasicValue(ref b) => marshal_basic(b, order, buf),
+        Value::Double(d) => write_uint(buf, d.to_bits(), 8, order),
+        Value::Array(ref array) => {
+            let elem = match signature::parse_single(value.get_signature())? {
+                Type::Array(elem) => elem,
+                _ => {
+                    bail!(ErrorKind::InvalidWireData("array without an array signature"
+                        .to_string()))
+                },
+            };
+            marshal_array(buf, order, elem.alignment(), |buf| {
+                for object in &array.objects {
+                    marshal(object, order, buf)?;
+                }
+
+                Ok(())
+            })?;
+        },
+        Value::Dictionary(ref dict) => {
+            marshal_array(buf, order, 8, |buf| {
+                for (key, value) in &dict.map {
+                    pad(buf, 8);
+                    marshal_basic(key, order, buf);
+                    marshal(value, order, buf)?;
+                }
+
+                Ok(())
+            })?;
+        },
+        Value::Struct(ref st) => {
+            pad(buf, 8);
+            for object in &st.objects {
+                marshal(object, order, buf)?;
+            }
+        },
+        Value::Variant(ref variant) => {
+            write_signature(buf, variant.object.get_signature());
+            marshal(&variant.object, order, buf)?;
+        },
+    }
+
+    Ok(())
+}
+
+/// Marshal an array whose elements are written by the given function.
+///
+/// The length of the array is filled in once the elements have been written.
+pub fn marshal_array<F>(buf: &mut Vec<u8>, order: ByteOrder, alignment: usize, f: F) -> Result<()>
+    where F: FnOnce(&mut Vec<u8>) -> Result<()>,
+{
+    pad(buf, 4);
+    let length_offset = buf.len();
+    write_uint(buf, 0, 4, order);
+    pad(buf, alignment);
+    let start = buf.len();
+
+    f(buf)?;
+
+    let length = buf.len() - start;
+    let mut length_bytes = vec![];
+    write_uint(&mut length_bytes, length as u64, 4, order);
+    buf[length_offset..length_offset + 4].copy_from_slice(&length_bytes);
+
+    Ok(())
+}
+
+/// The default maximum nesting depth of containers when demarshaling.
+///
+/// The specification allows 32 levels of array nesting and 32 levels of structure nesting.
+pub const MAX_DEPTH: usize = 64;
+
+/// A reader for data in the wire format.
+pub struct Demarshaller<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+    order: ByteOrder,
+    depth: usize,
+    max_depth: usize,
+    pool: Option<&'a ValuePool>,
+}
+
+impl<'a> Demarshaller<'a> {
+    /// Create a reader for the given bytes.
+    ///
+    /// Alignment is relative to the start of `bytes`.
+    pub fn new(bytes: &'a [u8], order: ByteOrder) -> Self {
+        Demarshaller {
+            bytes: bytes,
+            offset: 0,
+            order: order,
+            depth: 0,
+            max_depth: MAX_DEPTH,
+            pool: None,
+        }
+    }
+
+    /// Limit the nesting depth of containers (variants, arrays, structures, and dictionary
+    /// entries) which may be read.
+    ///
+    /// Since variants carry their own signatures, they may be nested arbitrarily deeply by a
+    /// sender. The default limit is `MAX_DEPTH`.
+    pub fn with_max_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = max_depth;
+
+        self
+    }
+
+    /// Take the buffers for values from a pool rather than allocating them.
+    pub fn with_pool(mut self, pool: &'a ValuePool) -> Self {
+        self.pool = Some(pool);
+
+        self
+    }
+
+    /// The offset of the next byte to be read.
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    fn error<T>(&self, desc: &str) -> Result<T> {
+        bail!(ErrorKind::InvalidWireData(format!("{} at offset {}", desc, self.offset)))
+    }
+
+    fn align(&mut self, alignment: usize) -> Result<()> {
+        while self.offset % alignment != 0 {
+            if self.offset >= self.bytes.len() {
+                return self.error("truncated data");
+            }
+            if self.bytes[self.offset] != 0 {
+                return self.error("non-zero padding");
+            }
+            self.offset += 1;
+        }
+
+        Ok(())
+    }
+
+    fn take(&mut self, count: usize) -> Result<&'a [u8]> {
+        if self.bytes.len() - self.offset < count {
+            return self.error("truncated data");
+        }
+        let bytes = &self.bytes[self.offset..self.offset + count];
+        self.offset += count;
+
+        Ok(bytes)
+    }
+
+    fn read_uint(&mut self, size: usize) -> Result<u64> {
+        self.align(size)?;
+        let order = self.order;
+        let bytes = self.take(size)?;
+
+        Ok(bytes.iter().enumerate().fold(0, |value, (i, &b)| {
+            let shift = match order {
+                ByteOrder::LittleEndian => i,
+                ByteOrder::BigEndian => size - i - 1,
+            };
+            value | ((b as u64) << (8 * shift))
+        }))
+    }
+
+    fn read_str(&mut self, length: usize) -> Result<String> {
+        let bytes = self.take(length + 1)?;
+        if bytes[length] != 0 {
+            return self.error("missing string terminator");
+        }
+
+        match str::from_utf8(&bytes[..length]) {
+            Ok(s) => {
+                let mut string = self.pool.map_or_else(String::new, ValuePool::take_string);
+                string.push_str(s);
+
+                Ok(string)
+            },
+            Err(_) => self.error("invalid UTF-8 in string"),
+        }
+    }
+
+    fn _values(&self) -> Vec<Value> {
+        self.pool.map_or_else(Vec::new, ValuePool::take_values)
+    }
+
+    fn read_string(&mut self) -> Result<String> {
+        let length = self.read_uint(4)? as usize;
+        self.read_str(length)
+    }
+
+    /// Read a signature value.
+    pub fn read_signature(&mut self) -> Result<String> {
+        let length = self.take(1)?[0] as usize;
+        self.read_str(length)
+    }
+
+    fn read_basic(&mut self, code: char) -> Result<BasicValue> {
+        Ok(match code {
+            'y' => BasicValue::Byte(self.take(1)?[0]),
+            'b' => {
+                match self.read_uint(4)? {
+                    0 => BasicValue::Boolean(false),
+                    1 => BasicValue::Boolean(true),
+                    _ => return self.error("invalid boolean value"),
+                }
+            },
+            'n' => BasicValue::Int16(self.read_uint(2)? as u16 as i16),
+            'q' => BasicValue::Uint16(self.read_uint(2)? as u16),
+            'i' => BasicValue::Int32(self.read_uint(4)? as u32 as i32),
+            // File descriptor indices are read as their index into the attached descriptors.
+            'u' | 'h' => BasicValue::Uint32(self.read_uint(4)? as u32),
+            'x' => BasicValue::Int64(self.read_uint(8)? as i64),
+            't' => BasicValue::Uint64(self.read_uint(8)?),
+            's' => BasicValue::String(self.read_string()?),
+            'o' => BasicValue::ObjectPath(Path(self.read_string()?)),
+            'g' => BasicValue::Signature(Signature(self.read_signature()?)),
+            _ => return self.error(&format!("unsupported type code '{}'", code)),
+        })
+    }
+
+    fn nested<T, F>(&mut self, f: F) -> Result<T>
+        where F: FnOnce(&mut Self) -> Result<T>,
+    {
+        if self.depth >= self.max_depth {
+            return self.error("values are nested too deeply");
+        }
+
+        self.depth += 1;
+        let res = f(self);
+        self.depth -= 1;
+
+        res
+    }
+
+    /// Read a value of the given type.
+    pub fn read(&mut self, ty: &Type) -> Result<Value> {
+        match *ty {
+            Type::Basic(_) => self.read_container(ty),
+            _ => self.nested(|this| this.read_container(ty)),
+        }
+    }
+
+    fn read_container(&mut self, ty: &Type) -> Result<Value> {
+        Ok(match *ty {
+            Type::Basic('d') => Value::Double(f64::from_bits(self.read_uint(8)?)),
+            Type::Basic(code) => Value::BasicValue(self.read_basic(code)?),
+            Type::Variant => {
+                let sig = self.read_signature()?;
+                let inner = signature::parse_single(&sig)?;
+                Value::Variant(Variant::new(self.read(&inner)?, &sig))
+            },
+            Type::Array(ref elem) => {
+                let length = self.read_uint(4)? as usize;
+                self.align(elem.alignment())?;
+                if self.bytes.len() - self.offset < length {
+                    return self.error("truncated array");
+                }
+                let end = self.offset + length;
+
+                if let Type::DictEntry(ref key, ref value) = **elem {
+                    let mut map = HashMap::new();
+                    while self.offset < end {
+                        let (key, value) = self.nested(|this| {
+                                this.align(8)?;
+                                let key = match this.read(key)? {
+                                    Value::BasicValue(b) => b,
+                                    _ => return this.error("non-basic dictionary key"),
+                                };
+
+                                Ok((key, this.read(value)?))
+                            })?;
+                        map.insert(key, value);
+                    }
+
+                    Value::Dictionary(Dictionary::new_with_sig(map, ty.to_string()))
+                } else {
+                    let mut objects = self._values();
+                    while self.offset < end {
+                        objects.push(self.read(elem)?);
+                    }
+
+                    Value::Array(Array::new_with_sig(objects, ty.to_string()))
+                }
+            },
+            Type::DictEntry(_, _) => return self.error("dictionary entry outside of an array"),
+            Type::Struct(ref fields) => {
+                self.align(8)?;
+                let mut objects = self._values();
+                for field in fields {
+                    objects.push(self.read(field)?);
+                }
+
+                Value::Struct(Struct {
+                    objects: objects,
+                    signature: Signature(ty.to_string()),
+                })
+            },
+        })
+    }
+}
+
+/// Demarshal the values described by a signature from the wire format.
+pub fn demarshal(sig: &str, bytes: &[u8], order: ByteOrder) -> Result<Vec<Value>> {
+    demarshal_with_max_depth(sig, bytes, order, MAX_DEPTH)
+}
+
+/// Demarshal the values described by a signature from the wire format with a limit on how deeply
+/// containers may be nested.
+pub fn demarshal_with_max_depth(sig: &str, bytes: &[u8], order: ByteOrder, max_depth: usize)
+                                -> Result<Vec<Value>> {
+    _demarshal(sig, Demarshaller::new(bytes, order).with_max_depth(max_depth))
+}
+
+/// Demarshal the values described by a signature from the wire format using buffers from a pool.
+///
+/// On success, the buffers may be returned to the pool using `ValuePool::recycle`.
+pub fn demarshal_pooled(sig: &str, bytes: &[u8], order: ByteOrder, max_depth: usize,
+                        pool: &ValuePool)
+                        -> Result<Vec<Value>> {
+    _demarshal(sig,
+               Demarshaller::new(bytes, order).with_max_depth(max_depth).with_pool(pool))
+}
+
+fn _demarshal(sig: &str, mut demarshaller: Demarshaller) -> Result<Vec<Value>> {
+    let types = signature::parse(sig)?;
+
+    let mut values = demarshaller._values();
+    for ty in &types {
+        values.push(demarshaller.read(ty)?);
+    }
+
+    if demarshaller.offset() != demarshaller.bytes.len() {
+        return demarshaller.error("trailing data");
+    }
+
+    Ok(values)
+}
+
+#[test]
+fn nesting_depth_limit() {
+    let nested_variants = |depth| {
+        let mut bytes = vec![];
+        for _ in 0..depth {
+            bytes.extend_from_slice(b"\x01v\x00");
+        }
+        bytes.extend_from_slice(b"\x01y\x00\x2a");
+        bytes
+    };
+
+    assert!(demarshal("v", &nested_variants(63), ByteOrder::LittleEndian).is_ok());
+
+    let err = demarshal("v", &nested_variants(64), ByteOrder::LittleEndian).unwrap_err();
+    assert_eq!(format!("{}", err),
+               "invalid wire data: values are nested too deeply at offset 192");
+
+    assert!(demarshal_with_max_depth("v", &nested_variants(8), ByteOrder::LittleEndian, 8)
+        .is_err());
+}
+
+#[test]
+fn demarshal_with_pool() {
+    let mut bytes = vec![];
+    let elements = vec![Value::BasicValue(BasicValue::Uint32(1))];
+    let values = vec![Value::BasicValue(BasicValue::String("pooled".to_string())),
+                      Value::Array(Array::new_with_sig(elements, "au".to_string()))];
+    for value in &values {
+        marshal(value, ByteOrder::LittleEndian, &mut bytes).unwrap();
+    }
+
+    let pool = ValuePool::new();
+    let pooled = demarshal_pooled("sau", &bytes, ByteOrder::LittleEndian, MAX_DEPTH, &pool)
+        .unwrap();
+    assert_eq!(pooled, demarshal("sau", &bytes, ByteOrder::LittleEndian).unwrap());
+
+    // Buffers from a previous message are reused.
+    pool.recycle(pooled);
+    let pooled = demarshal_pooled("sau", &bytes, ByteOrder::LittleEndian, MAX_DEPTH, &pool)
+        .unwrap();
+    assert_eq!(pooled, values);
+    assert_eq!(pool.take_string().capacity(), 0);
+}